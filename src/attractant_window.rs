@@ -0,0 +1,64 @@
+use imgui::{im_str, Condition, ImString, Slider, Ui, Window};
+use std::path::PathBuf;
+
+/// What the user asked to do with the attractant image.
+pub enum AttractantChange {
+    Load(PathBuf),
+    Clear,
+}
+
+/// Lets the user load an image for the agents to follow, and choose how strongly they do.
+pub struct AttractantWindow {
+    path: ImString,
+    status: String,
+}
+
+impl Default for AttractantWindow {
+    fn default() -> Self {
+        AttractantWindow::new()
+    }
+}
+
+impl AttractantWindow {
+    pub fn new() -> AttractantWindow {
+        let mut path = ImString::with_capacity(256);
+        path.push_str("attractant.png");
+
+        AttractantWindow {
+            path,
+            status: String::new(),
+        }
+    }
+
+    /// Shows the result of the last change.
+    pub fn set_status(&mut self, status: String) {
+        self.status = status;
+    }
+
+    /// The change is returned instead of applied, since the simulation can only take a new
+    /// attractant while the gpu is idle.
+    pub fn draw(&mut self, ui: &Ui, weight: &mut f32) -> Option<AttractantChange> {
+        let window = self;
+        let mut change = None;
+
+        Window::new(im_str!("Attractant"))
+            .position([790.0, 500.0], Condition::FirstUseEver)
+            .size([300.0, 130.0], Condition::FirstUseEver)
+            .build(ui, || {
+                ui.input_text(im_str!("Image"), &mut window.path).build();
+                if ui.button(im_str!("Load"), [0.0, 0.0]) {
+                    change = Some(AttractantChange::Load(PathBuf::from(window.path.to_str())));
+                }
+                ui.same_line(0.0);
+                if ui.button(im_str!("Clear"), [0.0, 0.0]) {
+                    change = Some(AttractantChange::Clear);
+                }
+                Slider::new(im_str!("Weight"))
+                    .range(0.0..=5.0)
+                    .build(ui, weight);
+                ui.text(&window.status);
+            });
+
+        change
+    }
+}
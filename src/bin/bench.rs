@@ -0,0 +1,305 @@
+// Measures the simulation throughput over a matrix of configurations, without a window.
+//
+// Usage: bench [--software | --device <device>] [--trail-format <format>] [--json] [--steps <amount>]
+//              [--output <file>]
+//
+// `--software` requires a cpu vulkan implementation (like lavapipe), so that regressions
+// can be tracked on machines without a gpu.
+//
+// The `*_gpu_ms` columns time each pass with gpu timestamps, see `profiler::GpuTimer`.
+// They are left empty on devices that can not write timestamps.
+//
+// The `estimated_copy_gb_per_second` column is calculated, not measured: the bandwidth that
+// copying the trail image every step would take at the measured steps per second.
+
+use serde::Serialize;
+use slime_simulation::device::{DeviceSelector, TrailFormat};
+use slime_simulation::headless;
+use slime_simulation::model::Model;
+use slime_simulation::parameters::ParameterSet;
+use slime_simulation::profiler::PassTimings;
+use slime_simulation::{SimError, Simulation, SimulationConfig};
+use std::fs;
+use std::sync::Arc;
+use std::time::Instant;
+use vulkano::device::{Device, Queue};
+use vulkano::instance::PhysicalDeviceType;
+
+const AGENT_AMOUNTS: [u32; 3] = [100_000, 500_000, 1_000_000];
+const RESOLUTIONS: [(u32, u32); 3] = [(1000, 700), (2000, 1400), (4000, 2800)];
+const SENSOR_RADII: [i32; 3] = [0, 1, 2];
+const BLUR_RADII: [i32; 2] = [1, 2];
+
+/// Steps that are run before measuring, so the agents have spread out a bit.
+const WARMUP_STEPS: u32 = 30;
+/// Steps that are timed pass by pass.
+const PROFILED_STEPS: u32 = 10;
+
+struct BenchOptions {
+    device: DeviceSelector,
+    trail_format: TrailFormat,
+    json: bool,
+    steps: u32,
+    output: Option<String>,
+}
+
+const USAGE: &str = "Usage: bench [--software | --device <device>] [--trail-format <format>] [--json] [--steps <amount>] [--output <file>]";
+
+impl BenchOptions {
+    /// Parsed by hand, since clap is only built along with the window.
+    /// Invalid arguments print what is wrong and exit.
+    fn from_args() -> BenchOptions {
+        let mut options = BenchOptions {
+            device: DeviceSelector::Auto,
+            trail_format: TrailFormat::default(),
+            json: false,
+            steps: 200,
+            output: None,
+        };
+
+        let mut args = std::env::args().skip(1);
+        while let Some(arg) = args.next() {
+            let mut value = |expected: &str| {
+                args.next()
+                    .unwrap_or_else(|| exit_with_usage(&format!("{} needs {}", arg, expected)))
+            };
+            match arg.as_str() {
+                "--software" => options.device = DeviceSelector::Type(PhysicalDeviceType::Cpu),
+                "--device" => {
+                    // Any text selects a device, by index, type or name.
+                    options.device = value("an index, name or type").parse().unwrap_or_default()
+                }
+                "--trail-format" => {
+                    options.trail_format = value("rgba8, rgba16f or rgba32f")
+                        .parse()
+                        .unwrap_or_else(|e: String| exit_with_usage(&e))
+                }
+                "--json" => options.json = true,
+                "--steps" => {
+                    let text = value("a positive number");
+                    options.steps = match text.parse() {
+                        Ok(steps) if steps > 0 => steps,
+                        _ => exit_with_usage(&format!(
+                            "'{}' is not a positive number of steps",
+                            text
+                        )),
+                    }
+                }
+                "--output" => options.output = Some(value("a path")),
+                _ => exit_with_usage(&format!("Unknown argument: {}", arg)),
+            }
+        }
+
+        options
+    }
+}
+
+fn exit_with_usage(message: &str) -> ! {
+    eprintln!("{}", message);
+    eprintln!("{}", USAGE);
+    std::process::exit(1);
+}
+
+struct BenchResult {
+    config: SimulationConfig,
+    sensor_radius: i32,
+    blur_radius: i32,
+    steps_per_second: f32,
+    /// The average of the profiled steps, if the device can time them.
+    timings: Option<PassTimings>,
+}
+
+fn run_config(
+    device: &Arc<Device>,
+    queue: &Arc<Queue>,
+    config: SimulationConfig,
+    sensor_radius: i32,
+    blur_radius: i32,
+    steps: u32,
+) -> Result<BenchResult, SimError> {
+    let mut sim = Simulation::init(device.clone(), queue.clone(), &config)?;
+
+    let mut parameters = ParameterSet::default();
+    parameters.sim.sensor_radius = sensor_radius;
+    parameters.fade.blur_radius = blur_radius;
+
+    sim.run_steps(WARMUP_STEPS, &parameters)?;
+
+    let start = Instant::now();
+    sim.run_steps(steps, &parameters)?;
+    let steps_per_second = steps as f32 / start.elapsed().as_secs_f32();
+
+    let mut timings = Some(PassTimings::default());
+    for _ in 0..PROFILED_STEPS {
+        let step = sim.step_profiled(&parameters)?;
+        timings = timings.zip(step).map(|(mut timings, step)| {
+            timings.clear_ms += step.clear_ms / PROFILED_STEPS as f32;
+            timings.agent_ms += step.agent_ms / PROFILED_STEPS as f32;
+            timings.blur_ms += step.blur_ms / PROFILED_STEPS as f32;
+            timings
+        });
+    }
+
+    Ok(BenchResult {
+        config,
+        sensor_radius,
+        blur_radius,
+        steps_per_second,
+        timings,
+    })
+}
+
+fn main() {
+    let options = BenchOptions::from_args();
+    let (device, queue) = headless::init_device(&options.device).unwrap_or_else(|e| {
+        eprintln!("Error: {}", e);
+        if let Some(hint) = e.hint() {
+            eprintln!("{}", hint);
+        }
+        std::process::exit(1);
+    });
+    // The table goes to stdout, everything else to stderr.
+    let physical = device.physical_device();
+    eprintln!(
+        "Using device: {} (type: {:?})",
+        physical.name(),
+        physical.ty()
+    );
+
+    let mut results = Vec::new();
+
+    for &agent_amount in AGENT_AMOUNTS.iter() {
+        for &(width, height) in RESOLUTIONS.iter() {
+            let config = SimulationConfig {
+                width,
+                height,
+                agent_amount,
+                // Same starting positions for every run, so results are comparable.
+                seed: 0,
+                trail_format: options.trail_format,
+            };
+
+            for &sensor_radius in SENSOR_RADII.iter() {
+                for &blur_radius in BLUR_RADII.iter() {
+                    let result = run_config(
+                        &device,
+                        &queue,
+                        config,
+                        sensor_radius,
+                        blur_radius,
+                        options.steps,
+                    );
+
+                    match result {
+                        Ok(result) => {
+                            eprintln!(
+                                "agents: {}, resolution: {}x{}, sensor radius: {}, blur radius: {} -> {:.1} steps/s",
+                                agent_amount, width, height, sensor_radius, blur_radius, result.steps_per_second
+                            );
+                            results.push(result);
+                        }
+                        // Large configurations might not fit on every device,
+                        // the others are still worth measuring.
+                        Err(e) => eprintln!(
+                            "agents: {}, resolution: {}x{}, sensor radius: {}, blur radius: {} -> skipped: {}",
+                            agent_amount, width, height, sensor_radius, blur_radius, e
+                        ),
+                    }
+                }
+            }
+        }
+    }
+
+    let table = if options.json {
+        format_json(&results)
+    } else {
+        format_csv(&results)
+    };
+
+    match options.output {
+        Some(path) => {
+            if let Err(e) = fs::write(&path, table) {
+                eprintln!("Failed to write '{}': {}", path, e);
+                std::process::exit(1);
+            }
+        }
+        None => print!("{}", table),
+    }
+}
+
+/// The memory bandwidth, in GB/s, that copying the whole trail image every step would take
+/// at the measured speed. The trail images take turns instead, so this is what it saves.
+/// Only the speed is measured, the copy itself is never run.
+fn estimated_copy_bandwidth(result: &BenchResult) -> f32 {
+    // Reading and writing every pixel.
+    let bytes_per_pixel = result.config.trail_format.bytes_per_pixel() as f32 * 2.0;
+    let bytes_per_step = result.config.width as f32 * result.config.height as f32 * bytes_per_pixel;
+    bytes_per_step * result.steps_per_second / 1e9
+}
+
+/// A pass time, or nothing when the passes could not be timed.
+fn format_ms(ms: Option<f32>) -> String {
+    ms.map_or_else(String::new, |ms| format!("{:.4}", ms))
+}
+
+fn format_csv(results: &[BenchResult]) -> String {
+    let mut csv = String::from(
+        "trail_format,agents,width,height,sensor_radius,blur_radius,steps_per_second,clear_gpu_ms,agent_gpu_ms,blur_gpu_ms,estimated_copy_gb_per_second\n",
+    );
+    for result in results {
+        csv.push_str(&format!(
+            "{},{},{},{},{},{},{:.3},{},{},{},{:.3}\n",
+            result.config.trail_format.name(),
+            result.config.agent_amount,
+            result.config.width,
+            result.config.height,
+            result.sensor_radius,
+            result.blur_radius,
+            result.steps_per_second,
+            format_ms(result.timings.map(|t| t.clear_ms)),
+            format_ms(result.timings.map(|t| t.agent_ms)),
+            format_ms(result.timings.map(|t| t.blur_ms)),
+            estimated_copy_bandwidth(result),
+        ));
+    }
+    csv
+}
+
+/// One configuration in the json report. Pass times are `null` when they could not be timed.
+#[derive(Serialize)]
+struct JsonRow {
+    trail_format: TrailFormat,
+    agents: u32,
+    width: u32,
+    height: u32,
+    sensor_radius: i32,
+    blur_radius: i32,
+    steps_per_second: f32,
+    clear_gpu_ms: Option<f32>,
+    agent_gpu_ms: Option<f32>,
+    blur_gpu_ms: Option<f32>,
+    estimated_copy_gb_per_second: f32,
+}
+
+fn format_json(results: &[BenchResult]) -> String {
+    let rows: Vec<JsonRow> = results
+        .iter()
+        .map(|result| JsonRow {
+            trail_format: result.config.trail_format,
+            agents: result.config.agent_amount,
+            width: result.config.width,
+            height: result.config.height,
+            sensor_radius: result.sensor_radius,
+            blur_radius: result.blur_radius,
+            steps_per_second: result.steps_per_second,
+            clear_gpu_ms: result.timings.map(|t| t.clear_ms),
+            agent_gpu_ms: result.timings.map(|t| t.agent_ms),
+            blur_gpu_ms: result.timings.map(|t| t.blur_ms),
+            estimated_copy_gb_per_second: estimated_copy_bandwidth(result),
+        })
+        .collect();
+
+    let mut json = serde_json::to_string_pretty(&rows).unwrap();
+    json.push('\n');
+    json
+}
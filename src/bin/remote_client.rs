@@ -0,0 +1,63 @@
+// Sends remote control requests to a running simulation over loopback, and prints the answers.
+//
+// Usage: remote_client [--address <address>] [<request>...]
+//
+// Every request is a line of json, for example:
+//   remote_client '{"command": "set", "parameter": "agent_speed", "value": 150.0}'
+//   remote_client '{"command": "get_all"}' '{"command": "stats"}'
+// When no requests are given, they are read from stdin, one per line.
+
+use std::io::{stdin, BufRead, BufReader, Write};
+use std::net::TcpStream;
+use std::process;
+
+const DEFAULT_ADDRESS: &str = "127.0.0.1:7878";
+
+fn main() {
+    let mut address = DEFAULT_ADDRESS.to_owned();
+    let mut requests = Vec::new();
+
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        if arg == "--address" {
+            address = args.next().unwrap_or_else(|| {
+                eprintln!("--address needs a value");
+                process::exit(1);
+            });
+        } else {
+            requests.push(arg);
+        }
+    }
+
+    let stream = TcpStream::connect(&address).unwrap_or_else(|e| {
+        eprintln!("Failed to connect to '{}': {}", address, e);
+        eprintln!("Is the simulation running with --remote {}?", address);
+        process::exit(1);
+    });
+    let mut writer = stream.try_clone().expect("Failed to clone connection");
+    let mut responses = BufReader::new(stream).lines();
+
+    let mut send = |request: &str| {
+        writeln!(writer, "{}", request).expect("Failed to send request");
+        match responses.next() {
+            Some(Ok(response)) => println!("{}", response),
+            _ => {
+                eprintln!("Connection closed by the simulation");
+                process::exit(1);
+            }
+        }
+    };
+
+    if requests.is_empty() {
+        for line in stdin().lock().lines() {
+            let line = line.expect("Failed to read stdin");
+            if !line.trim().is_empty() {
+                send(&line);
+            }
+        }
+    } else {
+        for request in &requests {
+            send(request);
+        }
+    }
+}
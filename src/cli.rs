@@ -0,0 +1,475 @@
+use clap::{crate_version, App, Arg, ArgMatches, ErrorKind};
+use slime_simulation::colony;
+use slime_simulation::colony::ColonySettings;
+use slime_simulation::device::DeviceSelector;
+use slime_simulation::parameters::{Kind, Parameter, PARAMETERS};
+use slime_simulation::post;
+use slime_simulation::post::PostSettings;
+use slime_simulation::preset::Preset;
+use slime_simulation::reaction;
+use slime_simulation::reaction::ReactionParameters;
+use slime_simulation::system::WindowConfig;
+use slime_simulation::timeline::Timeline;
+use slime_simulation::volume;
+use std::ffi::OsString;
+use std::path::PathBuf;
+use std::str::FromStr;
+
+/// Everything the command line decides about how to run the simulation.
+pub struct Options {
+    /// Preset file values, overridden by anything given on the command line.
+    pub preset: Preset,
+    pub window: WindowConfig,
+    pub device: DeviceSelector,
+    /// Print the available devices and exit.
+    pub list_devices: bool,
+    /// Run this many steps without a window, instead of opening one.
+    pub headless_steps: Option<u32>,
+    /// Simulate in a 3D volume of this size, instead of in 2D.
+    pub volume_size: Option<u32>,
+    /// Where the final image of a headless run is saved.
+    pub output: PathBuf,
+    /// Where to write the resolved preset, if anywhere.
+    pub save_preset: Option<PathBuf>,
+    pub timeline: Option<Timeline>,
+    /// Save an image every this many steps of a headless run, instead of only at the end.
+    pub frame_interval: Option<u32>,
+    /// Where to write the statistics of every step of a headless run, if anywhere.
+    pub statistics_csv: Option<PathBuf>,
+    /// Address to accept remote control connections on, if any.
+    pub remote_address: Option<String>,
+    /// Where remote control snapshots are saved, if not in the current directory.
+    pub snapshot_dir: Option<PathBuf>,
+    /// Image the agents are drawn to, alongside their trails.
+    pub attractant: Option<PathBuf>,
+    /// Run reaction-diffusion with these parameters, instead of the slime.
+    pub reaction: Option<ReactionParameters>,
+}
+
+pub fn parse() -> Options {
+    parse_from(std::env::args_os()).unwrap_or_else(|e| e.exit())
+}
+
+/// Returns the errors clap finds itself, the other invalid values exit right away.
+fn parse_from<I, T>(args: I) -> clap::Result<Options>
+where
+    I: IntoIterator<Item = T>,
+    T: Into<OsString> + Clone,
+{
+    let defaults = Preset::default();
+    let default_window = WindowConfig::default();
+
+    let default_resolution = format!("{}x{}", defaults.width, defaults.height);
+    let agents_help = format!(
+        "How many agents to simulate. [default: {}]",
+        defaults.agent_amount
+    );
+    let trail_format_help = format!(
+        "Precision of the trails. With rgba8, slow fading rounds away or shows bands. [default: {}]",
+        defaults.trail_format.name()
+    );
+    let default_window_size = format!("{}x{}", default_window.width, default_window.height);
+    let default_steps_per_frame = default_window.steps_per_frame.to_string();
+
+    let reaction_presets: Vec<&str> = reaction::PRESETS.iter().map(|p| p.name).collect();
+    // The reaction-diffusion has no agents, so none of the slime options apply to it.
+    let reaction_conflicts: Vec<&str> = ["volume", "agents", "trail-format"]
+        .iter()
+        .copied()
+        .chain(PARAMETERS.iter().map(|parameter| parameter.flag))
+        .collect();
+
+    // One option per parameter in each registry.
+    let parameter_help: Vec<String> = PARAMETERS.iter().map(parameter_help).collect();
+    let reaction_help: Vec<String> = reaction::PARAMETERS.iter().map(parameter_help).collect();
+    let post_help: Vec<String> = post::PARAMETERS.iter().map(parameter_help).collect();
+    let colony_help: Vec<String> = colony::PARAMETERS.iter().map(parameter_help).collect();
+    let reaction_args: Vec<Arg> = parameter_args(&reaction::PARAMETERS, &reaction_help)
+        .into_iter()
+        .map(|arg| arg.requires("reaction"))
+        .collect();
+    // The window applies the post-processing, a headless run saves the trails as they are.
+    let post_args: Vec<Arg> = parameter_args(&post::PARAMETERS, &post_help)
+        .into_iter()
+        .map(|arg| arg.conflicts_with("headless"))
+        .collect();
+    // Ants only live in the window of the 2D slime.
+    let colony_args: Vec<Arg> = parameter_args(&colony::PARAMETERS, &colony_help)
+        .into_iter()
+        .map(|arg| arg.conflicts_with_all(&["reaction", "volume", "headless"]))
+        .collect();
+
+    let matches = App::new("Slime Simulation")
+        .version(crate_version!())
+        .about("Simulates slime mold agents leaving and following trails.")
+        .arg(
+            Arg::with_name("preset")
+                .long("preset")
+                .value_name("FILE")
+                .help("Json preset file to start from. Other options override its values."),
+        )
+        .arg(
+            Arg::with_name("save-preset")
+                .long("save-preset")
+                .value_name("FILE")
+                .help("Writes the resulting parameters to a json preset file."),
+        )
+        .arg(
+            Arg::with_name("resolution")
+                .long("resolution")
+                .value_name("WIDTHxHEIGHT")
+                .default_value(&default_resolution)
+                .help("Size of the simulated area, in pixels."),
+        )
+        .arg(
+            Arg::with_name("agents")
+                .long("agents")
+                .value_name("AMOUNT")
+                .help(&agents_help),
+        )
+        .arg(
+            Arg::with_name("trail-format")
+                .long("trail-format")
+                .value_name("FORMAT")
+                .possible_values(&["rgba8", "rgba16f", "rgba32f"])
+                .help(&trail_format_help),
+        )
+        .arg(
+            Arg::with_name("seed")
+                .long("seed")
+                .value_name("SEED")
+                .help("Seed for the starting positions of the agents. Random when not given."),
+        )
+        .args(&parameter_args(&PARAMETERS, &parameter_help))
+        .args(&colony_args)
+        .arg(
+            Arg::with_name("attractant")
+                .long("attractant")
+                .value_name("FILE")
+                .conflicts_with_all(&["reaction", "volume"])
+                .help("Image the agents are drawn to, scaled to fit the resolution. Bright parts attract. Only in 2D."),
+        )
+        .arg(
+            Arg::with_name("reaction")
+                .long("reaction")
+                .value_name("PRESET")
+                .min_values(0)
+                .max_values(1)
+                .possible_values(&reaction_presets)
+                .case_insensitive(true)
+                .conflicts_with_all(&reaction_conflicts)
+                .help("Runs Gray-Scott reaction-diffusion instead of the slime, starting from a preset pattern. Not with the agent options."),
+        )
+        .args(&reaction_args)
+        .args(&post_args)
+        .arg(
+            Arg::with_name("window-size")
+                .long("window-size")
+                .value_name("WIDTHxHEIGHT")
+                .default_value(&default_window_size)
+                .help("Size of the window, in pixels."),
+        )
+        .arg(
+            Arg::with_name("fullscreen")
+                .long("fullscreen")
+                .help("Opens the window fullscreen."),
+        )
+        .arg(
+            Arg::with_name("resize-with-window")
+                .long("resize-with-window")
+                .help("Resizes the simulation along with the window. This starts it over."),
+        )
+        .arg(
+            Arg::with_name("steps-per-frame")
+                .long("steps-per-frame")
+                .value_name("STEPS")
+                .default_value(&default_steps_per_frame)
+                .help("Simulation steps per displayed frame."),
+        )
+        .arg(
+            Arg::with_name("device")
+                .long("device")
+                .value_name("DEVICE")
+                .help(
+                    "Vulkan device to use: an index from --list-devices, part of its name, \
+                     or a type (discrete, integrated, virtual, cpu). \
+                     Prefers a discrete gpu when not given.",
+                ),
+        )
+        .arg(
+            Arg::with_name("list-devices")
+                .long("list-devices")
+                .help("Lists the available vulkan devices and exits."),
+        )
+        .arg(
+            Arg::with_name("headless")
+                .long("headless")
+                .value_name("STEPS")
+                .help("Runs this many steps without a window, and saves the result to --output."),
+        )
+        .arg(
+            Arg::with_name("volume")
+                .long("volume")
+                .value_name("SIZE")
+                .help("Simulates in a 3D volume of SIZE voxels along each side, instead of in 2D. At most 1024."),
+        )
+        .arg(
+            Arg::with_name("timeline")
+                .long("timeline")
+                .value_name("FILE")
+                .conflicts_with("reaction")
+                .help("Json timeline file that animates the parameters. Not with --reaction."),
+        )
+        .arg(
+            Arg::with_name("frame-interval")
+                .long("frame-interval")
+                .value_name("STEPS")
+                .help("Saves a numbered image every this many steps of a headless run."),
+        )
+        .arg(
+            Arg::with_name("stats-csv")
+                .long("stats-csv")
+                .value_name("FILE")
+                .conflicts_with_all(&["reaction", "volume"])
+                .help("Writes statistics of every step of a headless run to a csv file. Only in 2D."),
+        )
+        .arg(
+            Arg::with_name("remote")
+                .long("remote")
+                .value_name("ADDRESS")
+                .conflicts_with_all(&["reaction", "volume"])
+                .help("Accepts json-over-tcp remote control connections, for example on 127.0.0.1:7878. Only in 2D."),
+        )
+        .arg(
+            Arg::with_name("snapshot-dir")
+                .long("snapshot-dir")
+                .value_name("DIR")
+                .requires("remote")
+                .help("Directory remote control snapshots are saved in, instead of the current one. Created if it does not exist."),
+        )
+        .arg(
+            Arg::with_name("output")
+                .long("output")
+                .value_name("FILE")
+                .default_value("output.png")
+                .help("Where to save the image of a headless run."),
+        )
+        .get_matches_from_safe(args)?;
+
+    let mut preset = match matches.value_of("preset") {
+        Some(path) => Preset::load(path).unwrap_or_else(|e| {
+            invalid_value("preset", &format!("failed to load '{}': {}", path, e))
+        }),
+        None => defaults,
+    };
+
+    if let Some((width, height)) = given(&matches, "resolution", parse_size) {
+        preset.width = width;
+        preset.height = height;
+    }
+    if let Some(agent_amount) = given(&matches, "agents", parse_positive) {
+        preset.agent_amount = agent_amount;
+    }
+    // A preset file could still ask for nothing to simulate.
+    if preset.width == 0 || preset.height == 0 || preset.agent_amount == 0 {
+        invalid_value(
+            "preset",
+            "the resolution and the amount of agents must be at least 1",
+        );
+    }
+    if let Some(trail_format) = given(&matches, "trail-format", parse_value) {
+        preset.trail_format = trail_format;
+    }
+    if let Some(seed) = given(&matches, "seed", parse_value) {
+        preset.seed = Some(seed);
+    }
+    apply_given(&matches, &PARAMETERS, &mut preset.parameters);
+    // Only added to the preset when asked for, so saved presets stay as short as before.
+    if any_given(&matches, &post::PARAMETERS) {
+        let post_settings = preset.post.get_or_insert_with(PostSettings::default);
+        apply_given(&matches, &post::PARAMETERS, post_settings);
+    }
+    if any_given(&matches, &colony::PARAMETERS) {
+        let colony_settings = preset.colony.get_or_insert_with(ColonySettings::default);
+        apply_given(&matches, &colony::PARAMETERS, colony_settings);
+    }
+
+    let timeline = matches.value_of("timeline").map(|path| {
+        Timeline::load(path).unwrap_or_else(|e| {
+            invalid_value("timeline", &format!("failed to load '{}': {}", path, e))
+        })
+    });
+
+    let reaction = if matches.is_present("reaction") {
+        let mut parameters = ReactionParameters::default();
+        if let Some(preset) = matches.value_of("reaction").and_then(reaction::find_preset) {
+            parameters.feed = preset.feed;
+            parameters.kill = preset.kill;
+        }
+        apply_given(&matches, &reaction::PARAMETERS, &mut parameters);
+        Some(parameters)
+    } else {
+        None
+    };
+
+    // Pick the seed now, so that a saved preset reproduces this exact run.
+    preset.seed.get_or_insert_with(rand::random);
+
+    let (window_width, window_height) =
+        parse_size("window-size", matches.value_of("window-size").unwrap());
+
+    Ok(Options {
+        preset,
+        window: WindowConfig {
+            width: window_width,
+            height: window_height,
+            fullscreen: matches.is_present("fullscreen"),
+            resize_with_window: matches.is_present("resize-with-window"),
+            steps_per_frame: parse_value(
+                "steps-per-frame",
+                matches.value_of("steps-per-frame").unwrap(),
+            ),
+        },
+        device: given(&matches, "device", parse_value).unwrap_or_default(),
+        list_devices: matches.is_present("list-devices"),
+        headless_steps: given(&matches, "headless", parse_value),
+        volume_size: given(&matches, "volume", parse_volume_size),
+        output: PathBuf::from(matches.value_of("output").unwrap()),
+        save_preset: matches.value_of("save-preset").map(PathBuf::from),
+        timeline,
+        frame_interval: given(&matches, "frame-interval", parse_positive),
+        statistics_csv: matches.value_of("stats-csv").map(PathBuf::from),
+        remote_address: matches.value_of("remote").map(str::to_owned),
+        snapshot_dir: matches.value_of("snapshot-dir").map(PathBuf::from),
+        attractant: matches.value_of("attractant").map(PathBuf::from),
+        reaction,
+    })
+}
+
+/// Only returns the value when it was actually given on the command line,
+/// so that clap's displayed defaults don't override the preset file.
+fn given<T>(matches: &ArgMatches, name: &str, parse: fn(&str, &str) -> T) -> Option<T> {
+    if matches.occurrences_of(name) == 0 {
+        return None;
+    }
+    matches.value_of(name).map(|value| parse(name, value))
+}
+
+fn parse_value<T: FromStr>(name: &str, value: &str) -> T {
+    value
+        .parse()
+        .unwrap_or_else(|_| invalid_value(name, &format!("'{}'", value)))
+}
+
+/// Parses counts and intervals, which make no sense at zero.
+fn parse_positive(name: &str, value: &str) -> u32 {
+    match parse_value(name, value) {
+        0 => invalid_value(name, "'0', expected at least 1"),
+        count => count,
+    }
+}
+
+fn parse_volume_size(name: &str, value: &str) -> u32 {
+    match parse_positive(name, value) {
+        size if size > volume::MAX_SIZE => invalid_value(
+            name,
+            &format!("'{}', expected at most {}", size, volume::MAX_SIZE),
+        ),
+        size => size,
+    }
+}
+
+/// Exits with a clap error, like the ones for values clap checks itself.
+fn invalid_value(name: &str, message: &str) -> ! {
+    clap::Error::with_description(
+        &format!("Invalid value for '--{}': {}", name, message),
+        ErrorKind::InvalidValue,
+    )
+    .exit()
+}
+
+/// One option per parameter of a registry. Their defaults are only mentioned in the help,
+/// so that clap does not count them as given when checking which options go together.
+fn parameter_args<'a, T>(table: &'static [Parameter<T>], help: &'a [String]) -> Vec<Arg<'a, 'a>> {
+    table
+        .iter()
+        .zip(help)
+        .map(|(parameter, help)| {
+            Arg::with_name(parameter.flag)
+                .long(parameter.flag)
+                .value_name(match parameter.kind {
+                    Kind::Color => "R,G,B",
+                    Kind::Bool => "true|false",
+                    _ => "VALUE",
+                })
+                .help(help)
+        })
+        .collect()
+}
+
+/// The tooltip of the parameter, with its range and default.
+fn parameter_help<T>(parameter: &Parameter<T>) -> String {
+    let (min, max) = parameter.range;
+    let range = format!("{} - {}", min, max);
+    let help = match parameter.kind {
+        Kind::Bool => parameter.tooltip.to_owned(),
+        _ if parameter.units.is_empty() => {
+            format!("{} Clamped to [{}].", parameter.tooltip, range)
+        }
+        _ => format!(
+            "{} Clamped to [{}], in {}.",
+            parameter.tooltip, range, parameter.units
+        ),
+    };
+    format!("{} [default: {}]", help, parameter.default)
+}
+
+fn any_given<T>(matches: &ArgMatches, table: &[Parameter<T>]) -> bool {
+    table
+        .iter()
+        .any(|parameter| matches.occurrences_of(parameter.flag) > 0)
+}
+
+/// Sets the parameters of the registry that were given on the command line, clamped to
+/// their ranges.
+fn apply_given<T>(matches: &ArgMatches, table: &[Parameter<T>], target: &mut T) {
+    for parameter in table {
+        if let Some(text) = matches.value_of(parameter.flag) {
+            let value = parameter
+                .parse(text)
+                .unwrap_or_else(|e| invalid_value(parameter.flag, &e));
+            parameter.set_value(target, value);
+        }
+    }
+}
+
+/// Parses sizes in the form `WIDTHxHEIGHT`.
+fn parse_size(name: &str, value: &str) -> (u32, u32) {
+    let mut parts = value.splitn(2, 'x');
+    match (parts.next(), parts.next()) {
+        (Some(width), Some(height)) => (parse_positive(name, width), parse_positive(name, height)),
+        _ => invalid_value(name, &format!("'{}', expected WIDTHxHEIGHT", value)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn agent_options_are_rejected_with_reaction() {
+        let parse = |args: &[&str]| parse_from(["slime_simulation"].iter().chain(args));
+
+        let error = parse(&["--reaction", "--agents", "10"]).err().unwrap();
+        assert_eq!(error.kind, ErrorKind::ArgumentConflict);
+        assert!(parse(&["--reaction", "--trail-format", "rgba8"]).is_err());
+        // Every generated slime parameter.
+        for parameter in PARAMETERS.iter() {
+            let flag = format!("--{}", parameter.flag);
+            assert!(parse(&["--reaction", flag.as_str(), "1"]).is_err());
+        }
+
+        assert!(parse(&["--reaction", "--feed", "0.03"]).is_ok());
+        assert!(parse(&["--agents", "10"]).is_ok());
+    }
+}
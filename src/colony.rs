@@ -0,0 +1,270 @@
+use crate::parameters;
+use crate::parameters::{Kind, Parameter, Value};
+use crate::simulation::SimulationConfig;
+#[cfg(feature = "ui")]
+use imgui::{im_str, Condition, Ui, Window};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+/// Has to match MAX_FOOD_PILES in the `agent_shader`.
+pub const MAX_FOOD_PILES: usize = 16;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct FoodPile {
+    pub centre: [f32; 2],
+    pub radius: f32,
+    /// How much food is left. Each ant takes one.
+    pub amount: u32,
+}
+
+/// The sizes of the nest and of the food piles that are placed.
+/// Its json is made up of `PARAMETERS`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ColonySettings {
+    pub nest_radius: f32,
+    /// Radius of newly placed food piles.
+    pub pile_radius: f32,
+    /// How much food newly placed piles hold.
+    pub pile_amount: i32,
+}
+
+/// The colony parameters, in the order they are shown in.
+pub static PARAMETERS: [Parameter<ColonySettings>; 3] = [
+    Parameter {
+        key: "nest_radius",
+        flag: "nest-radius",
+        label: "Nest radius",
+        units: "px",
+        tooltip: "How close to the nest ants have to come to drop their food.",
+        group: "Nest and food",
+        kind: Kind::Float,
+        range: (5.0, 200.0),
+        default: Value::Float(20.0),
+        get: |colony| Value::Float(colony.nest_radius),
+        set: |colony, value| colony.nest_radius = value.as_float(),
+    },
+    Parameter {
+        key: "pile_radius",
+        flag: "pile-radius",
+        label: "Pile radius",
+        units: "px",
+        tooltip: "Radius of the food piles that are placed.",
+        group: "Nest and food",
+        kind: Kind::Float,
+        range: (5.0, 200.0),
+        default: Value::Float(30.0),
+        get: |colony| Value::Float(colony.pile_radius),
+        set: |colony, value| colony.pile_radius = value.as_float(),
+    },
+    Parameter {
+        key: "pile_amount",
+        flag: "pile-amount",
+        label: "Pile amount",
+        units: "",
+        tooltip: "How much food the piles that are placed hold. Each ant takes one.",
+        group: "Nest and food",
+        kind: Kind::Int,
+        range: (1.0, 100_000.0),
+        default: Value::Int(2000),
+        get: |colony| Value::Int(colony.pile_amount),
+        set: |colony, value| colony.pile_amount = value.as_int(),
+    },
+];
+
+impl Default for ColonySettings {
+    /// Every parameter at its `default`.
+    fn default() -> Self {
+        // The values of the registry are filled in below.
+        let mut settings = ColonySettings {
+            nest_radius: 0.0,
+            pile_radius: 0.0,
+            pile_amount: 0,
+        };
+        parameters::set_defaults(&PARAMETERS, &mut settings);
+        settings
+    }
+}
+
+impl Serialize for ColonySettings {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        parameters::serialize(&PARAMETERS, self, serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for ColonySettings {
+    /// Missing fields get their default value, and values outside of their range are clamped.
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        parameters::deserialize(&PARAMETERS, deserializer)
+    }
+}
+
+/// The nest and the food of the ant foraging mode.
+///
+/// In this mode the agents are ants. Ants looking for food follow the food trails (green) and
+/// leave home trails (red) behind, ants carrying food follow the home trails and leave food trails.
+/// Ants pick up food when they reach a pile, and turn around to bring it back to the nest.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Colony {
+    /// Without it, the agents are slime mold again.
+    pub enabled: bool,
+    pub nest: [f32; 2],
+    pub settings: ColonySettings,
+    /// At most `MAX_FOOD_PILES`.
+    pub food: Vec<FoodPile>,
+}
+
+impl Colony {
+    /// A disabled colony with the nest in the centre and no food.
+    pub fn new(config: &SimulationConfig, settings: ColonySettings) -> Colony {
+        Colony {
+            enabled: false,
+            nest: [config.width as f32 / 2.0, config.height as f32 / 2.0],
+            settings,
+            food: Vec::new(),
+        }
+    }
+}
+
+/// What the ants have done since the simulation was reset.
+#[derive(Clone, Debug, Default)]
+pub struct ColonyStatus {
+    /// How much food has been brought to the nest.
+    pub collected: u32,
+    /// The food left in each pile of the `Colony`.
+    pub food_left: Vec<u32>,
+}
+
+/// What a click on the trails does.
+#[cfg(feature = "ui")]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Tool {
+    MoveNest,
+    PlaceFood,
+}
+
+/// What the user changed in the colony window.
+#[cfg(feature = "ui")]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ColonyChange {
+    /// The nest or the food changed.
+    Layout,
+    /// The ant mode was switched on or off, so the agents start over.
+    Restart,
+}
+
+/// Lets the user switch to the ant mode, place the nest and the food, and shows the food
+/// collected so far.
+#[cfg(feature = "ui")]
+pub struct ColonyWindow {
+    pub colony: Colony,
+    tool: Tool,
+    status: ColonyStatus,
+}
+
+#[cfg(feature = "ui")]
+impl ColonyWindow {
+    pub fn new(config: &SimulationConfig, settings: ColonySettings) -> ColonyWindow {
+        ColonyWindow {
+            colony: Colony::new(config, settings),
+            tool: Tool::PlaceFood,
+            status: ColonyStatus::default(),
+        }
+    }
+
+    /// Takes the food left from the gpu, so changing the layout does not refill the piles.
+    pub fn set_status(&mut self, status: ColonyStatus) {
+        for (pile, &left) in self.colony.food.iter_mut().zip(&status.food_left) {
+            pile.amount = left;
+        }
+        self.status = status;
+    }
+
+    /// Handles a click on the trails, at a position in simulation pixels.
+    pub fn click(&mut self, position: [f32; 2]) -> Option<ColonyChange> {
+        if !self.colony.enabled {
+            return None;
+        }
+
+        match self.tool {
+            Tool::MoveNest => self.colony.nest = position,
+            Tool::PlaceFood => {
+                if self.colony.food.len() >= MAX_FOOD_PILES {
+                    self.colony.food.remove(0);
+                }
+                self.colony.food.push(FoodPile {
+                    centre: position,
+                    radius: self.colony.settings.pile_radius,
+                    amount: self.colony.settings.pile_amount.max(0) as u32,
+                });
+            }
+        }
+        Some(ColonyChange::Layout)
+    }
+
+    /// The changes are returned instead of applied, since the simulation can only take them
+    /// while the gpu is idle.
+    pub fn draw(&mut self, ui: &Ui) -> Option<ColonyChange> {
+        let window = self;
+        let mut change = None;
+
+        Window::new(im_str!("Ants"))
+            .position([1160.0, 320.0], Condition::FirstUseEver)
+            .size([240.0, 250.0], Condition::FirstUseEver)
+            .build(ui, || {
+                if ui.checkbox(im_str!("Ant mode"), &mut window.colony.enabled) {
+                    change = Some(ColonyChange::Restart);
+                }
+                if !window.colony.enabled {
+                    return;
+                }
+
+                ui.text(format!("Food collected: {}", window.status.collected));
+                let left: u32 = window.status.food_left.iter().sum();
+                ui.text(format!("Food left: {}", left));
+
+                ui.separator();
+                ui.text(im_str!("Clicking on the trails"));
+                ui.radio_button(im_str!("moves the nest"), &mut window.tool, Tool::MoveNest);
+                ui.radio_button(im_str!("places food"), &mut window.tool, Tool::PlaceFood);
+                // Only the nest radius changes the layout, but uploading it again is cheap.
+                if parameters::draw_parameters(ui, &PARAMETERS, &mut window.colony.settings) {
+                    change = change.or(Some(ColonyChange::Layout));
+                }
+                if ui.button(im_str!("Remove food"), [0.0, 0.0]) {
+                    window.colony.food.clear();
+                    change = change.or(Some(ColonyChange::Layout));
+                }
+            });
+
+        if window.colony.enabled {
+            window.draw_overlay(ui);
+        }
+        change
+    }
+
+    /// Outlines the nest and the food piles on top of the trails.
+    fn draw_overlay(&self, ui: &Ui) {
+        // The trails are shown one simulation pixel per physical pixel, imgui uses logical ones.
+        let scale = 1.0 / ui.io().display_framebuffer_scale[0];
+        let draw_list = ui.get_background_draw_list();
+
+        let [x, y] = self.colony.nest;
+        draw_list
+            .add_circle(
+                [x * scale, y * scale],
+                self.colony.settings.nest_radius * scale,
+                [1.0, 0.3, 0.3, 1.0],
+            )
+            .build();
+        for pile in &self.colony.food {
+            let [x, y] = pile.centre;
+            let color = if pile.amount > 0 {
+                [0.3, 1.0, 0.3, 1.0]
+            } else {
+                [0.5, 0.5, 0.5, 1.0]
+            };
+            draw_list
+                .add_circle([x * scale, y * scale], pile.radius * scale, color)
+                .build();
+        }
+    }
+}
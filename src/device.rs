@@ -0,0 +1,262 @@
+use crate::error::SimError;
+use serde::{Deserialize, Serialize};
+use std::str::FromStr;
+use std::sync::Arc;
+use vulkano::device::DeviceExtensions;
+use vulkano::format::Format;
+use vulkano::instance::{
+    Instance, InstanceExtensions, PhysicalDevice, PhysicalDeviceType, Version,
+};
+
+/// The format of the image the trails are converted to for displaying and saving them.
+pub const DISPLAY_FORMAT: Format = Format::R8G8B8A8Unorm;
+
+/// The format the trails are simulated in.
+///
+/// With 8 bits per channel, small amounts of evaporation per step round to zero, so trails
+/// either never fade or fade in visible bands. The float formats fade smoothly.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TrailFormat {
+    Rgba8,
+    Rgba16f,
+    Rgba32f,
+}
+
+impl Default for TrailFormat {
+    fn default() -> Self {
+        TrailFormat::Rgba16f
+    }
+}
+
+impl FromStr for TrailFormat {
+    type Err = String;
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        match text.to_lowercase().as_str() {
+            "rgba8" => Ok(TrailFormat::Rgba8),
+            "rgba16f" => Ok(TrailFormat::Rgba16f),
+            "rgba32f" => Ok(TrailFormat::Rgba32f),
+            _ => Err(format!(
+                "unknown trail format '{}', expected rgba8, rgba16f or rgba32f",
+                text
+            )),
+        }
+    }
+}
+
+impl TrailFormat {
+    /// The name used on the command line and in preset files.
+    pub fn name(self) -> &'static str {
+        match self {
+            TrailFormat::Rgba8 => "rgba8",
+            TrailFormat::Rgba16f => "rgba16f",
+            TrailFormat::Rgba32f => "rgba32f",
+        }
+    }
+
+    pub fn format(self) -> Format {
+        match self {
+            TrailFormat::Rgba8 => Format::R8G8B8A8Unorm,
+            TrailFormat::Rgba16f => Format::R16G16B16A16Sfloat,
+            TrailFormat::Rgba32f => Format::R32G32B32A32Sfloat,
+        }
+    }
+
+    pub fn bytes_per_pixel(self) -> u32 {
+        match self {
+            TrailFormat::Rgba8 => 4,
+            TrailFormat::Rgba16f => 8,
+            TrailFormat::Rgba32f => 16,
+        }
+    }
+}
+
+/// Which physical device to run the simulation on.
+#[derive(Clone, Debug, PartialEq)]
+pub enum DeviceSelector {
+    /// Prefers a discrete gpu, then an integrated one, then anything else that works.
+    Auto,
+    /// The index in the list of devices, as shown by `list_devices`.
+    Index(usize),
+    /// The first device whose name contains this, ignoring case.
+    Name(String),
+    /// The first device of this type.
+    Type(PhysicalDeviceType),
+}
+
+impl Default for DeviceSelector {
+    fn default() -> Self {
+        DeviceSelector::Auto
+    }
+}
+
+impl FromStr for DeviceSelector {
+    type Err = std::convert::Infallible;
+
+    /// Parses `auto`, a device index, a device type (`discrete`, `integrated`, `virtual`
+    /// or `cpu`), and otherwise treats the text as part of a device name.
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        let selector = match text.to_lowercase().as_str() {
+            "auto" => DeviceSelector::Auto,
+            "discrete" => DeviceSelector::Type(PhysicalDeviceType::DiscreteGpu),
+            "integrated" => DeviceSelector::Type(PhysicalDeviceType::IntegratedGpu),
+            "virtual" => DeviceSelector::Type(PhysicalDeviceType::VirtualGpu),
+            "cpu" => DeviceSelector::Type(PhysicalDeviceType::Cpu),
+            _ => match text.parse() {
+                Ok(index) => DeviceSelector::Index(index),
+                Err(_) => DeviceSelector::Name(text.to_owned()),
+            },
+        };
+        Ok(selector)
+    }
+}
+
+impl DeviceSelector {
+    fn matches(&self, physical: &PhysicalDevice) -> bool {
+        match self {
+            DeviceSelector::Auto => true,
+            DeviceSelector::Index(index) => physical.index() == *index,
+            DeviceSelector::Name(name) => physical
+                .name()
+                .to_lowercase()
+                .contains(&name.to_lowercase()),
+            DeviceSelector::Type(ty) => physical.ty() == *ty,
+        }
+    }
+}
+
+/// A short description of a device, for listing them to the user.
+#[derive(Clone, Debug)]
+pub struct DeviceInfo {
+    pub index: usize,
+    pub name: String,
+    pub ty: PhysicalDeviceType,
+    /// Why the simulation can not run on this device, if it can not.
+    pub unsupported: Option<String>,
+}
+
+/// All devices the vulkan implementation knows about.
+pub fn list_devices() -> Result<Vec<DeviceInfo>, SimError> {
+    let instance = Instance::new(None, &InstanceExtensions::none(), None)?;
+
+    Ok(PhysicalDevice::enumerate(&instance)
+        .map(|physical| DeviceInfo {
+            index: physical.index(),
+            name: physical.name().to_owned(),
+            ty: physical.ty(),
+            unsupported: required_extensions(physical)
+                .and_then(|_| check_formats(physical))
+                .err()
+                .map(|e| e.to_string()),
+        })
+        .collect())
+}
+
+/// Picks the device to use. Devices for which `usable` returns false, or that miss
+/// something the simulation needs, are skipped unless they were asked for explicitly.
+pub fn select<'a, F>(
+    instance: &'a Arc<Instance>,
+    selector: &DeviceSelector,
+    usable: F,
+) -> Result<PhysicalDevice<'a>, SimError>
+where
+    F: Fn(PhysicalDevice) -> bool,
+{
+    let mut candidates: Vec<PhysicalDevice> = PhysicalDevice::enumerate(instance)
+        .filter(|p| selector.matches(p))
+        .collect();
+
+    if candidates.is_empty() {
+        return Err(SimError::NoDevice(match selector {
+            DeviceSelector::Auto => "no vulkan devices available".to_owned(),
+            DeviceSelector::Index(index) => format!("there is no device with index {}", index),
+            DeviceSelector::Name(name) => format!("no device name contains '{}'", name),
+            DeviceSelector::Type(ty) => format!("there is no device of type {:?}", ty),
+        }));
+    }
+
+    // An explicitly chosen device reports what is wrong with it, instead of being skipped.
+    if let DeviceSelector::Index(_) = selector {
+        let physical = candidates[0];
+        check_support(physical)?;
+        return Ok(physical);
+    }
+
+    candidates.sort_by_key(|p| type_preference(p.ty()));
+
+    let mut last_error = None;
+    for physical in candidates {
+        if !usable(physical) {
+            continue;
+        }
+        match check_support(physical) {
+            Ok(()) => return Ok(physical),
+            Err(e) => last_error = Some(e),
+        }
+    }
+
+    Err(last_error.unwrap_or_else(|| {
+        SimError::NoDevice("none of the matching devices can be used".to_owned())
+    }))
+}
+
+/// Lower is better.
+fn type_preference(ty: PhysicalDeviceType) -> u32 {
+    match ty {
+        PhysicalDeviceType::DiscreteGpu => 0,
+        PhysicalDeviceType::IntegratedGpu => 1,
+        PhysicalDeviceType::VirtualGpu => 2,
+        PhysicalDeviceType::Cpu => 3,
+        PhysicalDeviceType::Other => 4,
+    }
+}
+
+fn check_support(physical: PhysicalDevice) -> Result<(), SimError> {
+    required_extensions(physical)?;
+    check_formats(physical)
+}
+
+/// The extensions the simulation needs, as far as the device does not have them built in.
+pub fn required_extensions(physical: PhysicalDevice) -> Result<DeviceExtensions, SimError> {
+    let supported = DeviceExtensions::supported_by_device(physical);
+    let mut extensions = DeviceExtensions::none();
+
+    // Needed for compute shaders, but part of vulkan itself since 1.1.
+    if supported.khr_storage_buffer_storage_class {
+        extensions.khr_storage_buffer_storage_class = true;
+    } else if physical.api_version()
+        < (Version {
+            major: 1,
+            minor: 1,
+            patch: 0,
+        })
+    {
+        return Err(SimError::MissingExtension(
+            "VK_KHR_storage_buffer_storage_class",
+        ));
+    }
+
+    Ok(extensions)
+}
+
+/// Checks that the display image can be written by a shader and copied around.
+pub fn check_formats(physical: PhysicalDevice) -> Result<(), SimError> {
+    let features = DISPLAY_FORMAT.properties(physical).optimal_tiling_features;
+    if features.storage_image && features.transfer_src && features.transfer_dst {
+        Ok(())
+    } else {
+        Err(SimError::UnsupportedFormat(DISPLAY_FORMAT))
+    }
+}
+
+/// Checks that the trails can be simulated in the given format, which needs storage images
+/// that can be cleared.
+pub fn check_trail_format(physical: PhysicalDevice, format: TrailFormat) -> Result<(), SimError> {
+    let features = format.format().properties(physical).optimal_tiling_features;
+    if features.storage_image && features.transfer_dst {
+        Ok(())
+    } else {
+        Err(SimError::UnsupportedFormat(format.format()))
+    }
+}
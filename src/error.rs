@@ -0,0 +1,159 @@
+use std::error::Error;
+use std::fmt;
+use vulkano::buffer::cpu_access::{ReadLockError, WriteLockError};
+use vulkano::command_buffer::{
+    BuildError, ClearColorImageError, CommandBufferExecError, CopyBufferImageError, CopyImageError,
+    DispatchError, FillBufferError, UpdateBufferError,
+};
+use vulkano::descriptor::descriptor_set::{
+    PersistentDescriptorSetBuildError, PersistentDescriptorSetError,
+};
+use vulkano::device::DeviceCreationError;
+use vulkano::format::Format;
+use vulkano::image::ImageCreationError;
+use vulkano::instance::InstanceCreationError;
+use vulkano::memory::DeviceMemoryAllocError;
+use vulkano::pipeline::ComputePipelineCreationError;
+use vulkano::sync::FlushError;
+use vulkano::OomError;
+
+/// Everything that can go wrong while setting up or running the simulation.
+#[derive(Debug)]
+pub enum SimError {
+    /// No vulkan implementation could be loaded.
+    Instance(InstanceCreationError),
+    /// No device matches what was asked for.
+    NoDevice(String),
+    /// The device has no queue family that can run compute shaders.
+    NoComputeQueue,
+    /// The device lacks an extension the simulation needs.
+    MissingExtension(&'static str),
+    /// The device can not use this format for the trail or display images.
+    UnsupportedFormat(Format),
+    Device(DeviceCreationError),
+    /// A gpu buffer or image could not be allocated.
+    OutOfMemory(String),
+    /// A shader, pipeline or descriptor set could not be created.
+    Pipeline(String),
+    /// A command buffer could not be recorded.
+    CommandBuffer(String),
+    /// Submitting work to the gpu, or waiting for it, failed.
+    Execution(String),
+    /// A buffer that should be read or written on the cpu is still in use by the gpu.
+    BufferLocked(String),
+    /// The window, its surface or its swapchain could not be created.
+    Window(String),
+    /// A frame could not be drawn to the window, or its swapchain could not be recreated.
+    Display(String),
+    /// A file could not be read or written, with its path in the message.
+    File(String),
+    /// Parameters could not be written as json, like for the texts of a screenshot.
+    Serialize(String),
+}
+
+impl SimError {
+    /// Advice on what the user could change to avoid the error, if there is any.
+    pub fn hint(&self) -> Option<&'static str> {
+        match self {
+            SimError::Instance(_) => Some(
+                "Make sure a vulkan driver is installed. Without a gpu, a software \
+                 implementation like lavapipe or swiftshader can be used.",
+            ),
+            SimError::NoDevice(_)
+            | SimError::NoComputeQueue
+            | SimError::MissingExtension(_)
+            | SimError::Device(_) => Some(
+                "Run with --list-devices to see the available devices, \
+                 and pick another one with --device.",
+            ),
+            SimError::UnsupportedFormat(_) => {
+                Some("Try another --trail-format, or pick another device with --device.")
+            }
+            SimError::OutOfMemory(_) => {
+                Some("Try a smaller --resolution or fewer --agents, or another --device.")
+            }
+            SimError::Window(_) => Some("Use --headless to run without a window."),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for SimError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SimError::Instance(e) => write!(f, "Failed to load vulkan: {}", e),
+            SimError::NoDevice(e) => write!(f, "No suitable device: {}", e),
+            SimError::NoComputeQueue => write!(f, "The device can not run compute shaders"),
+            SimError::MissingExtension(name) => {
+                write!(f, "The device does not support the {} extension", name)
+            }
+            SimError::UnsupportedFormat(format) => {
+                write!(f, "The device can not use {:?} for storage images", format)
+            }
+            SimError::Device(e) => write!(f, "Failed to create the device: {}", e),
+            SimError::OutOfMemory(e) => write!(f, "Failed to allocate gpu memory: {}", e),
+            SimError::Pipeline(e) => write!(f, "Failed to create a compute pipeline: {}", e),
+            SimError::CommandBuffer(e) => write!(f, "Failed to record commands: {}", e),
+            SimError::Execution(e) => write!(f, "Failed to run commands on the gpu: {}", e),
+            SimError::BufferLocked(e) => write!(f, "Buffer is in use by the gpu: {}", e),
+            SimError::Window(e) => write!(f, "Failed to create the window: {}", e),
+            SimError::Display(e) => write!(f, "Failed to display a frame: {}", e),
+            SimError::File(e) => write!(f, "Failed to access {}", e),
+            SimError::Serialize(e) => write!(f, "Failed to write the parameters as json: {}", e),
+        }
+    }
+}
+
+impl Error for SimError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            SimError::Instance(e) => Some(e),
+            SimError::Device(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<InstanceCreationError> for SimError {
+    fn from(e: InstanceCreationError) -> Self {
+        SimError::Instance(e)
+    }
+}
+
+impl From<DeviceCreationError> for SimError {
+    fn from(e: DeviceCreationError) -> Self {
+        SimError::Device(e)
+    }
+}
+
+/// Converts errors that are only reported as a message.
+macro_rules! impl_from {
+    ($variant:ident: $($error:ty),+) => {
+        $(
+            impl From<$error> for SimError {
+                fn from(e: $error) -> Self {
+                    SimError::$variant(e.to_string())
+                }
+            }
+        )+
+    };
+}
+
+impl_from!(OutOfMemory: DeviceMemoryAllocError, ImageCreationError, OomError);
+impl_from!(
+    Pipeline: ComputePipelineCreationError,
+    PersistentDescriptorSetError,
+    PersistentDescriptorSetBuildError
+);
+impl_from!(
+    CommandBuffer: BuildError,
+    ClearColorImageError,
+    CopyBufferImageError,
+    CopyImageError,
+    DispatchError,
+    FillBufferError,
+    UpdateBufferError
+);
+impl_from!(Execution: CommandBufferExecError, FlushError);
+impl_from!(BufferLocked: ReadLockError, WriteLockError);
+impl_from!(Serialize: serde_json::Error);
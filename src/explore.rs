@@ -0,0 +1,342 @@
+use crate::error::SimError;
+use crate::metrics::ImageMetrics;
+use crate::model::Model;
+use crate::parameters;
+use crate::preset::Preset;
+use crate::simulation::{AgentParameters, FadeParameters, Simulation, SimulationConfig};
+use image::imageops::FilterType;
+use image::RgbaImage;
+use imgui::{im_str, Condition, ImString, ImageButton, TextureId, Textures, Ui, Window};
+use imgui_vulkano_renderer::Texture;
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+use rand::{Rng, SeedableRng};
+use std::f32::consts::PI;
+use std::sync::Arc;
+use vulkano::device::{Device, Queue};
+use vulkano::format::Format;
+use vulkano::image::{Dimensions, ImageViewAccess, ImmutableImage};
+use vulkano::sampler::Sampler;
+use vulkano::sync::GpuFuture;
+
+/// Candidates are run smaller than the real simulation, so a generation finishes quickly.
+const EXPLORE_WIDTH: u32 = 400;
+const EXPLORE_HEIGHT: u32 = 280;
+const EXPLORE_AGENTS: u32 = 40_000;
+const EXPLORE_STEPS: u32 = 300;
+
+const THUMBNAIL_WIDTH: u32 = 120;
+const THUMBNAIL_HEIGHT: u32 = 84;
+const GENERATION_SIZE: usize = 12;
+const COLUMNS: usize = 4;
+
+/// Chance for each parameter of a child to be mutated.
+const MUTATION_CHANCE: f64 = 0.3;
+/// How far a mutation moves a parameter, relative to its value.
+const MUTATION_STRENGTH: f32 = 0.25;
+
+pub struct Candidate {
+    pub preset: Preset,
+    pub metrics: ImageMetrics,
+    texture: TextureId,
+    selected: bool,
+}
+
+/// Runs randomly sampled or bred parameter sets, and shows the results to pick from.
+pub struct Explorer {
+    device: Arc<Device>,
+    queue: Arc<Queue>,
+    /// Parameters that are not explored are taken from here.
+    base: Preset,
+    rng: StdRng,
+    sampler: Arc<Sampler>,
+    /// Presets that still need to be run.
+    pending: Vec<Preset>,
+    candidates: Vec<Candidate>,
+    prefix: ImString,
+    status: String,
+}
+
+impl Explorer {
+    pub fn new(device: Arc<Device>, queue: Arc<Queue>, base: Preset) -> Explorer {
+        let sampler = Sampler::simple_repeat_linear(device.clone());
+
+        let mut prefix = ImString::with_capacity(256);
+        prefix.push_str("explored");
+
+        Explorer {
+            device,
+            queue,
+            base,
+            rng: StdRng::from_entropy(),
+            sampler,
+            pending: Vec::new(),
+            candidates: Vec::new(),
+            prefix,
+            status: String::new(),
+        }
+    }
+
+    /// Replaces the candidates with randomly sampled ones.
+    pub fn random_generation(&mut self, textures: &mut Textures<Texture>) {
+        self.clear(textures);
+        self.pending = (0..GENERATION_SIZE)
+            .map(|_| random_preset(&mut self.rng, &self.base))
+            .collect();
+    }
+
+    /// Replaces the candidates with children of the selected ones.
+    /// The parents themselves are kept as well.
+    pub fn breed_selected(&mut self, textures: &mut Textures<Texture>) {
+        let parents: Vec<Preset> = self
+            .candidates
+            .iter()
+            .filter(|c| c.selected)
+            .map(|c| c.preset.clone())
+            .collect();
+        if parents.is_empty() {
+            self.status = "Select one or more candidates to breed.".to_owned();
+            return;
+        }
+
+        self.clear(textures);
+        self.pending = parents.clone();
+        while self.pending.len() < GENERATION_SIZE {
+            let a = parents.choose(&mut self.rng).unwrap();
+            let b = parents.choose(&mut self.rng).unwrap();
+            let child = breed(&mut self.rng, a, b);
+            self.pending.push(child);
+        }
+    }
+
+    /// Runs a single pending candidate. Call once per frame, so the ui stays responsive.
+    pub fn update(&mut self, textures: &mut Textures<Texture>) {
+        let preset = match self.pending.pop() {
+            Some(preset) => preset,
+            None => return,
+        };
+
+        let image = match self.run(&preset) {
+            Ok(image) => image,
+            Err(e) => {
+                self.status = format!("Failed to run a candidate: {}", e);
+                return;
+            }
+        };
+        let metrics = ImageMetrics::measure(&image);
+        let thumbnail = image::imageops::resize(
+            &image,
+            THUMBNAIL_WIDTH,
+            THUMBNAIL_HEIGHT,
+            FilterType::Triangle,
+        );
+        let texture = textures.insert((self.upload(thumbnail.into_raw()), self.sampler.clone()));
+
+        self.candidates.push(Candidate {
+            preset,
+            metrics,
+            texture,
+            selected: false,
+        });
+        self.candidates
+            .sort_by(|a, b| b.metrics.score().partial_cmp(&a.metrics.score()).unwrap());
+    }
+
+    pub fn draw(
+        &mut self,
+        ui: &Ui,
+        textures: &mut Textures<Texture>,
+        sim_parameters: &mut AgentParameters,
+        fade_parameters: &mut FadeParameters,
+    ) {
+        let explorer = self;
+
+        Window::new(im_str!("Explore"))
+            .position([10.0, 500.0], Condition::FirstUseEver)
+            .size([560.0, 520.0], Condition::FirstUseEver)
+            .build(ui, || {
+                if ui.button(im_str!("Random generation"), [0.0, 0.0]) {
+                    explorer.random_generation(textures);
+                }
+                ui.same_line(0.0);
+                if ui.button(im_str!("Breed selected"), [0.0, 0.0]) {
+                    explorer.breed_selected(textures);
+                }
+
+                if !explorer.pending.is_empty() {
+                    ui.text(format!(
+                        "Running candidates, {} to go...",
+                        explorer.pending.len()
+                    ));
+                }
+
+                let mut applied = None;
+                for (i, candidate) in explorer.candidates.iter_mut().enumerate() {
+                    let id = ui.push_id(i as i32);
+                    ui.group(|| {
+                        let background = if candidate.selected {
+                            [1.0, 0.8, 0.0, 1.0]
+                        } else {
+                            [0.0, 0.0, 0.0, 0.0]
+                        };
+                        if ImageButton::new(
+                            candidate.texture,
+                            [THUMBNAIL_WIDTH as f32, THUMBNAIL_HEIGHT as f32],
+                        )
+                        .background_col(background)
+                        .build(&ui)
+                        {
+                            candidate.selected = !candidate.selected;
+                        }
+                        if ui.is_item_hovered() {
+                            ui.tooltip_text(format!(
+                                "Coverage: {:.2}\nEdges: {:.2}\nEntropy: {:.2}",
+                                candidate.metrics.coverage,
+                                candidate.metrics.edge_density,
+                                candidate.metrics.entropy
+                            ));
+                        }
+
+                        ui.text(format!("Score {:.2}", candidate.metrics.score()));
+                        ui.same_line(0.0);
+                        if ui.button(im_str!("Use"), [0.0, 0.0]) {
+                            applied = Some(i);
+                        }
+                    });
+                    id.pop(&ui);
+
+                    if (i + 1) % COLUMNS != 0 {
+                        ui.same_line(0.0);
+                    }
+                }
+                ui.new_line();
+
+                if let Some(index) = applied {
+                    let preset = &explorer.candidates[index].preset;
+                    *sim_parameters = preset.sim_parameters();
+                    *fade_parameters = preset.fade_parameters();
+                }
+
+                ui.separator();
+                ui.input_text(im_str!("Preset prefix"), &mut explorer.prefix)
+                    .build();
+                if ui.button(im_str!("Save selected as presets"), [0.0, 0.0]) {
+                    explorer.save_selected();
+                }
+                ui.text(&explorer.status);
+            });
+    }
+
+    fn save_selected(&mut self) {
+        let mut saved = 0;
+        for (i, candidate) in self.candidates.iter().filter(|c| c.selected).enumerate() {
+            let path = format!("{}_{}.json", self.prefix.to_str(), i);
+            if let Err(e) = candidate.preset.save(&path) {
+                self.status = format!("Failed to save '{}': {}", path, e);
+                return;
+            }
+            saved += 1;
+        }
+        self.status = format!("Saved {} presets.", saved);
+    }
+
+    fn clear(&mut self, textures: &mut Textures<Texture>) {
+        for candidate in self.candidates.drain(..) {
+            textures.remove(candidate.texture);
+        }
+        self.pending.clear();
+        self.status.clear();
+    }
+
+    /// Runs the preset on a small simulation, and returns the resulting trails.
+    fn run(&self, preset: &Preset) -> Result<RgbaImage, SimError> {
+        let config = SimulationConfig {
+            width: EXPLORE_WIDTH,
+            height: EXPLORE_HEIGHT,
+            agent_amount: EXPLORE_AGENTS,
+            seed: preset.seed.unwrap_or(0),
+            trail_format: preset.trail_format,
+        };
+        let mut sim = Simulation::init(self.device.clone(), self.queue.clone(), &config)?;
+        sim.run_steps(EXPLORE_STEPS, &preset.parameters)?;
+
+        sim.read_result_image()
+    }
+
+    fn upload(&self, pixels: Vec<u8>) -> Arc<dyn ImageViewAccess + Send + Sync> {
+        let (image, future) = ImmutableImage::from_iter(
+            pixels.into_iter(),
+            Dimensions::Dim2d {
+                width: THUMBNAIL_WIDTH,
+                height: THUMBNAIL_HEIGHT,
+            },
+            Format::R8G8B8A8Unorm,
+            self.queue.clone(),
+        )
+        .expect("Failed to create thumbnail image");
+
+        future
+            .then_signal_fence_and_flush()
+            .unwrap()
+            .wait(None)
+            .unwrap();
+
+        image
+    }
+}
+
+fn random_preset(rng: &mut StdRng, base: &Preset) -> Preset {
+    let mut preset = base.clone();
+    preset.seed = Some(rng.gen());
+
+    let parameters = &mut preset.parameters;
+    parameters.sim.agent_speed = rng.gen_range(10.0..300.0);
+    parameters.sim.agent_turn_speed = rng.gen_range(1.0..100.0);
+    parameters.sim.sensor_radius = rng.gen_range(0..=3);
+    parameters.sim.sensor_angle_spacing = rng.gen_range(0.05..PI / 2.0);
+    parameters.fade.evaporate_speed = rng.gen_range(0.1..3.0);
+    parameters.fade.blur_radius = rng.gen_range(1..=2);
+    preset
+}
+
+/// Takes each parameter from either parent, and then randomly nudges some of them.
+fn breed(rng: &mut StdRng, a: &Preset, b: &Preset) -> Preset {
+    let mut pick = |x: f32, y: f32| {
+        let value = if rng.gen_bool(0.5) { x } else { y };
+        if rng.gen_bool(MUTATION_CHANCE) {
+            value * (1.0 + rng.gen_range(-MUTATION_STRENGTH..MUTATION_STRENGTH))
+        } else {
+            value
+        }
+    };
+
+    let (a_sim, b_sim) = (&a.parameters.sim, &b.parameters.sim);
+    let (a_fade, b_fade) = (&a.parameters.fade, &b.parameters.fade);
+    let agent_speed = pick(a_sim.agent_speed, b_sim.agent_speed);
+    let agent_turn_speed = pick(a_sim.agent_turn_speed, b_sim.agent_turn_speed);
+    let sensor_angle_spacing = pick(a_sim.sensor_angle_spacing, b_sim.sensor_angle_spacing);
+    let evaporate_speed = pick(a_fade.evaporate_speed, b_fade.evaporate_speed);
+
+    let mut child = a.clone();
+    child.seed = Some(rng.gen());
+
+    let parameters = &mut child.parameters;
+    parameters.sim.agent_speed = agent_speed;
+    parameters.sim.agent_turn_speed = agent_turn_speed;
+    parameters.sim.sensor_radius = if rng.gen_bool(0.5) {
+        a_sim.sensor_radius
+    } else {
+        b_sim.sensor_radius
+    };
+    parameters.sim.sensor_angle_spacing = sensor_angle_spacing;
+    parameters.fade.evaporate_speed = evaporate_speed;
+    parameters.fade.blur_radius = if rng.gen_bool(0.5) {
+        a_fade.blur_radius
+    } else {
+        b_fade.blur_radius
+    };
+
+    // Mutations can push values out of their range.
+    parameters::clamp_all(&mut parameters.sim, &mut parameters.fade);
+    child
+}
@@ -0,0 +1,126 @@
+use crate::device;
+use crate::device::DeviceSelector;
+use crate::error::SimError;
+use crate::model::Model;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use vulkano::device::{Device, Features, Queue};
+use vulkano::instance::{Instance, InstanceExtensions};
+
+/// Creates a device and compute queue without a window, for running the simulation headless.
+/// `device.physical_device()` tells which device was picked.
+///
+/// Use `DeviceSelector::Type(PhysicalDeviceType::Cpu)` to require a cpu implementation
+/// (like lavapipe or swiftshader).
+pub fn init_device(selector: &DeviceSelector) -> Result<(Arc<Device>, Arc<Queue>), SimError> {
+    let instance = Instance::new(None, &InstanceExtensions::none(), None)?;
+
+    let physical = device::select(&instance, selector, |p| {
+        p.queue_families().any(|q| q.supports_compute())
+    })?;
+
+    let queue_family = physical
+        .queue_families()
+        .find(|&q| q.supports_compute())
+        .ok_or(SimError::NoComputeQueue)?;
+
+    let (device, mut queues) = Device::new(
+        physical,
+        &Features::none(),
+        &device::required_extensions(physical)?,
+        [(queue_family, 0.5)].iter().cloned(),
+    )?;
+
+    let queue = queues.next().unwrap();
+
+    Ok((device, queue))
+}
+
+/// How `run` steps a model and where it saves the images.
+#[derive(Clone, Debug)]
+pub struct RunConfig {
+    pub steps: u32,
+    /// Submit every step on its own, for callbacks that need each of them.
+    pub every_step: bool,
+    /// Save a numbered image every this many steps, instead of only at the end.
+    pub frame_interval: Option<u32>,
+    /// Where the final image is saved, and what the numbered images are named after.
+    pub output: PathBuf,
+}
+
+/// Runs any model without a window, and saves its images. Returns the paths they were saved
+/// to, in order. Failing to save an image stops the run.
+///
+/// `before_batch` can change the parameters before every batch of steps, and `after_batch`
+/// can look at the model after it. Both get the amount of steps run so far.
+pub fn run<M, B, A>(
+    model: &mut M,
+    parameters: &mut M::Parameters,
+    config: &RunConfig,
+    mut before_batch: B,
+    mut after_batch: A,
+) -> Result<Vec<PathBuf>, SimError>
+where
+    M: Model,
+    B: FnMut(&mut M::Parameters, u32),
+    A: FnMut(&M, u32) -> Result<(), SimError>,
+{
+    let batch_size = match (config.every_step, config.frame_interval) {
+        (true, _) => 1,
+        (false, Some(interval)) => interval.max(1),
+        (false, None) => config.steps.max(1),
+    };
+
+    let mut step = 0;
+    let mut saved = Vec::new();
+    while step < config.steps {
+        before_batch(parameters, step);
+
+        let batch = batch_size.min(config.steps - step);
+        model.run_steps(batch, parameters)?;
+        step += batch;
+
+        after_batch(model, step)?;
+
+        if let Some(interval) = config.frame_interval {
+            if step % interval.max(1) == 0 {
+                let path = frame_path(&config.output, saved.len() as u32);
+                save_snapshot(model, parameters, step, &path)?;
+                saved.push(path);
+            }
+        }
+    }
+
+    if config.frame_interval.is_none() {
+        save_snapshot(model, parameters, step, &config.output)?;
+        saved.push(config.output.clone());
+    }
+    Ok(saved)
+}
+
+/// Png images get the parameters embedded, so they can be passed back as `--preset`.
+fn save_snapshot<M: Model>(
+    model: &M,
+    parameters: &M::Parameters,
+    step: u32,
+    path: &Path,
+) -> Result<(), SimError> {
+    model
+        .snapshot(parameters, step as u64)?
+        .save(path)
+        .map_err(|e| SimError::File(format!("'{}': {}", path.display(), e)))
+}
+
+/// Turns `output.png` into `output_00042.png`.
+pub fn frame_path(output: &Path, frame: u32) -> PathBuf {
+    let stem = output
+        .file_stem()
+        .map(|s| s.to_string_lossy().into_owned())
+        .unwrap_or_else(|| "frame".to_owned());
+    let extension = output
+        .extension()
+        .map(|e| e.to_string_lossy().into_owned())
+        .unwrap_or_else(|| "png".to_owned());
+
+    output.with_file_name(format!("{}_{:05}.{}", stem, frame, extension))
+}
@@ -0,0 +1,242 @@
+use crate::parameters::ParameterSet;
+use crate::simulation::{AgentParameters, FadeParameters};
+use imgui::{im_str, Condition, ImString, Key, Selectable, Ui, Window};
+
+/// Older entries are dropped once there are more than this.
+const MAX_ENTRIES: usize = 200;
+
+/// The parameters after a change, and what was changed.
+struct Entry {
+    parameters: ParameterSet,
+    description: String,
+}
+
+/// Records the parameter edits made in the ui, so they can be undone with Ctrl+Z and redone
+/// with Ctrl+Y. A slider drag becomes a single entry once the slider is let go.
+/// Changes made outside of the ui, like by a timeline or remote control, are not recorded.
+pub struct History {
+    /// The oldest entry holds the parameters the history started with.
+    entries: Vec<Entry>,
+    /// Index of the entry the parameters were last set to.
+    current: usize,
+    /// The parameters before the ui was drawn this frame.
+    before_ui: ParameterSet,
+    /// Whether the ui changed the parameters since the last entry, in a drag that is still going.
+    editing: bool,
+}
+
+impl History {
+    pub fn new(sim_parameters: &AgentParameters, fade_parameters: &FadeParameters) -> History {
+        let parameters = ParameterSet::from_parameters(sim_parameters, fade_parameters);
+        History {
+            entries: vec![Entry {
+                parameters,
+                description: "Start".to_owned(),
+            }],
+            current: 0,
+            before_ui: parameters,
+            editing: false,
+        }
+    }
+
+    /// Call before drawing the windows that edit the parameters.
+    pub fn begin_frame(
+        &mut self,
+        sim_parameters: &AgentParameters,
+        fade_parameters: &FadeParameters,
+    ) {
+        self.before_ui = ParameterSet::from_parameters(sim_parameters, fade_parameters);
+    }
+
+    /// Call after drawing the windows that edit the parameters. Records what they changed,
+    /// handles the shortcuts and draws the history window.
+    pub fn end_frame(
+        &mut self,
+        ui: &Ui,
+        sim_parameters: &mut AgentParameters,
+        fade_parameters: &mut FadeParameters,
+    ) {
+        let after_ui = ParameterSet::from_parameters(sim_parameters, fade_parameters);
+        if after_ui != self.before_ui {
+            self.editing = true;
+        }
+        // Wait for drags and text input to finish, so they end up as a single entry.
+        if self.editing && !ui.is_any_item_active() {
+            self.editing = false;
+            self.record(after_ui);
+        }
+
+        let io = ui.io();
+        if io.key_ctrl && !io.want_text_input {
+            let redo = ui.is_key_pressed(Key::Y) || (io.key_shift && ui.is_key_pressed(Key::Z));
+            if redo {
+                self.jump(self.current + 1, sim_parameters, fade_parameters);
+            } else if ui.is_key_pressed(Key::Z) && self.current > 0 {
+                self.jump(self.current - 1, sim_parameters, fade_parameters);
+            }
+        }
+
+        self.draw_window(ui, sim_parameters, fade_parameters);
+    }
+
+    fn record(&mut self, parameters: ParameterSet) {
+        let changes = self.entries[self.current].parameters.changes(&parameters);
+        if changes.is_empty() {
+            // A drag that ended where it started.
+            return;
+        }
+
+        let labels: Vec<&str> = changes.iter().map(|parameter| parameter.label).collect();
+        self.entries.truncate(self.current + 1);
+        self.entries.push(Entry {
+            parameters,
+            description: labels.join(", "),
+        });
+        if self.entries.len() > MAX_ENTRIES {
+            self.entries.remove(0);
+        }
+        self.current = self.entries.len() - 1;
+    }
+
+    /// Sets the parameters to those of an entry, keeping the later entries to redo.
+    fn jump(
+        &mut self,
+        index: usize,
+        sim_parameters: &mut AgentParameters,
+        fade_parameters: &mut FadeParameters,
+    ) {
+        if let Some(entry) = self.entries.get(index) {
+            entry.parameters.apply_to(sim_parameters, fade_parameters);
+            self.current = index;
+            self.editing = false;
+        }
+    }
+
+    fn draw_window(
+        &mut self,
+        ui: &Ui,
+        sim_parameters: &mut AgentParameters,
+        fade_parameters: &mut FadeParameters,
+    ) {
+        let history = self;
+        let mut jump_to = None;
+
+        Window::new(im_str!("History"))
+            .position([1160.0, 10.0], Condition::FirstUseEver)
+            .size([240.0, 300.0], Condition::FirstUseEver)
+            .build(ui, || {
+                if ui.button(im_str!("Undo"), [0.0, 0.0]) && history.current > 0 {
+                    jump_to = Some(history.current - 1);
+                }
+                ui.same_line(0.0);
+                if ui.button(im_str!("Redo"), [0.0, 0.0]) {
+                    jump_to = Some(history.current + 1);
+                }
+                ui.separator();
+
+                // Newest first. Entries after the current one can still be redone.
+                for (index, entry) in history.entries.iter().enumerate().rev() {
+                    let label =
+                        ImString::new(format!("{}: {}##{}", index, entry.description, index));
+                    if Selectable::new(&label)
+                        .selected(index == history.current)
+                        .build(ui)
+                    {
+                        jump_to = Some(index);
+                    }
+                }
+            });
+
+        if let Some(index) = jump_to {
+            history.jump(index, sim_parameters, fade_parameters);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parameters;
+    use crate::parameters::Value;
+
+    fn history() -> History {
+        let set = ParameterSet::default();
+        History::new(&set.sim, &set.fade)
+    }
+
+    fn with_speed(speed: f32) -> ParameterSet {
+        let mut set = ParameterSet::default();
+        set.set(
+            parameters::find("agent_speed").unwrap(),
+            Value::Float(speed),
+        );
+        set
+    }
+
+    fn speed(history: &History) -> Value {
+        let entry = &history.entries[history.current];
+        entry
+            .parameters
+            .get(parameters::find("agent_speed").unwrap())
+    }
+
+    #[test]
+    fn record_adds_an_entry_for_changes() {
+        let mut history = history();
+        history.record(with_speed(1.0));
+        assert_eq!(history.entries.len(), 2);
+        assert_eq!(history.current, 1);
+        assert_eq!(history.entries[1].description, "Speed");
+        assert_eq!(speed(&history), Value::Float(1.0));
+    }
+
+    #[test]
+    fn record_ignores_unchanged_parameters() {
+        let mut history = history();
+        history.record(ParameterSet::default());
+        assert_eq!(history.entries.len(), 1);
+        assert_eq!(history.current, 0);
+    }
+
+    #[test]
+    fn new_edit_after_undo_drops_the_redo_entries() {
+        let mut history = history();
+        let mut set = ParameterSet::default();
+        history.record(with_speed(1.0));
+        history.record(with_speed(2.0));
+        history.record(with_speed(3.0));
+
+        history.jump(1, &mut set.sim, &mut set.fade);
+        assert_eq!(
+            set.get(parameters::find("agent_speed").unwrap()),
+            Value::Float(1.0)
+        );
+        assert_eq!(history.entries.len(), 4);
+
+        history.record(with_speed(5.0));
+        assert_eq!(history.entries.len(), 3);
+        assert_eq!(history.current, 2);
+        assert_eq!(speed(&history), Value::Float(5.0));
+
+        // Nothing left to redo.
+        history.jump(3, &mut set.sim, &mut set.fade);
+        assert_eq!(history.current, 2);
+    }
+
+    #[test]
+    fn oldest_entries_are_dropped_after_max_entries() {
+        let mut history = history();
+        for speed in 1..=MAX_ENTRIES + 10 {
+            history.record(with_speed(speed as f32));
+        }
+        assert_eq!(history.entries.len(), MAX_ENTRIES);
+        assert_eq!(history.current, MAX_ENTRIES - 1);
+        assert_eq!(speed(&history), Value::Float((MAX_ENTRIES + 10) as f32));
+        assert_eq!(
+            history.entries[0]
+                .parameters
+                .get(parameters::find("agent_speed").unwrap()),
+            Value::Float(11.0)
+        );
+    }
+}
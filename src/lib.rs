@@ -0,0 +1,44 @@
+//! A gpu slime mold simulation, and a reaction-diffusion model that shares its display
+//! and export.
+//!
+//! The simulation itself only needs a vulkan device and queue, so it can be embedded
+//! without a window. The window, the imgui interface and the tools built on them are
+//! behind the `ui` feature, which is enabled by default.
+
+pub mod colony;
+pub mod device;
+pub mod error;
+pub mod headless;
+pub mod metrics;
+pub mod model;
+pub mod neighbours;
+pub mod parameters;
+pub mod post;
+pub mod preset;
+pub mod profiler;
+pub mod reaction;
+pub mod remote;
+pub mod screenshot;
+pub mod simulation;
+pub mod statistics;
+pub mod timeline;
+pub mod volume;
+
+#[cfg(feature = "ui")]
+pub mod attractant_window;
+#[cfg(feature = "ui")]
+pub mod explore;
+#[cfg(feature = "ui")]
+pub mod history;
+#[cfg(feature = "ui")]
+pub mod slime_tools;
+#[cfg(feature = "ui")]
+pub mod statistics_window;
+#[cfg(feature = "ui")]
+pub mod system;
+#[cfg(feature = "ui")]
+pub mod timeline_editor;
+
+pub use crate::error::SimError;
+pub use crate::preset::Preset;
+pub use crate::simulation::{AgentParameters, FadeParameters, Simulation, SimulationConfig};
@@ -1,42 +1,372 @@
-use crate::system::System;
-use imgui::{im_str, Condition, Slider, Window};
-use std::f32::consts::PI;
+use crate::cli::Options;
+use slime_simulation::explore::Explorer;
+use slime_simulation::headless::RunConfig;
+use slime_simulation::history::History;
+use slime_simulation::parameters::ParameterSet;
+use slime_simulation::post::Colormap;
+use slime_simulation::reaction::{ReactionConfig, ReactionDiffusion, ReactionWindow};
+use slime_simulation::remote::RemoteServer;
+use slime_simulation::simulation::{AgentParameters, FadeParameters};
+use slime_simulation::slime_tools::SlimeTools;
+use slime_simulation::statistics::{CsvWriter, Statistics};
+use slime_simulation::system::System;
+use slime_simulation::timeline_editor::TimelineEditor;
+use slime_simulation::volume::{OrbitCamera, VolumeConfig, VolumeParameters, VolumeSimulation};
+use slime_simulation::{device, headless, parameters, volume};
+use slime_simulation::{SimError, Simulation};
+use std::path::{Path, PathBuf};
+use vulkano::device::Device;
 
-mod simulation;
-mod system;
+mod cli;
 
 fn main() {
-    println!("Hello, world!");
+    let options = cli::parse();
 
-    let system = System::init("Slime Simulation");
+    if options.list_devices {
+        list_devices();
+        return;
+    }
+
+    let config = options.preset.config();
+    let sim_parameters = options.preset.sim_parameters();
+    let fade_parameters = options.preset.fade_parameters();
+
+    if let Some(path) = &options.save_preset {
+        if let Err(e) = options.preset.save(path) {
+            eprintln!("Failed to save preset '{}': {}", path.display(), e);
+        }
+    }
+
+    if let Some(steps) = options.headless_steps {
+        if let Err(e) = run_headless(&options, steps) {
+            exit_with_error(e);
+        }
+        return;
+    }
+
+    let system = System::init("Slime Simulation", &options.window, &options.device)
+        .unwrap_or_else(|e| exit_with_error(e));
+    print_device(&system.device);
+
+    if let Some(reaction_parameters) = options.reaction {
+        let reaction = ReactionDiffusion::with_display_queue(
+            system.device.clone(),
+            system.compute_queue.clone(),
+            &system.queue,
+            &reaction_config(&options),
+        )
+        .unwrap_or_else(|e| exit_with_error(e));
+
+        // The amount of B is shown in grey, a colormap makes the patterns easier to see.
+        let mut post_settings = options.preset.post.unwrap_or_default();
+        if post_settings.colormap == Colormap::None {
+            post_settings.colormap = Colormap::Magma;
+        }
+        let mut reaction_window = ReactionWindow::new();
+        let result = system.main_loop(
+            reaction,
+            reaction_parameters,
+            post_settings,
+            (),
+            move |_, parameters, ui, _, _| reaction_window.draw(ui, parameters),
+            exit_on_error,
+        );
+        if let Err(e) = result {
+            exit_with_error(e);
+        }
+        return;
+    }
+
+    if let Some(size) = options.volume_size {
+        let volume = VolumeSimulation::with_display_queue(
+            system.device.clone(),
+            system.compute_queue.clone(),
+            &system.queue,
+            &volume_config(&options, size),
+        )
+        .unwrap_or_else(|e| exit_with_error(e));
+
+        let mut timeline_editor = TimelineEditor::new(options.timeline.unwrap_or_default());
+        let mut history = History::new(&sim_parameters, &fade_parameters);
+        let parameters = VolumeParameters {
+            sim: sim_parameters,
+            fade: fade_parameters,
+            camera: OrbitCamera::default(),
+        };
+        let result = system.main_loop(
+            volume,
+            parameters,
+            options.preset.post.unwrap_or_default(),
+            (),
+            move |_, parameters, ui, _, steps| {
+                let VolumeParameters {
+                    sim: sim_parameters,
+                    fade: fade_parameters,
+                    camera,
+                } = parameters;
+                timeline_editor.update(steps, sim_parameters, fade_parameters);
+
+                history.begin_frame(sim_parameters, fade_parameters);
+                parameters::draw_window(ui, sim_parameters, fade_parameters);
+                timeline_editor.draw(ui, sim_parameters, fade_parameters);
+                history.end_frame(ui, sim_parameters, fade_parameters);
+                volume::draw_camera_window(ui, camera);
+            },
+            exit_on_error,
+        );
+        if let Err(e) = result {
+            exit_with_error(e);
+        }
+        return;
+    }
 
     // ---- Computing to an image buffer ----
 
-    let sim = simulation::Simulation::init(system.device.clone(), system.queue.clone());
+    let sim = Simulation::with_display_queue(
+        system.device.clone(),
+        system.compute_queue.clone(),
+        &system.queue,
+        &config,
+    )
+    .unwrap_or_else(|e| exit_with_error(e));
+    if let Some(path) = &options.attractant {
+        load_attractant(&sim, path).unwrap_or_else(|e| exit_with_error(e));
+    }
+
+    let remote_server = options.remote_address.as_ref().map(|address| {
+        let snapshot_dir = options
+            .snapshot_dir
+            .clone()
+            .unwrap_or_else(|| PathBuf::from("."));
+        let server = RemoteServer::start(address.as_str(), snapshot_dir).unwrap_or_else(|e| {
+            eprintln!("Failed to start remote control on '{}': {}", address, e);
+            std::process::exit(1);
+        });
+        println!("Remote control listening on {}", server.local_addr());
+        server
+    });
+    let colony_settings = options.preset.colony.unwrap_or_default();
+    let tools = SlimeTools::new(&sim, colony_settings, remote_server)
+        .unwrap_or_else(|e| exit_with_error(e));
+    let post_settings = options.preset.post.unwrap_or_default();
+
+    let mut timeline_editor = TimelineEditor::new(options.timeline.unwrap_or_default());
+    // The explorer uploads thumbnails for imgui, so it uses the graphics queue.
+    let mut explorer = Explorer::new(system.device.clone(), system.queue.clone(), options.preset);
+    let mut history = History::new(&sim_parameters, &fade_parameters);
 
     // ---- Window imgui loop ----
 
-    system.main_loop(sim, move |_, sim_parameters, fade_parameters, ui| {
-        Window::new(im_str!("Hello World!"))
-            .size([300.0, 200.0], Condition::FirstUseEver)
-            .build(ui, || {
-                ui.push_item_width(100.0);
-
-                ui.text(im_str!("Hello World!"));
-                ui.input_float(im_str!("Speed (px/s)"), &mut sim_parameters.agent_speed)
-                    .build();
-                ui.input_float(
-                    im_str!("Turn speed (rad/s)"),
-                    &mut sim_parameters.agent_turn_speed,
-                )
-                .build();
-                ui.input_int(im_str!("Sensor radius"), &mut sim_parameters.sensor_radius)
-                    .build();
-                Slider::new(im_str!("Sensor angles"))
-                    .range(0.0..=PI)
-                    .build(&ui, &mut sim_parameters.sensor_angle_spacing);
-                ui.input_float(im_str!("Fade speed"), &mut fade_parameters.evaporate_speed)
-                    .build();
-            });
-    })
+    let parameters = ParameterSet {
+        sim: sim_parameters,
+        fade: fade_parameters,
+    };
+    let result = system.main_loop(
+        sim,
+        parameters,
+        post_settings,
+        tools,
+        move |_, parameters, ui, textures, steps| {
+            let ParameterSet {
+                sim: sim_parameters,
+                fade: fade_parameters,
+            } = parameters;
+            timeline_editor.update(steps, sim_parameters, fade_parameters);
+            explorer.update(textures);
+
+            // Only the edits made in these windows end up in the history,
+            // not the changes made by playing the timeline.
+            history.begin_frame(sim_parameters, fade_parameters);
+            parameters::draw_window(ui, sim_parameters, fade_parameters);
+            timeline_editor.draw(ui, sim_parameters, fade_parameters);
+            explorer.draw(ui, textures, sim_parameters, fade_parameters);
+            history.end_frame(ui, sim_parameters, fade_parameters);
+        },
+        exit_on_error,
+    );
+    if let Err(e) = result {
+        exit_with_error(e);
+    }
+}
+
+fn list_devices() {
+    let devices = device::list_devices().unwrap_or_else(|e| exit_with_error(e));
+    if devices.is_empty() {
+        println!("No vulkan devices found.");
+    }
+    for info in devices {
+        match info.unsupported {
+            None => println!("{}: {} ({:?})", info.index, info.name, info.ty),
+            Some(reason) => println!(
+                "{}: {} ({:?}) - unsupported: {}",
+                info.index, info.name, info.ty, reason
+            ),
+        }
+    }
+}
+
+fn print_device(device: &Device) {
+    let physical = device.physical_device();
+    println!(
+        "Using device: {} (type: {:?})",
+        physical.name(),
+        physical.ty()
+    );
+}
+
+/// Explains what went wrong, and what might help, before exiting.
+fn exit_with_error(error: SimError) -> ! {
+    eprintln!("Error: {}", error);
+    if let Some(hint) = error.hint() {
+        eprintln!("{}", hint);
+    }
+    std::process::exit(1);
+}
+
+/// Called once the window loop has stopped, which ends the process after it.
+fn exit_on_error(result: Result<(), SimError>) {
+    if let Err(e) = result {
+        exit_with_error(e);
+    }
+}
+
+/// Runs the simulation without a window, and saves the final image.
+/// With a frame interval, numbered images are saved along the way instead.
+fn run_headless(options: &Options, steps: u32) -> Result<(), SimError> {
+    let (device, queue) = headless::init_device(&options.device)?;
+    print_device(&device);
+
+    let mut run_config = RunConfig {
+        steps,
+        every_step: false,
+        frame_interval: options.frame_interval,
+        output: options.output.clone(),
+    };
+
+    if let Some(size) = options.volume_size {
+        let mut volume = VolumeSimulation::init(device, queue, &volume_config(options, size))?;
+        // The timeline needs every step on its own.
+        run_config.every_step = options.timeline.is_some();
+
+        let mut parameters = VolumeParameters {
+            sim: options.preset.sim_parameters(),
+            fade: options.preset.fade_parameters(),
+            camera: OrbitCamera::default(),
+        };
+        let saved = headless::run(
+            &mut volume,
+            &mut parameters,
+            &run_config,
+            |parameters, step| {
+                apply_timeline(options, step, &mut parameters.sim, &mut parameters.fade)
+            },
+            |_, _| Ok(()),
+        )?;
+        print_run(steps, &saved);
+        return Ok(());
+    }
+
+    if let Some(parameters) = &options.reaction {
+        let mut reaction = ReactionDiffusion::init(device, queue, &reaction_config(options))?;
+        let saved = headless::run(
+            &mut reaction,
+            &mut parameters.clone(),
+            &run_config,
+            |_, _| {},
+            |_, _| Ok(()),
+        )?;
+        print_run(steps, &saved);
+        return Ok(());
+    }
+
+    let mut sim = Simulation::init(device, queue, &options.preset.config())?;
+    if let Some(path) = &options.attractant {
+        load_attractant(&sim, path)?;
+    }
+
+    let statistics = Statistics::new(&sim)?;
+    let mut statistics_csv = options
+        .statistics_csv
+        .as_ref()
+        .map(|path| {
+            CsvWriter::create(path)
+                .map_err(|e| SimError::File(format!("'{}': {}", path.display(), e)))
+        })
+        .transpose()?;
+
+    // Timelines and statistics need every step on its own,
+    // otherwise the steps can be submitted together.
+    run_config.every_step = options.timeline.is_some() || statistics_csv.is_some();
+
+    let mut parameters = ParameterSet {
+        sim: options.preset.sim_parameters(),
+        fade: options.preset.fade_parameters(),
+    };
+    let saved = headless::run(
+        &mut sim,
+        &mut parameters,
+        &run_config,
+        |parameters, step| apply_timeline(options, step, &mut parameters.sim, &mut parameters.fade),
+        |_, step| {
+            if let Some(csv) = &mut statistics_csv {
+                if let Err(e) = csv.write(&statistics.measure(step as u64)?) {
+                    eprintln!("Failed to write statistics: {}", e);
+                    statistics_csv = None;
+                }
+            }
+            Ok(())
+        },
+    )?;
+    print_run(steps, &saved);
+    Ok(())
+}
+
+/// Sets the parameters to the timeline at the time of the step, if there is a timeline.
+fn apply_timeline(
+    options: &Options,
+    step: u32,
+    sim_parameters: &mut AgentParameters,
+    fade_parameters: &mut FadeParameters,
+) {
+    if let Some(timeline) = &options.timeline {
+        let time = step as f32 * sim_parameters.delta_time;
+        timeline.apply(time, sim_parameters, fade_parameters);
+    }
+}
+
+fn print_run(steps: u32, saved: &[PathBuf]) {
+    for path in saved {
+        println!("Saved '{}'", path.display());
+    }
+    println!("Ran {} steps", steps);
+}
+
+/// The volume is viewed at the size of the window, or of the resolution without one.
+fn volume_config(options: &Options, size: u32) -> VolumeConfig {
+    let (view_width, view_height) = match options.headless_steps {
+        Some(_) => (options.preset.width, options.preset.height),
+        None => (options.window.width, options.window.height),
+    };
+    VolumeConfig {
+        size,
+        agent_amount: options.preset.agent_amount,
+        seed: options.preset.seed.unwrap_or(0),
+        view_width,
+        view_height,
+    }
+}
+
+fn load_attractant(sim: &Simulation, path: &Path) -> Result<(), SimError> {
+    let image = image::open(path)
+        .map_err(|e| SimError::File(format!("attractant '{}': {}", path.display(), e)))?;
+    sim.set_attractant(Some(&image))
+}
+
+/// The reaction-diffusion covers the resolution of the preset, and starts from its seed.
+fn reaction_config(options: &Options) -> ReactionConfig {
+    ReactionConfig {
+        width: options.preset.width,
+        height: options.preset.height,
+        seed: options.preset.seed.unwrap_or(0),
+    }
 }
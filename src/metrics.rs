@@ -0,0 +1,78 @@
+use image::RgbaImage;
+
+/// Brightness above which a pixel counts as covered by trails.
+const COVERAGE_THRESHOLD: f32 = 0.05;
+/// Brightness difference between neighbours above which a pixel counts as an edge.
+const EDGE_THRESHOLD: f32 = 0.1;
+const HISTOGRAM_BINS: usize = 32;
+
+/// Simple measures of how a trail image looks, each in the range [0 - 1].
+#[derive(Clone, Copy, Debug, Default)]
+pub struct ImageMetrics {
+    /// Fraction of the pixels that have trails on them.
+    pub coverage: f32,
+    /// Fraction of the pixels that lie on the edge of a trail.
+    pub edge_density: f32,
+    /// Shannon entropy of the brightness histogram, normalized.
+    pub entropy: f32,
+}
+
+impl ImageMetrics {
+    pub fn measure(image: &RgbaImage) -> ImageMetrics {
+        let width = image.width() as usize;
+        let height = image.height() as usize;
+        let pixel_count = (width * height).max(1) as f32;
+
+        let brightness: Vec<f32> = image
+            .pixels()
+            .map(|p| (p[0] as f32 + p[1] as f32 + p[2] as f32) / (3.0 * 255.0))
+            .collect();
+
+        let mut covered = 0;
+        let mut edges = 0;
+        let mut histogram = [0u32; HISTOGRAM_BINS];
+
+        for y in 0..height {
+            for x in 0..width {
+                let value = brightness[y * width + x];
+
+                if value > COVERAGE_THRESHOLD {
+                    covered += 1;
+                }
+
+                let bin = ((value * HISTOGRAM_BINS as f32) as usize).min(HISTOGRAM_BINS - 1);
+                histogram[bin] += 1;
+
+                if x + 1 < width && y + 1 < height {
+                    let dx = (brightness[y * width + x + 1] - value).abs();
+                    let dy = (brightness[(y + 1) * width + x] - value).abs();
+                    if dx + dy > EDGE_THRESHOLD {
+                        edges += 1;
+                    }
+                }
+            }
+        }
+
+        let entropy: f32 = histogram
+            .iter()
+            .filter(|&&count| count > 0)
+            .map(|&count| {
+                let p = count as f32 / pixel_count;
+                -p * p.log2()
+            })
+            .sum();
+
+        ImageMetrics {
+            coverage: covered as f32 / pixel_count,
+            edge_density: edges as f32 / pixel_count,
+            entropy: entropy / (HISTOGRAM_BINS as f32).log2(),
+        }
+    }
+
+    /// Higher is more interesting: lots of detail, varied brightness,
+    /// and neither an empty nor a completely filled image.
+    pub fn score(&self) -> f32 {
+        let balanced_coverage = 4.0 * self.coverage * (1.0 - self.coverage);
+        self.entropy + 2.0 * self.edge_density + balanced_coverage
+    }
+}
@@ -0,0 +1,151 @@
+use crate::error::SimError;
+use crate::profiler::PassTimings;
+use crate::screenshot;
+use image::RgbaImage;
+use std::collections::HashMap;
+use std::error::Error;
+use std::path::Path;
+use std::sync::Arc;
+use vulkano::device::{Device, Queue};
+use vulkano::format::Format;
+use vulkano::image::StorageImage;
+use vulkano::sync;
+use vulkano::sync::GpuFuture;
+
+/// How many steps `run_steps` submits before waiting for them to finish.
+pub const STEPS_PER_SUBMISSION: u32 = 16;
+
+/// A simulation that runs on the gpu and shows its state as an image in
+/// `device::DISPLAY_FORMAT`. The window, screenshots and headless runs work with any of them.
+pub trait Model {
+    /// What is fixed once the model has been created, like its resolution and seed.
+    type Config;
+    /// Everything that can be changed while it runs.
+    type Parameters: Clone;
+
+    /// Runs the model on `queue`, while the `result_image` is displayed from `display_queue`.
+    /// Without a window, both can be the same queue.
+    fn init(
+        device: Arc<Device>,
+        queue: Arc<Queue>,
+        display_queue: &Arc<Queue>,
+        config: &Self::Config,
+    ) -> Result<Self, SimError>
+    where
+        Self: Sized;
+
+    fn device(&self) -> &Arc<Device>;
+
+    /// The queue the steps run on.
+    fn queue(&self) -> &Arc<Queue>;
+
+    /// Width and height of the `result_image`.
+    fn size(&self) -> [u32; 2];
+
+    /// Submits the given amount of steps after `future`, and updates the result image after
+    /// the last one. Nothing waits for them to finish.
+    fn steps_after<F>(
+        &mut self,
+        future: F,
+        steps: u32,
+        parameters: &Self::Parameters,
+    ) -> Result<Box<dyn GpuFuture>, SimError>
+    where
+        F: GpuFuture + 'static;
+
+    /// Runs the given amount of steps and waits for them to finish.
+    fn run_steps(&mut self, steps: u32, parameters: &Self::Parameters) -> Result<(), SimError> {
+        let mut remaining = steps;
+        while remaining > 0 {
+            let batch = remaining.min(STEPS_PER_SUBMISSION);
+            remaining -= batch;
+
+            let now = sync::now(self.device().clone());
+            self.steps_after(now, batch, parameters)?
+                .then_signal_fence_and_flush()?
+                .wait(None)?;
+        }
+
+        Ok(())
+    }
+
+    /// Like `run_steps`, while timing each pass of the steps on its own.
+    /// Models that have no passes worth timing only run the steps.
+    fn run_steps_profiled(
+        &mut self,
+        steps: u32,
+        parameters: &Self::Parameters,
+    ) -> Result<Option<PassTimings>, SimError> {
+        self.run_steps(steps, parameters)?;
+        Ok(None)
+    }
+
+    /// The latest state, ready to be displayed or saved.
+    fn result_image(&self) -> &Arc<StorageImage<Format>>;
+
+    /// Copies the result image back to the cpu.
+    /// The gpu should not be running steps when this is called.
+    fn read_result_image(&self) -> Result<RgbaImage, SimError> {
+        screenshot::read_image(
+            self.device(),
+            self.queue(),
+            self.result_image(),
+            self.size(),
+        )
+    }
+
+    /// Starts over from the state given by the seed.
+    /// The gpu should not be running steps when this is called.
+    fn reset(&mut self, seed: u64) -> Result<(), SimError>;
+
+    /// Starts over at a new resolution. This replaces the `result_image`.
+    /// The gpu should not be running steps when this is called.
+    fn resize(&mut self, width: u32, height: u32) -> Result<(), SimError>;
+
+    /// The png text chunks that describe how to get back to this state, as keyword and text.
+    fn screenshot_texts(
+        &self,
+        parameters: &Self::Parameters,
+        step: u64,
+    ) -> Result<Vec<(&'static str, String)>, SimError>;
+
+    /// The result image, with the texts to get back to it.
+    /// The gpu should not be running steps when this is called.
+    fn snapshot(&self, parameters: &Self::Parameters, step: u64) -> Result<Snapshot, SimError> {
+        Ok(Snapshot {
+            image: self.read_result_image()?,
+            texts: self.screenshot_texts(parameters, step)?,
+        })
+    }
+
+    /// Applies the texts of a snapshot, and starts over from its seed.
+    /// What can only be set when the model is created stays as it is.
+    /// Returns what happened, for the user.
+    fn restore(
+        &mut self,
+        texts: &HashMap<String, String>,
+        parameters: &mut Self::Parameters,
+    ) -> Result<String, Box<dyn Error>>;
+}
+
+/// An image of a model, with the text chunks that describe how to get back to it.
+pub struct Snapshot {
+    pub image: RgbaImage,
+    /// As keyword and text.
+    pub texts: Vec<(&'static str, String)>,
+}
+
+impl Snapshot {
+    /// Png images get the texts embedded, other formats only get the image.
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), Box<dyn Error>> {
+        let is_png = path
+            .as_ref()
+            .extension()
+            .map_or(false, |e| e.eq_ignore_ascii_case("png"));
+        if is_png {
+            screenshot::save_with_texts(path, &self.image, &self.texts)
+        } else {
+            Ok(self.image.save(path)?)
+        }
+    }
+}
@@ -0,0 +1,291 @@
+use crate::error::SimError;
+use crate::simulation::agent_shader;
+use std::sync::Arc;
+use vulkano::buffer::{BufferUsage, CpuAccessibleBuffer, DeviceLocalBuffer};
+use vulkano::command_buffer::{AutoCommandBuffer, AutoCommandBufferBuilder};
+use vulkano::descriptor::descriptor_set::{DescriptorSet, PersistentDescriptorSet};
+use vulkano::descriptor::PipelineLayoutAbstract;
+use vulkano::device::{Device, Queue};
+use vulkano::pipeline::ComputePipeline;
+
+/// The width and height of a grid cell in pixels. Has to match CELL_SIZE in the `agent_shader`.
+/// Neighbours are only looked for in the 3 by 3 cells around an agent, so this is also the
+/// largest `flock_radius`.
+pub const CELL_SIZE: u32 = 32;
+
+/// Sorts the agents into a grid of cells, so an agent can find its neighbours by looking
+/// through the cells around it instead of through all agents.
+///
+/// The grid is rebuilt from the agent positions with a counting sort: count the agents per cell,
+/// sum the counts up to where each cell starts, and then write the agent indices in cell order.
+pub struct NeighbourGrid {
+    /// The first index into `sorted_buffer` and the agent count of every cell, alternating.
+    /// Cells are stored row by row.
+    pub cells_buffer: Arc<DeviceLocalBuffer<[u32]>>,
+    /// The agent indices, sorted by cell.
+    pub sorted_buffer: Arc<DeviceLocalBuffer<[u32]>>,
+    pub columns: u32,
+    pub rows: u32,
+    /// Rebuilds the grid. Has to run after the agents moved, before they look for neighbours.
+    pub command: Arc<AutoCommandBuffer>,
+}
+
+impl NeighbourGrid {
+    /// A grid over `width` by `height` pixels, for the agents in `agents_buffer`.
+    pub fn new(
+        device: &Arc<Device>,
+        queue: &Arc<Queue>,
+        agents_buffer: &Arc<CpuAccessibleBuffer<[agent_shader::ty::Agent]>>,
+        width: u32,
+        height: u32,
+    ) -> Result<NeighbourGrid, SimError> {
+        let columns = (width + CELL_SIZE - 1) / CELL_SIZE;
+        let rows = (height + CELL_SIZE - 1) / CELL_SIZE;
+        let agent_amount = agents_buffer.len() as u32;
+
+        let usage = BufferUsage {
+            storage_buffer: true,
+            transfer_destination: true,
+            ..BufferUsage::none()
+        };
+        let cells_buffer = DeviceLocalBuffer::array(
+            device.clone(),
+            (columns * rows * 2) as usize,
+            usage,
+            Some(queue.family()),
+        )?;
+        let sorted_buffer = DeviceLocalBuffer::array(
+            device.clone(),
+            agent_amount.max(1) as usize,
+            usage,
+            Some(queue.family()),
+        )?;
+
+        let count_shader = grid_count_shader::Shader::load(device.clone())?;
+        let count_pipeline = Arc::new(ComputePipeline::new(
+            device.clone(),
+            &count_shader.main_entry_point(),
+            &(),
+            None,
+        )?);
+        let scan_shader = grid_scan_shader::Shader::load(device.clone())?;
+        let scan_pipeline = Arc::new(ComputePipeline::new(
+            device.clone(),
+            &scan_shader.main_entry_point(),
+            &(),
+            None,
+        )?);
+        let scatter_shader = grid_scatter_shader::Shader::load(device.clone())?;
+        let scatter_pipeline = Arc::new(ComputePipeline::new(
+            device.clone(),
+            &scatter_shader.main_entry_point(),
+            &(),
+            None,
+        )?);
+
+        let count_set: Arc<dyn DescriptorSet + Send + Sync> = Arc::new(
+            PersistentDescriptorSet::start(
+                count_pipeline.descriptor_set_layout(0).unwrap().clone(),
+            )
+            .add_buffer(agents_buffer.clone())?
+            .add_buffer(cells_buffer.clone())?
+            .build()?,
+        );
+        let scan_set: Arc<dyn DescriptorSet + Send + Sync> = Arc::new(
+            PersistentDescriptorSet::start(scan_pipeline.descriptor_set_layout(0).unwrap().clone())
+                .add_buffer(cells_buffer.clone())?
+                .build()?,
+        );
+        let scatter_set: Arc<dyn DescriptorSet + Send + Sync> = Arc::new(
+            PersistentDescriptorSet::start(
+                scatter_pipeline.descriptor_set_layout(0).unwrap().clone(),
+            )
+            .add_buffer(agents_buffer.clone())?
+            .add_buffer(cells_buffer.clone())?
+            .add_buffer(sorted_buffer.clone())?
+            .build()?,
+        );
+
+        let count_constants = grid_count_shader::ty::Grid {
+            columns: columns as i32,
+            rows: rows as i32,
+        };
+        let scatter_constants = grid_scatter_shader::ty::Grid {
+            columns: columns as i32,
+            rows: rows as i32,
+        };
+        let agent_groups = [(agent_amount + 63) / 64, 1, 1];
+
+        let mut builder =
+            AutoCommandBufferBuilder::primary_simultaneous_use(device.clone(), queue.family())?;
+        builder
+            .fill_buffer(cells_buffer.clone(), 0)?
+            .dispatch(agent_groups, count_pipeline, count_set, count_constants)?
+            // The scan runs in a single work group, which walks over all cells.
+            .dispatch([1, 1, 1], scan_pipeline, scan_set, ())?
+            .dispatch(
+                agent_groups,
+                scatter_pipeline,
+                scatter_set,
+                scatter_constants,
+            )?;
+        let command = Arc::new(builder.build()?);
+
+        Ok(NeighbourGrid {
+            cells_buffer,
+            sorted_buffer,
+            columns,
+            rows,
+            command,
+        })
+    }
+}
+
+mod grid_count_shader {
+    vulkano_shaders::shader! {
+        ty: "compute",
+        src:
+"
+#version 450
+
+// Has to match CELL_SIZE in neighbours.rs.
+const float CELL_SIZE = 32.0;
+
+// Has to match Agent in the agent_shader.
+struct Agent {
+    vec2 pos;
+    float angle;
+    uint carrying;
+};
+
+layout(local_size_x = 64, local_size_y = 1, local_size_z = 1) in;
+
+layout(set = 0, binding = 0) readonly buffer Agents {
+    Agent data[];
+} agents;
+layout(set = 0, binding = 1) buffer Cells {
+    uint data[];
+} cells;
+
+layout(push_constant) uniform Grid {
+    int columns;
+    int rows;
+} grid;
+
+void main() {
+    uint id = gl_GlobalInvocationID.x;
+    if (id >= agents.data.length()) {
+        return;
+    }
+
+    // Agents can sit right on the right and bottom edges.
+    ivec2 cell = clamp(ivec2(agents.data[id].pos / CELL_SIZE), ivec2(0), ivec2(grid.columns - 1, grid.rows - 1));
+    uint index = uint(cell.y * grid.columns + cell.x);
+    atomicAdd(cells.data[index * 2 + 1], 1u);
+}
+"
+    }
+}
+
+mod grid_scan_shader {
+    vulkano_shaders::shader! {
+        ty: "compute",
+        src:
+"
+#version 450
+
+// Every device supports work groups of at least 128 invocations.
+const uint THREADS = 128;
+
+layout(local_size_x = 128, local_size_y = 1, local_size_z = 1) in;
+
+layout(set = 0, binding = 0) buffer Cells {
+    uint data[];
+} cells;
+
+// The agent count of each thread's cells, summed up to and including the thread.
+shared uint sums[THREADS];
+
+void main() {
+    uint thread = gl_LocalInvocationID.x;
+    uint cell_count = cells.data.length() / 2;
+    uint per_thread = (cell_count + THREADS - 1) / THREADS;
+    uint first = min(thread * per_thread, cell_count);
+    uint last = min(first + per_thread, cell_count);
+
+    uint sum = 0;
+    for (uint cell = first; cell < last; cell++) {
+        sum += cells.data[cell * 2 + 1];
+    }
+    sums[thread] = sum;
+    barrier();
+
+    for (uint offset = 1; offset < THREADS; offset *= 2) {
+        uint add = thread >= offset ? sums[thread - offset] : 0;
+        barrier();
+        sums[thread] += add;
+        barrier();
+    }
+
+    // Set where every cell starts, and clear its count so the scatter can count it again.
+    uint start = sums[thread] - sum;
+    for (uint cell = first; cell < last; cell++) {
+        uint count = cells.data[cell * 2 + 1];
+        cells.data[cell * 2] = start;
+        cells.data[cell * 2 + 1] = 0;
+        start += count;
+    }
+}
+"
+    }
+}
+
+mod grid_scatter_shader {
+    vulkano_shaders::shader! {
+        ty: "compute",
+        src:
+"
+#version 450
+
+// Has to match CELL_SIZE in neighbours.rs.
+const float CELL_SIZE = 32.0;
+
+// Has to match Agent in the agent_shader.
+struct Agent {
+    vec2 pos;
+    float angle;
+    uint carrying;
+};
+
+layout(local_size_x = 64, local_size_y = 1, local_size_z = 1) in;
+
+layout(set = 0, binding = 0) readonly buffer Agents {
+    Agent data[];
+} agents;
+layout(set = 0, binding = 1) buffer Cells {
+    uint data[];
+} cells;
+layout(set = 0, binding = 2) writeonly buffer SortedAgents {
+    uint data[];
+} sorted;
+
+layout(push_constant) uniform Grid {
+    int columns;
+    int rows;
+} grid;
+
+void main() {
+    uint id = gl_GlobalInvocationID.x;
+    if (id >= agents.data.length()) {
+        return;
+    }
+
+    ivec2 cell = clamp(ivec2(agents.data[id].pos / CELL_SIZE), ivec2(0), ivec2(grid.columns - 1, grid.rows - 1));
+    uint index = uint(cell.y * grid.columns + cell.x);
+    // Counting again gives every agent its own place in the cell.
+    uint place = atomicAdd(cells.data[index * 2 + 1], 1u);
+    sorted.data[cells.data[index * 2] + place] = id;
+}
+"
+    }
+}
@@ -0,0 +1,799 @@
+use crate::neighbours;
+use crate::simulation;
+use crate::simulation::{AgentParameters, FadeParameters};
+#[cfg(feature = "ui")]
+use imgui::{im_str, ColorEdit, Condition, ImString, Slider, Ui, Window};
+use serde::de::Error as _;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use serde_json::Map;
+use std::convert::TryFrom;
+use std::f32::consts::PI;
+use std::fmt;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Kind {
+    Float,
+    Int,
+    /// Red, green and blue, each in the range of the parameter.
+    Color,
+    /// Switches a part of the model on or off. The range does not apply.
+    Bool,
+}
+
+/// The value of a single parameter.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Value {
+    Float(f32),
+    Int(i32),
+    Color([f32; 3]),
+    Bool(bool),
+}
+
+/// Describes a parameter of `T` that can be changed while the simulation runs.
+/// The ui, the command line, presets and remote control are all built from tables of these,
+/// like `PARAMETERS` for the slime, `post::PARAMETERS`, `reaction::PARAMETERS` and
+/// `colony::PARAMETERS`. Keys and flags are unique over all tables.
+pub struct Parameter<T = ParameterSet> {
+    /// Name in preset files and remote control requests.
+    pub key: &'static str,
+    /// Name of the command line option, without the dashes.
+    pub flag: &'static str,
+    /// Name in the ui.
+    pub label: &'static str,
+    /// Empty for parameters without a unit.
+    pub units: &'static str,
+    pub tooltip: &'static str,
+    /// Heading the parameter is shown under in the ui. A group that starts with a `Bool`
+    /// parameter is headed by it instead, and the rest of the group is only shown while it is on.
+    pub group: &'static str,
+    pub kind: Kind,
+    /// The smallest and largest allowed value. Values outside of it are clamped.
+    pub range: (f32, f32),
+    /// The value a new simulation starts with, and that presets fall back to.
+    pub default: Value,
+    get: fn(&T) -> Value,
+    set: fn(&mut T, Value),
+}
+
+/// All parameters of the slime, in the order they are shown in.
+pub static PARAMETERS: [Parameter; 14] = [
+    Parameter {
+        key: "agent_speed",
+        flag: "speed",
+        label: "Speed",
+        units: "px/s",
+        tooltip: "How far the agents move per second.",
+        group: "Agents",
+        kind: Kind::Float,
+        range: (0.0, 1000.0),
+        default: Value::Float(100.0),
+        get: |set| Value::Float(set.sim.agent_speed),
+        set: |set, value| set.sim.agent_speed = value.as_float(),
+    },
+    Parameter {
+        key: "agent_turn_speed",
+        flag: "turn-speed",
+        label: "Turn speed",
+        units: "rad/s",
+        tooltip: "How fast the agents turn towards the strongest trail.",
+        group: "Agents",
+        kind: Kind::Float,
+        range: (0.0, 200.0),
+        default: Value::Float(50.0),
+        get: |set| Value::Float(set.sim.agent_turn_speed),
+        set: |set, value| set.sim.agent_turn_speed = value.as_float(),
+    },
+    Parameter {
+        key: "sensor_radius",
+        flag: "sensor-radius",
+        label: "Sensor radius",
+        units: "px",
+        tooltip: "Radius of the square each sensor samples the trails in.",
+        group: "Agents",
+        kind: Kind::Int,
+        range: (0.0, 8.0),
+        default: Value::Int(1),
+        get: |set| Value::Int(set.sim.sensor_radius),
+        set: |set, value| set.sim.sensor_radius = value.as_int(),
+    },
+    Parameter {
+        key: "sensor_angle_spacing",
+        flag: "sensor-angle",
+        label: "Sensor angles",
+        units: "rad",
+        tooltip: "Angle between the forward sensor and the side sensors.",
+        group: "Agents",
+        kind: Kind::Float,
+        range: (0.0, PI),
+        default: Value::Float(0.18),
+        get: |set| Value::Float(set.sim.sensor_angle_spacing),
+        set: |set, value| set.sim.sensor_angle_spacing = value.as_float(),
+    },
+    Parameter {
+        key: "deposit_amount",
+        flag: "deposit-amount",
+        label: "Deposit amount",
+        units: "",
+        tooltip: "How much of the trail color each agent adds per step. Overlapping agents add up.",
+        group: "Agents",
+        kind: Kind::Float,
+        range: (0.0, 4.0),
+        default: Value::Float(0.5),
+        get: |set| Value::Float(set.sim.deposit_amount),
+        set: |set, value| set.sim.deposit_amount = value.as_float(),
+    },
+    Parameter {
+        key: "attractant_weight",
+        flag: "attractant-weight",
+        label: "Attractant weight",
+        units: "",
+        tooltip: "How strongly the attractant image counts, compared to the trails.",
+        group: "Agents",
+        kind: Kind::Float,
+        range: (0.0, 5.0),
+        default: Value::Float(1.0),
+        get: |set| Value::Float(set.sim.attractant_weight),
+        set: |set, value| set.sim.attractant_weight = value.as_float(),
+    },
+    Parameter {
+        key: "trail_weight",
+        flag: "trail-weight",
+        label: "Trail weight",
+        units: "",
+        tooltip: "How strongly the agents follow the trails, compared to flocking.",
+        group: "Agents",
+        kind: Kind::Float,
+        range: (0.0, 2.0),
+        default: Value::Float(1.0),
+        get: |set| Value::Float(set.sim.trail_weight),
+        set: |set, value| set.sim.trail_weight = value.as_float(),
+    },
+    Parameter {
+        key: "separation_weight",
+        flag: "separation",
+        label: "Separation",
+        units: "",
+        tooltip: "How strongly the agents steer away from close neighbours.",
+        group: "Agents",
+        kind: Kind::Float,
+        range: (0.0, 5.0),
+        default: Value::Float(0.0),
+        get: |set| Value::Float(set.sim.separation_weight),
+        set: |set, value| set.sim.separation_weight = value.as_float(),
+    },
+    Parameter {
+        key: "alignment_weight",
+        flag: "alignment",
+        label: "Alignment",
+        units: "",
+        tooltip: "How strongly the agents steer along with the heading of their neighbours.",
+        group: "Agents",
+        kind: Kind::Float,
+        range: (0.0, 5.0),
+        default: Value::Float(0.0),
+        get: |set| Value::Float(set.sim.alignment_weight),
+        set: |set, value| set.sim.alignment_weight = value.as_float(),
+    },
+    Parameter {
+        key: "cohesion_weight",
+        flag: "cohesion",
+        label: "Cohesion",
+        units: "",
+        tooltip: "How strongly the agents steer towards the centre of their neighbours.",
+        group: "Agents",
+        kind: Kind::Float,
+        range: (0.0, 5.0),
+        default: Value::Float(0.0),
+        get: |set| Value::Float(set.sim.cohesion_weight),
+        set: |set, value| set.sim.cohesion_weight = value.as_float(),
+    },
+    Parameter {
+        key: "flock_radius",
+        flag: "flock-radius",
+        label: "Flock radius",
+        units: "px",
+        tooltip: "How far away other agents count as neighbours for flocking.",
+        group: "Agents",
+        kind: Kind::Float,
+        range: (1.0, neighbours::CELL_SIZE as f32),
+        default: Value::Float(20.0),
+        get: |set| Value::Float(set.sim.flock_radius),
+        set: |set, value| set.sim.flock_radius = value.as_float(),
+    },
+    Parameter {
+        key: "trail_color",
+        flag: "trail-color",
+        label: "Trail color",
+        units: "",
+        tooltip: "Color the agents leave behind.",
+        group: "Agents",
+        kind: Kind::Color,
+        range: (0.0, 1.0),
+        default: Value::Color([1.0, 0.0, 0.5]),
+        get: |set| {
+            let [red, green, blue, _] = set.sim.trail_color;
+            Value::Color([red, green, blue])
+        },
+        set: |set, value| {
+            let [red, green, blue] = value.as_color();
+            set.sim.trail_color = [red, green, blue, 1.0];
+        },
+    },
+    Parameter {
+        key: "evaporate_speed",
+        flag: "evaporate-speed",
+        label: "Fade speed",
+        units: "/s",
+        tooltip: "How fast the trails fade.",
+        group: "Trails",
+        kind: Kind::Float,
+        range: (0.0, 10.0),
+        default: Value::Float(0.9),
+        get: |set| Value::Float(set.fade.evaporate_speed),
+        set: |set, value| set.fade.evaporate_speed = value.as_float(),
+    },
+    Parameter {
+        key: "blur_radius",
+        flag: "blur-radius",
+        label: "Blur radius",
+        units: "px",
+        tooltip: "Radius of the trail blur. Zero only fades the trails.",
+        group: "Trails",
+        kind: Kind::Int,
+        range: (0.0, 8.0),
+        default: Value::Int(1),
+        get: |set| Value::Int(set.fade.blur_radius),
+        set: |set, value| set.fade.blur_radius = value.as_int(),
+    },
+];
+
+/// The parameter with the given preset key.
+pub fn find(key: &str) -> Option<&'static Parameter> {
+    find_in(&PARAMETERS, key)
+}
+
+/// The parameter of the table with the given preset key.
+pub fn find_in<T>(table: &'static [Parameter<T>], key: &str) -> Option<&'static Parameter<T>> {
+    table.iter().find(|parameter| parameter.key == key)
+}
+
+/// Clamps every parameter to its range.
+pub fn clamp_all(sim_parameters: &mut AgentParameters, fade_parameters: &mut FadeParameters) {
+    for parameter in PARAMETERS.iter() {
+        let value = parameter.get(sim_parameters, fade_parameters);
+        parameter.set(sim_parameters, fade_parameters, value);
+    }
+}
+
+/// Sets every parameter of the table to its default.
+pub fn set_defaults<T>(table: &[Parameter<T>], target: &mut T) {
+    for parameter in table {
+        parameter.set_value(target, parameter.default);
+    }
+}
+
+/// The values of the table as a json map, one entry per key.
+pub fn to_json_map<T>(table: &[Parameter<T>], target: &T) -> Map<String, serde_json::Value> {
+    table
+        .iter()
+        .map(|parameter| {
+            let value = parameter.to_json(parameter.value(target));
+            (parameter.key.to_owned(), value)
+        })
+        .collect()
+}
+
+/// Sets the values in the json map, clamped to their range. Keys of other tables are ignored,
+/// so that a map can hold the values of several tables, and missing keys are left alone.
+pub fn apply_json_map<T>(
+    table: &[Parameter<T>],
+    target: &mut T,
+    map: &Map<String, serde_json::Value>,
+) -> Result<(), String> {
+    for parameter in table {
+        if let Some(json) = map.get(parameter.key) {
+            let value = parameter
+                .from_json(json)
+                .map_err(|e| format!("invalid '{}': {}", parameter.key, e))?;
+            parameter.set_value(target, value);
+        }
+    }
+    Ok(())
+}
+
+/// Serializes the values of a table, for types whose `Serialize` is the registry.
+pub fn serialize<T, S: Serializer>(
+    table: &[Parameter<T>],
+    target: &T,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    to_json_map(table, target).serialize(serializer)
+}
+
+/// Deserializes the values of a table on top of `T::default()`, for types whose `Deserialize`
+/// is the registry. Unknown keys are ignored, like the other preset fields.
+pub fn deserialize<'de, T: Default, D: Deserializer<'de>>(
+    table: &[Parameter<T>],
+    deserializer: D,
+) -> Result<T, D::Error> {
+    let map = Map::<String, serde_json::Value>::deserialize(deserializer)?;
+    let mut target = T::default();
+    apply_json_map(table, &mut target, &map).map_err(D::Error::custom)?;
+    Ok(target)
+}
+
+impl Value {
+    pub fn as_float(self) -> f32 {
+        match self {
+            Value::Float(value) => value,
+            Value::Int(value) => value as f32,
+            Value::Color([red, ..]) => red,
+            Value::Bool(on) => on as u8 as f32,
+        }
+    }
+
+    pub fn as_int(self) -> i32 {
+        self.as_float().round() as i32
+    }
+
+    pub fn as_color(self) -> [f32; 3] {
+        match self {
+            Value::Color(color) => color,
+            other => [other.as_float(); 3],
+        }
+    }
+
+    pub fn as_bool(self) -> bool {
+        match self {
+            Value::Bool(on) => on,
+            other => other.as_float() != 0.0,
+        }
+    }
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Value::Float(value) => write!(f, "{}", value),
+            Value::Int(value) => write!(f, "{}", value),
+            Value::Color([red, green, blue]) => write!(f, "{},{},{}", red, green, blue),
+            Value::Bool(on) => write!(f, "{}", on),
+        }
+    }
+}
+
+impl Parameter {
+    pub fn get(&self, sim_parameters: &AgentParameters, fade_parameters: &FadeParameters) -> Value {
+        self.value(&ParameterSet {
+            sim: *sim_parameters,
+            fade: *fade_parameters,
+        })
+    }
+
+    /// Sets the value, clamped to the range of the parameter.
+    pub fn set(
+        &self,
+        sim_parameters: &mut AgentParameters,
+        fade_parameters: &mut FadeParameters,
+        value: Value,
+    ) {
+        let mut set = ParameterSet {
+            sim: *sim_parameters,
+            fade: *fade_parameters,
+        };
+        self.set_value(&mut set, value);
+        *sim_parameters = set.sim;
+        *fade_parameters = set.fade;
+    }
+}
+
+impl<T> Parameter<T> {
+    pub fn value(&self, target: &T) -> Value {
+        (self.get)(target)
+    }
+
+    /// Sets the value, clamped to the range of the parameter.
+    pub fn set_value(&self, target: &mut T, value: Value) {
+        (self.set)(target, self.clamp(value));
+    }
+
+    /// Converts the value to the kind of the parameter, and clamps it to its range.
+    pub fn clamp(&self, value: Value) -> Value {
+        let (min, max) = self.range;
+        match self.kind {
+            Kind::Float => Value::Float(value.as_float().max(min).min(max)),
+            Kind::Int => Value::Int(value.as_int().max(min as i32).min(max as i32)),
+            Kind::Color => {
+                let [red, green, blue] = value.as_color();
+                Value::Color([
+                    red.max(min).min(max),
+                    green.max(min).min(max),
+                    blue.max(min).min(max),
+                ])
+            }
+            Kind::Bool => Value::Bool(value.as_bool()),
+        }
+    }
+
+    /// Parses a value as written on the command line, like `1.5`, `3`, `1,0,0.5` or `true`.
+    pub fn parse(&self, text: &str) -> Result<Value, String> {
+        let invalid = || format!("'{}' is not {}", text, self.expected());
+        match self.kind {
+            Kind::Float => text.trim().parse().map(Value::Float).map_err(|_| invalid()),
+            Kind::Int => text.trim().parse().map(Value::Int).map_err(|_| invalid()),
+            Kind::Color => {
+                let channels: Result<Vec<f32>, _> =
+                    text.split(',').map(|c| c.trim().parse()).collect();
+                match channels.as_deref() {
+                    Ok(&[red, green, blue]) => Ok(Value::Color([red, green, blue])),
+                    _ => Err(invalid()),
+                }
+            }
+            Kind::Bool => text.trim().parse().map(Value::Bool).map_err(|_| invalid()),
+        }
+    }
+
+    /// Reads a value from json, as found in a preset or a remote control request.
+    pub fn from_json(&self, json: &serde_json::Value) -> Result<Value, String> {
+        let invalid = || format!("{} is not {}", json, self.expected());
+        match self.kind {
+            Kind::Float => json
+                .as_f64()
+                .map(|value| Value::Float(value as f32))
+                .ok_or_else(invalid),
+            // Whole numbers that do not fit are rejected, rather than wrapped around.
+            Kind::Int => json
+                .as_i64()
+                .and_then(|value| i32::try_from(value).ok())
+                .map(Value::Int)
+                .ok_or_else(invalid),
+            Kind::Color => match serde_json::from_value::<[f32; 3]>(json.clone()) {
+                Ok(color) => Ok(Value::Color(color)),
+                Err(_) => Err(invalid()),
+            },
+            Kind::Bool => json.as_bool().map(Value::Bool).ok_or_else(invalid),
+        }
+    }
+
+    pub fn to_json(&self, value: Value) -> serde_json::Value {
+        match value {
+            Value::Float(value) => serde_json::json!(value),
+            Value::Int(value) => serde_json::json!(value),
+            Value::Color(color) => serde_json::json!(color),
+            Value::Bool(on) => serde_json::json!(on),
+        }
+    }
+
+    /// The label with the units, like `Speed (px/s)`.
+    pub fn label_with_units(&self) -> String {
+        if self.units.is_empty() {
+            self.label.to_owned()
+        } else {
+            format!("{} ({})", self.label, self.units)
+        }
+    }
+
+    fn expected(&self) -> &'static str {
+        match self.kind {
+            Kind::Float => "a number",
+            Kind::Int => "a whole number",
+            Kind::Color => "a color like [1, 0, 0.5]",
+            Kind::Bool => "true or false",
+        }
+    }
+}
+
+/// The values of all parameters, as stored in presets: one field per parameter key.
+/// Missing keys get their default value, and values outside of their range are clamped.
+#[derive(Clone, Copy)]
+pub struct ParameterSet {
+    pub sim: AgentParameters,
+    pub fade: FadeParameters,
+}
+
+impl Default for ParameterSet {
+    /// Every parameter at its `default`, stepping at 60 steps per second.
+    fn default() -> Self {
+        // The values of the registry are filled in below.
+        let mut set = ParameterSet {
+            sim: AgentParameters {
+                trail_color: [0.0, 0.0, 0.0, 1.0],
+                agent_speed: 0.0,
+                agent_turn_speed: 0.0,
+                sensor_radius: 0,
+                sensor_angle_spacing: 0.0,
+                delta_time: simulation::DEFAULT_DELTA_TIME,
+                deposit_amount: 0.0,
+                attractant_weight: 0.0,
+                trail_weight: 0.0,
+                separation_weight: 0.0,
+                alignment_weight: 0.0,
+                cohesion_weight: 0.0,
+                flock_radius: 0.0,
+            },
+            fade: FadeParameters {
+                delta_time: simulation::DEFAULT_DELTA_TIME,
+                evaporate_speed: 0.0,
+                blur_radius: 0,
+            },
+        };
+        set_defaults(&PARAMETERS, &mut set);
+        set
+    }
+}
+
+impl ParameterSet {
+    /// Takes the parameter values, leaving anything else (like the time step) at its default.
+    pub fn from_parameters(
+        sim_parameters: &AgentParameters,
+        fade_parameters: &FadeParameters,
+    ) -> ParameterSet {
+        let mut set = ParameterSet::default();
+        for parameter in PARAMETERS.iter() {
+            let value = parameter.get(sim_parameters, fade_parameters);
+            set.set(parameter, value);
+        }
+        set
+    }
+
+    pub fn get(&self, parameter: &Parameter) -> Value {
+        parameter.value(self)
+    }
+
+    pub fn set(&mut self, parameter: &Parameter, value: Value) {
+        parameter.set_value(self, value);
+    }
+
+    /// Sets the parameter values, leaving anything else (like the time step) alone.
+    pub fn apply_to(
+        &self,
+        sim_parameters: &mut AgentParameters,
+        fade_parameters: &mut FadeParameters,
+    ) {
+        for parameter in PARAMETERS.iter() {
+            parameter.set(sim_parameters, fade_parameters, self.get(parameter));
+        }
+    }
+
+    /// The parameters whose values differ between the two sets.
+    pub fn changes(&self, other: &ParameterSet) -> Vec<&'static Parameter> {
+        PARAMETERS
+            .iter()
+            .filter(|parameter| self.get(parameter) != other.get(parameter))
+            .collect()
+    }
+}
+
+/// Only compares the parameter values.
+impl PartialEq for ParameterSet {
+    fn eq(&self, other: &ParameterSet) -> bool {
+        self.changes(other).is_empty()
+    }
+}
+
+impl fmt::Debug for ParameterSet {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut map = f.debug_map();
+        for parameter in PARAMETERS.iter() {
+            map.entry(&parameter.key, &self.get(parameter));
+        }
+        map.finish()
+    }
+}
+
+impl Serialize for ParameterSet {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serialize(&PARAMETERS, self, serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for ParameterSet {
+    /// Unknown keys are ignored, like the other preset fields.
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserialize(&PARAMETERS, deserializer)
+    }
+}
+
+/// The window with all parameters, grouped like the agent and trail shaders read them.
+/// Values are clamped to their range after every edit.
+#[cfg(feature = "ui")]
+pub fn draw_window(
+    ui: &Ui,
+    sim_parameters: &mut AgentParameters,
+    fade_parameters: &mut FadeParameters,
+) {
+    let mut set = ParameterSet {
+        sim: *sim_parameters,
+        fade: *fade_parameters,
+    };
+    Window::new(im_str!("Parameters"))
+        .size([300.0, 260.0], Condition::FirstUseEver)
+        .build(ui, || {
+            ui.push_item_width(100.0);
+            draw_parameters(ui, &PARAMETERS, &mut set);
+        });
+    *sim_parameters = set.sim;
+    *fade_parameters = set.fade;
+}
+
+/// Draws the parameters of the table under the headings of their groups.
+/// Returns whether any of them was changed.
+#[cfg(feature = "ui")]
+pub fn draw_parameters<T>(ui: &Ui, table: &[Parameter<T>], target: &mut T) -> bool {
+    let mut changed = false;
+    let mut group = None;
+    // Whether the switch heading the current group, if any, is on.
+    let mut shown = true;
+
+    for parameter in table {
+        if group != Some(parameter.group) {
+            group = Some(parameter.group);
+            shown = true;
+            ui.separator();
+            if parameter.kind != Kind::Bool {
+                ui.text(parameter.group);
+            }
+        } else if !shown {
+            continue;
+        }
+
+        changed |= draw_parameter(ui, parameter, target);
+        if parameter.kind == Kind::Bool {
+            shown = parameter.value(target).as_bool();
+        }
+    }
+    changed
+}
+
+/// Returns whether the value was changed.
+#[cfg(feature = "ui")]
+pub fn draw_parameter<T>(ui: &Ui, parameter: &Parameter<T>, target: &mut T) -> bool {
+    let label = ImString::new(parameter.label_with_units());
+    let (min, max) = parameter.range;
+
+    let (changed, value) = match parameter.value(target) {
+        Value::Float(mut value) => {
+            let mut slider = Slider::new(&label).range(min..=max);
+            // Rates like those of the reaction-diffusion need more than the usual 3 decimals.
+            if max - min < 0.5 {
+                slider = slider.display_format(im_str!("%.4f"));
+            }
+            let changed = slider.build(ui, &mut value);
+            (changed, Value::Float(value))
+        }
+        Value::Int(mut value) => {
+            let changed = Slider::new(&label)
+                .range(min as i32..=max as i32)
+                .build(ui, &mut value);
+            (changed, Value::Int(value))
+        }
+        Value::Color(mut color) => {
+            let changed = ColorEdit::new(&label, &mut color).alpha(false).build(ui);
+            (changed, Value::Color(color))
+        }
+        Value::Bool(mut on) => (ui.checkbox(&label, &mut on), Value::Bool(on)),
+    };
+    if ui.is_item_hovered() {
+        ui.tooltip_text(parameter.tooltip);
+    }
+
+    // Ctrl+click lets sliders be typed into, so the value is clamped here as well.
+    parameter.set_value(target, value);
+    changed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parameter(key: &str) -> &'static Parameter {
+        find(key).unwrap()
+    }
+
+    #[test]
+    fn clamp_keeps_values_in_range() {
+        let speed = parameter("agent_speed");
+        assert_eq!(speed.clamp(Value::Float(-5.0)), Value::Float(0.0));
+        assert_eq!(speed.clamp(Value::Float(5000.0)), Value::Float(1000.0));
+        assert_eq!(speed.clamp(Value::Float(12.5)), Value::Float(12.5));
+
+        let color = parameter("trail_color");
+        assert_eq!(
+            color.clamp(Value::Color([-1.0, 0.5, 2.0])),
+            Value::Color([0.0, 0.5, 1.0])
+        );
+    }
+
+    #[test]
+    fn clamp_converts_to_the_kind_of_the_parameter() {
+        let radius = parameter("blur_radius");
+        assert_eq!(radius.clamp(Value::Float(2.6)), Value::Int(3));
+        assert_eq!(radius.clamp(Value::Int(100)), Value::Int(8));
+
+        let speed = parameter("agent_speed");
+        assert_eq!(speed.clamp(Value::Int(3)), Value::Float(3.0));
+    }
+
+    #[test]
+    fn parse_accepts_every_kind() {
+        assert_eq!(
+            parameter("agent_speed").parse(" 1.5 "),
+            Ok(Value::Float(1.5))
+        );
+        assert_eq!(parameter("sensor_radius").parse("3"), Ok(Value::Int(3)));
+        assert_eq!(
+            parameter("trail_color").parse("1, 0,0.5"),
+            Ok(Value::Color([1.0, 0.0, 0.5]))
+        );
+    }
+
+    #[test]
+    fn parse_rejects_invalid_text() {
+        assert!(parameter("agent_speed").parse("fast").is_err());
+        assert!(parameter("sensor_radius").parse("1.5").is_err());
+        assert!(parameter("trail_color").parse("1,0").is_err());
+        assert!(parameter("trail_color").parse("1,0,0,1").is_err());
+    }
+
+    #[test]
+    fn json_whole_numbers_out_of_i32_are_rejected() {
+        let radius = parameter("blur_radius");
+        assert_eq!(radius.from_json(&serde_json::json!(-3)), Ok(Value::Int(-3)));
+        assert!(radius
+            .from_json(&serde_json::json!(4_294_967_297i64))
+            .is_err());
+        assert!(serde_json::from_str::<ParameterSet>(r#"{"blur_radius": 4294967297}"#).is_err());
+    }
+
+    #[test]
+    fn default_values_are_in_range() {
+        for parameter in PARAMETERS.iter() {
+            assert_eq!(
+                parameter.clamp(parameter.default),
+                parameter.default,
+                "{}",
+                parameter.key
+            );
+        }
+    }
+
+    #[test]
+    fn json_roundtrip() {
+        let mut set = ParameterSet::default();
+        set.set(parameter("agent_speed"), Value::Float(42.0));
+        set.set(parameter("trail_color"), Value::Color([0.25, 0.5, 0.75]));
+
+        let json = serde_json::to_string(&set).unwrap();
+        let read: ParameterSet = serde_json::from_str(&json).unwrap();
+        assert_eq!(read, set);
+    }
+
+    #[test]
+    fn json_missing_keys_get_their_default() {
+        let read: ParameterSet = serde_json::from_str(r#"{"agent_speed": 42}"#).unwrap();
+        assert_eq!(read.get(parameter("agent_speed")), Value::Float(42.0));
+        let changed: Vec<&str> = read
+            .changes(&ParameterSet::default())
+            .iter()
+            .map(|p| p.key)
+            .collect();
+        assert_eq!(changed, vec!["agent_speed"]);
+    }
+
+    #[test]
+    fn json_values_out_of_range_are_clamped() {
+        let read: ParameterSet =
+            serde_json::from_str(r#"{"agent_speed": -10, "blur_radius": 50}"#).unwrap();
+        assert_eq!(read.get(parameter("agent_speed")), Value::Float(0.0));
+        assert_eq!(read.get(parameter("blur_radius")), Value::Int(8));
+    }
+
+    #[test]
+    fn json_unknown_keys_are_ignored_and_invalid_values_rejected() {
+        let read: ParameterSet = serde_json::from_str(r#"{"not_a_parameter": 1}"#).unwrap();
+        assert_eq!(read, ParameterSet::default());
+
+        assert!(serde_json::from_str::<ParameterSet>(r#"{"agent_speed": "fast"}"#).is_err());
+        assert!(serde_json::from_str::<ParameterSet>(r#"{"trail_color": [1, 0]}"#).is_err());
+    }
+}
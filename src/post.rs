@@ -0,0 +1,684 @@
+use crate::device;
+use crate::error::SimError;
+use crate::parameters;
+use crate::parameters::{Kind, Parameter, Value};
+#[cfg(feature = "ui")]
+use imgui::{im_str, ComboBox, Condition, ImString, Ui, Window};
+use serde::de::Error as _;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use serde_json::Map;
+use std::sync::Arc;
+use vulkano::command_buffer::AutoCommandBufferBuilder;
+use vulkano::descriptor::descriptor_set::{DescriptorSet, PersistentDescriptorSet};
+use vulkano::descriptor::pipeline_layout::PipelineLayout;
+use vulkano::descriptor::PipelineLayoutAbstract;
+use vulkano::device::{Device, Queue};
+use vulkano::format::Format;
+use vulkano::image::{Dimensions, StorageImage};
+use vulkano::pipeline::ComputePipeline;
+
+/// The bloom is blurred at this fraction of the resolution, which makes wide glows cheap.
+/// Has to match DOWNSAMPLE in the `threshold_shader` and the `composite_shader`.
+const BLOOM_DOWNSAMPLE: u32 = 4;
+/// The bloom images can hold values above 1, so overlapping glows add up.
+const BLOOM_FORMAT: Format = Format::R16G16B16A16Sfloat;
+
+/// Maps the brightness of an image onto a gradient, for simulations that only have a single
+/// value per pixel. Has to match COLORMAPS in the `composite_shader`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Colormap {
+    /// Keeps the colors of the image.
+    None,
+    Magma,
+    Viridis,
+    Ocean,
+    Fire,
+}
+
+impl Default for Colormap {
+    fn default() -> Self {
+        Colormap::None
+    }
+}
+
+impl Colormap {
+    pub const ALL: [Colormap; 5] = [
+        Colormap::None,
+        Colormap::Magma,
+        Colormap::Viridis,
+        Colormap::Ocean,
+        Colormap::Fire,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Colormap::None => "None",
+            Colormap::Magma => "Magma",
+            Colormap::Viridis => "Viridis",
+            Colormap::Ocean => "Ocean",
+            Colormap::Fire => "Fire",
+        }
+    }
+
+    /// 0 for none, otherwise the gradient in the `composite_shader` plus one.
+    fn shader_index(&self) -> i32 {
+        Colormap::ALL.iter().position(|c| c == self).unwrap() as i32
+    }
+}
+
+/// How the trails are made to look on screen. None of this changes the trails themselves,
+/// so the agents keep sensing the raw trail map.
+///
+/// Everything but the colormap is in `PARAMETERS`, which also makes up its json.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PostSettings {
+    /// Adds a glow around the bright parts of the trails.
+    pub bloom: bool,
+    /// Intensity above which pixels start to glow, in the range [0 - 1].
+    pub threshold: f32,
+    /// Radius of the glow, in downsampled pixels.
+    pub bloom_radius: i32,
+    pub bloom_strength: f32,
+    /// Maps colors with `1 - exp(-color * exposure)`, so bright glows do not clip to white.
+    pub tone_mapping: bool,
+    pub exposure: f32,
+    /// Applied after the tone mapping and before the gamma correction.
+    pub colormap: Colormap,
+    pub gamma_correction: bool,
+    pub gamma: f32,
+}
+
+/// The post-processing parameters, in the order they are shown in.
+pub static PARAMETERS: [Parameter<PostSettings>; 8] = [
+    Parameter {
+        key: "bloom",
+        flag: "bloom",
+        label: "Bloom",
+        units: "",
+        tooltip: "Adds a glow around the bright parts of the trails.",
+        group: "Bloom",
+        kind: Kind::Bool,
+        range: (0.0, 1.0),
+        default: Value::Bool(false),
+        get: |post| Value::Bool(post.bloom),
+        set: |post, value| post.bloom = value.as_bool(),
+    },
+    Parameter {
+        key: "threshold",
+        flag: "bloom-threshold",
+        label: "Threshold",
+        units: "",
+        tooltip: "Brightness above which pixels start to glow.",
+        group: "Bloom",
+        kind: Kind::Float,
+        range: (0.0, 1.0),
+        default: Value::Float(0.5),
+        get: |post| Value::Float(post.threshold),
+        set: |post, value| post.threshold = value.as_float(),
+    },
+    Parameter {
+        key: "bloom_radius",
+        flag: "bloom-radius",
+        label: "Radius",
+        units: "",
+        tooltip:
+            "Radius of the glow, in pixels of the bloom, which has a quarter of the resolution.",
+        group: "Bloom",
+        kind: Kind::Int,
+        range: (1.0, 32.0),
+        default: Value::Int(8),
+        get: |post| Value::Int(post.bloom_radius),
+        set: |post, value| post.bloom_radius = value.as_int(),
+    },
+    Parameter {
+        key: "bloom_strength",
+        flag: "bloom-strength",
+        label: "Strength",
+        units: "",
+        tooltip: "How bright the glow is added on top of the trails.",
+        group: "Bloom",
+        kind: Kind::Float,
+        range: (0.0, 4.0),
+        default: Value::Float(1.0),
+        get: |post| Value::Float(post.bloom_strength),
+        set: |post, value| post.bloom_strength = value.as_float(),
+    },
+    Parameter {
+        key: "tone_mapping",
+        flag: "tone-mapping",
+        label: "Tone mapping",
+        units: "",
+        tooltip:
+            "Maps colors with 1 - exp(-color * exposure), so bright glows do not clip to white.",
+        group: "Tone mapping",
+        kind: Kind::Bool,
+        range: (0.0, 1.0),
+        default: Value::Bool(false),
+        get: |post| Value::Bool(post.tone_mapping),
+        set: |post, value| post.tone_mapping = value.as_bool(),
+    },
+    Parameter {
+        key: "exposure",
+        flag: "exposure",
+        label: "Exposure",
+        units: "",
+        tooltip: "Higher values make the trails brighter before they are tone mapped.",
+        group: "Tone mapping",
+        kind: Kind::Float,
+        range: (0.1, 8.0),
+        default: Value::Float(1.5),
+        get: |post| Value::Float(post.exposure),
+        set: |post, value| post.exposure = value.as_float(),
+    },
+    Parameter {
+        key: "gamma_correction",
+        flag: "gamma-correction",
+        label: "Gamma correction",
+        units: "",
+        tooltip: "Brightens the dark parts of the trails, applied last.",
+        group: "Gamma correction",
+        kind: Kind::Bool,
+        range: (0.0, 1.0),
+        default: Value::Bool(false),
+        get: |post| Value::Bool(post.gamma_correction),
+        set: |post, value| post.gamma_correction = value.as_bool(),
+    },
+    Parameter {
+        key: "gamma",
+        flag: "gamma",
+        label: "Gamma",
+        units: "",
+        tooltip: "The gamma the colors are corrected for.",
+        group: "Gamma correction",
+        kind: Kind::Float,
+        range: (1.0, 3.0),
+        default: Value::Float(2.2),
+        get: |post| Value::Float(post.gamma),
+        set: |post, value| post.gamma = value.as_float(),
+    },
+];
+
+impl Default for PostSettings {
+    /// Every parameter at its `default`, without a colormap.
+    fn default() -> Self {
+        // The values of the registry are filled in below.
+        let mut settings = PostSettings {
+            bloom: false,
+            threshold: 0.0,
+            bloom_radius: 0,
+            bloom_strength: 0.0,
+            tone_mapping: false,
+            exposure: 0.0,
+            colormap: Colormap::None,
+            gamma_correction: false,
+            gamma: 0.0,
+        };
+        parameters::set_defaults(&PARAMETERS, &mut settings);
+        settings
+    }
+}
+
+impl Serialize for PostSettings {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut map = parameters::to_json_map(&PARAMETERS, self);
+        let colormap = serde_json::to_value(self.colormap).map_err(serde::ser::Error::custom)?;
+        map.insert("colormap".to_owned(), colormap);
+        map.serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for PostSettings {
+    /// Missing fields get their default value, and values outside of their range are clamped.
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let map = Map::<String, serde_json::Value>::deserialize(deserializer)?;
+        let mut settings = PostSettings::default();
+        parameters::apply_json_map(&PARAMETERS, &mut settings, &map).map_err(D::Error::custom)?;
+        if let Some(colormap) = map.get("colormap") {
+            settings.colormap =
+                serde_json::from_value(colormap.clone()).map_err(D::Error::custom)?;
+        }
+        Ok(settings)
+    }
+}
+
+impl PostSettings {
+    /// Whether any of the stages is enabled. Otherwise the trails are displayed as they are.
+    pub fn is_active(&self) -> bool {
+        self.bloom || self.tone_mapping || self.colormap != Colormap::None || self.gamma_correction
+    }
+
+    #[cfg(feature = "ui")]
+    pub fn draw_window(&mut self, ui: &Ui) {
+        let settings = self;
+
+        Window::new(im_str!("Post-processing"))
+            .position([320.0, 500.0], Condition::FirstUseEver)
+            .size([300.0, 280.0], Condition::FirstUseEver)
+            .build(ui, || {
+                parameters::draw_parameters(ui, &PARAMETERS, settings);
+
+                ui.separator();
+                let names: Vec<ImString> = Colormap::ALL
+                    .iter()
+                    .map(|c| ImString::new(c.name()))
+                    .collect();
+                let name_refs: Vec<&ImString> = names.iter().collect();
+                let mut selected = settings.colormap.shader_index() as usize;
+                if ComboBox::new(im_str!("Colormap")).build_simple_string(
+                    &ui,
+                    &mut selected,
+                    &name_refs,
+                ) {
+                    settings.colormap = Colormap::ALL[selected];
+                }
+            });
+    }
+}
+
+/// Applies the `PostSettings` to an image in `device::DISPLAY_FORMAT`, writing the result
+/// to a separate `output` image.
+pub struct PostProcessor {
+    dimensions: [u32; 2],
+    bloom_dimensions: [u32; 2],
+    output: Arc<StorageImage<Format>>,
+    threshold_pipeline: Arc<ComputePipeline<PipelineLayout<threshold_shader::Layout>>>,
+    threshold_set: Arc<dyn DescriptorSet + Send + Sync>,
+    blur_pipeline: Arc<ComputePipeline<PipelineLayout<bloom_blur_shader::Layout>>>,
+    /// Blurs the first bloom image horizontally into the second one, and then back vertically.
+    blur_sets: [Arc<dyn DescriptorSet + Send + Sync>; 2],
+    composite_pipeline: Arc<ComputePipeline<PipelineLayout<composite_shader::Layout>>>,
+    composite_set: Arc<dyn DescriptorSet + Send + Sync>,
+}
+
+impl PostProcessor {
+    /// The passes are recorded for `queue`, whose family has to be able to use `source`.
+    pub fn new(
+        device: &Arc<Device>,
+        queue: &Arc<Queue>,
+        source: &Arc<StorageImage<Format>>,
+    ) -> Result<PostProcessor, SimError> {
+        let dimensions = source.dimensions();
+        let bloom_size = Dimensions::Dim2d {
+            width: (dimensions.width() + BLOOM_DOWNSAMPLE - 1) / BLOOM_DOWNSAMPLE,
+            height: (dimensions.height() + BLOOM_DOWNSAMPLE - 1) / BLOOM_DOWNSAMPLE,
+        };
+
+        let bloom_images = [
+            StorageImage::new(
+                device.clone(),
+                bloom_size,
+                BLOOM_FORMAT,
+                Some(queue.family()),
+            )?,
+            StorageImage::new(
+                device.clone(),
+                bloom_size,
+                BLOOM_FORMAT,
+                Some(queue.family()),
+            )?,
+        ];
+        let output = StorageImage::new(
+            device.clone(),
+            dimensions,
+            device::DISPLAY_FORMAT,
+            Some(queue.family()),
+        )?;
+
+        let threshold_shader = threshold_shader::Shader::load(device.clone())?;
+        let threshold_pipeline = Arc::new(ComputePipeline::new(
+            device.clone(),
+            &threshold_shader.main_entry_point(),
+            &(),
+            None,
+        )?);
+        let threshold_set = Arc::new(
+            PersistentDescriptorSet::start(
+                threshold_pipeline
+                    .layout()
+                    .descriptor_set_layout(0)
+                    .unwrap()
+                    .clone(),
+            )
+            .add_image(source.clone())?
+            .add_image(bloom_images[0].clone())?
+            .build()?,
+        );
+
+        let blur_shader = bloom_blur_shader::Shader::load(device.clone())?;
+        let blur_pipeline = Arc::new(ComputePipeline::new(
+            device.clone(),
+            &blur_shader.main_entry_point(),
+            &(),
+            None,
+        )?);
+        let blur_set = |from: &Arc<StorageImage<Format>>,
+                        to: &Arc<StorageImage<Format>>|
+         -> Result<Arc<dyn DescriptorSet + Send + Sync>, SimError> {
+            Ok(Arc::new(
+                PersistentDescriptorSet::start(
+                    blur_pipeline
+                        .layout()
+                        .descriptor_set_layout(0)
+                        .unwrap()
+                        .clone(),
+                )
+                .add_image(from.clone())?
+                .add_image(to.clone())?
+                .build()?,
+            ))
+        };
+        let blur_sets = [
+            blur_set(&bloom_images[0], &bloom_images[1])?,
+            blur_set(&bloom_images[1], &bloom_images[0])?,
+        ];
+
+        let composite_shader = composite_shader::Shader::load(device.clone())?;
+        let composite_pipeline = Arc::new(ComputePipeline::new(
+            device.clone(),
+            &composite_shader.main_entry_point(),
+            &(),
+            None,
+        )?);
+        let composite_set = Arc::new(
+            PersistentDescriptorSet::start(
+                composite_pipeline
+                    .layout()
+                    .descriptor_set_layout(0)
+                    .unwrap()
+                    .clone(),
+            )
+            .add_image(source.clone())?
+            .add_image(bloom_images[0].clone())?
+            .add_image(output.clone())?
+            .build()?,
+        );
+
+        Ok(PostProcessor {
+            dimensions: [dimensions.width(), dimensions.height()],
+            bloom_dimensions: [bloom_size.width(), bloom_size.height()],
+            output,
+            threshold_pipeline,
+            threshold_set,
+            blur_pipeline,
+            blur_sets,
+            composite_pipeline,
+            composite_set,
+        })
+    }
+
+    /// The image the last recorded passes write to.
+    pub fn output(&self) -> &Arc<StorageImage<Format>> {
+        &self.output
+    }
+
+    /// Records the enabled stages. The source image is only read.
+    pub fn record(
+        &self,
+        builder: &mut AutoCommandBufferBuilder,
+        settings: &PostSettings,
+    ) -> Result<(), SimError> {
+        let bloom_groups = [
+            (self.bloom_dimensions[0] + 7) / 8,
+            (self.bloom_dimensions[1] + 7) / 8,
+            1,
+        ];
+
+        if settings.bloom {
+            builder.dispatch(
+                bloom_groups,
+                self.threshold_pipeline.clone(),
+                self.threshold_set.clone(),
+                threshold_shader::ty::PushConstantData {
+                    threshold: settings.threshold,
+                },
+            )?;
+
+            for (index, horizontal) in [true, false].iter().enumerate() {
+                builder.dispatch(
+                    bloom_groups,
+                    self.blur_pipeline.clone(),
+                    self.blur_sets[index].clone(),
+                    bloom_blur_shader::ty::PushConstantData {
+                        radius: settings.bloom_radius.max(1),
+                        horizontal: *horizontal as i32,
+                    },
+                )?;
+            }
+        }
+
+        builder.dispatch(
+            [
+                (self.dimensions[0] + 7) / 8,
+                (self.dimensions[1] + 7) / 8,
+                1,
+            ],
+            self.composite_pipeline.clone(),
+            self.composite_set.clone(),
+            composite_shader::ty::PushConstantData {
+                bloom_strength: settings.bloom_strength,
+                exposure: settings.exposure,
+                gamma: settings.gamma,
+                bloom: settings.bloom as i32,
+                tone_mapping: settings.tone_mapping as i32,
+                gamma_correction: settings.gamma_correction as i32,
+                colormap: settings.colormap.shader_index(),
+            },
+        )?;
+
+        Ok(())
+    }
+}
+
+pub mod threshold_shader {
+    vulkano_shaders::shader! {
+        ty: "compute",
+        src:
+"
+#version 450
+
+// Has to match BLOOM_DOWNSAMPLE.
+const int DOWNSAMPLE = 4;
+
+layout(local_size_x = 8, local_size_y = 8, local_size_z = 1) in;
+
+layout(set = 0, binding = 0, rgba8) uniform readonly image2D source_img;
+layout(set = 0, binding = 1, rgba16f) uniform writeonly image2D bloom_img;
+
+layout(push_constant) uniform PushConstantData {
+    float threshold;
+} pc;
+
+void main() {
+    ivec2 pos = ivec2(gl_GlobalInvocationID.xy);
+    if (pos.x >= imageSize(bloom_img).x || pos.y >= imageSize(bloom_img).y) {
+        return;
+    }
+    ivec2 size = imageSize(source_img);
+
+    // Average the source pixels that fall in this bloom pixel.
+    vec3 sum = vec3(0.0);
+    float count = 0.0;
+    for (int x = 0; x < DOWNSAMPLE; x++) {
+        for (int y = 0; y < DOWNSAMPLE; y++) {
+            ivec2 sample_pos = pos * DOWNSAMPLE + ivec2(x, y);
+            if (sample_pos.x < size.x && sample_pos.y < size.y) {
+                sum += imageLoad(source_img, sample_pos).rgb;
+                count += 1.0;
+            }
+        }
+    }
+
+    vec3 bright = max(sum / max(count, 1.0) - pc.threshold, 0.0);
+    imageStore(bloom_img, pos, vec4(bright, 1.0));
+}
+"
+    }
+}
+
+pub mod bloom_blur_shader {
+    vulkano_shaders::shader! {
+        ty: "compute",
+        src:
+"
+#version 450
+
+layout(local_size_x = 8, local_size_y = 8, local_size_z = 1) in;
+
+layout(set = 0, binding = 0, rgba16f) uniform readonly image2D in_img;
+layout(set = 0, binding = 1, rgba16f) uniform writeonly image2D out_img;
+
+layout(push_constant) uniform PushConstantData {
+    // In pixels.
+    int radius;
+    // Blurs along x when not 0, and along y otherwise.
+    int horizontal;
+} pc;
+
+void main() {
+    ivec2 pos = ivec2(gl_GlobalInvocationID.xy);
+    ivec2 size = imageSize(in_img);
+    if (pos.x >= size.x || pos.y >= size.y) {
+        return;
+    }
+
+    ivec2 direction = pc.horizontal != 0 ? ivec2(1, 0) : ivec2(0, 1);
+    float sigma = max(float(pc.radius) / 2.0, 1.0);
+
+    vec3 sum = vec3(0.0);
+    float weight_sum = 0.0;
+    for (int i = -pc.radius; i <= pc.radius; i++) {
+        ivec2 sample_pos = clamp(pos + direction * i, ivec2(0), size - 1);
+        float weight = exp(-float(i * i) / (2.0 * sigma * sigma));
+        sum += imageLoad(in_img, sample_pos).rgb * weight;
+        weight_sum += weight;
+    }
+
+    imageStore(out_img, pos, vec4(sum / weight_sum, 1.0));
+}
+"
+    }
+}
+
+pub mod composite_shader {
+    vulkano_shaders::shader! {
+        ty: "compute",
+        src:
+"
+#version 450
+
+// Has to match BLOOM_DOWNSAMPLE.
+const int DOWNSAMPLE = 4;
+
+layout(local_size_x = 8, local_size_y = 8, local_size_z = 1) in;
+
+layout(set = 0, binding = 0, rgba8) uniform readonly image2D source_img;
+layout(set = 0, binding = 1, rgba16f) uniform readonly image2D bloom_img;
+layout(set = 0, binding = 2, rgba8) uniform writeonly image2D out_img;
+
+layout(push_constant) uniform PushConstantData {
+    float bloom_strength;
+    float exposure;
+    float gamma;
+    // Which stages are enabled, 0 means disabled.
+    int bloom;
+    int tone_mapping;
+    int gamma_correction;
+    // 0 for none, otherwise one more than the index into COLORMAPS.
+    int colormap;
+} pc;
+
+// Five evenly spaced colors of every colormap, from dark to bright.
+const int COLORMAP_STOPS = 5;
+const vec3 COLORMAPS[20] = vec3[](
+    // Magma
+    vec3(0.001, 0.000, 0.014), vec3(0.316, 0.071, 0.485), vec3(0.716, 0.215, 0.475),
+    vec3(0.987, 0.536, 0.382), vec3(0.987, 0.991, 0.749),
+    // Viridis
+    vec3(0.267, 0.005, 0.329), vec3(0.229, 0.322, 0.546), vec3(0.128, 0.567, 0.551),
+    vec3(0.369, 0.789, 0.383), vec3(0.993, 0.906, 0.144),
+    // Ocean
+    vec3(0.000, 0.000, 0.050), vec3(0.000, 0.150, 0.350), vec3(0.000, 0.450, 0.600),
+    vec3(0.300, 0.800, 0.850), vec3(0.900, 1.000, 1.000),
+    // Fire
+    vec3(0.000, 0.000, 0.000), vec3(0.500, 0.000, 0.000), vec3(0.900, 0.300, 0.000),
+    vec3(1.000, 0.800, 0.200), vec3(1.000, 1.000, 0.900)
+);
+
+vec3 apply_colormap(vec3 color) {
+    float brightness = clamp(dot(color, vec3(0.2126, 0.7152, 0.0722)), 0.0, 1.0);
+    float position = brightness * float(COLORMAP_STOPS - 1);
+    int stop = min(int(position), COLORMAP_STOPS - 2);
+    int first = (pc.colormap - 1) * COLORMAP_STOPS + stop;
+    return mix(COLORMAPS[first], COLORMAPS[first + 1], position - float(stop));
+}
+
+// Bilinearly upsamples the bloom image.
+vec3 sample_bloom(ivec2 pos) {
+    ivec2 size = imageSize(bloom_img);
+    vec2 bloom_pos = (vec2(pos) + 0.5) / DOWNSAMPLE - 0.5;
+    ivec2 base = ivec2(floor(bloom_pos));
+    vec2 t = bloom_pos - vec2(base);
+
+    vec3 a = imageLoad(bloom_img, clamp(base, ivec2(0), size - 1)).rgb;
+    vec3 b = imageLoad(bloom_img, clamp(base + ivec2(1, 0), ivec2(0), size - 1)).rgb;
+    vec3 c = imageLoad(bloom_img, clamp(base + ivec2(0, 1), ivec2(0), size - 1)).rgb;
+    vec3 d = imageLoad(bloom_img, clamp(base + ivec2(1, 1), ivec2(0), size - 1)).rgb;
+    return mix(mix(a, b, t.x), mix(c, d, t.x), t.y);
+}
+
+void main() {
+    ivec2 pos = ivec2(gl_GlobalInvocationID.xy);
+    ivec2 size = imageSize(source_img);
+    if (pos.x >= size.x || pos.y >= size.y) {
+        return;
+    }
+
+    vec3 color = imageLoad(source_img, pos).rgb;
+
+    if (pc.bloom != 0) {
+        color += sample_bloom(pos) * pc.bloom_strength;
+    }
+    if (pc.tone_mapping != 0) {
+        color = vec3(1.0) - exp(-color * pc.exposure);
+    }
+    if (pc.colormap != 0) {
+        color = apply_colormap(color);
+    }
+    if (pc.gamma_correction != 0) {
+        color = pow(clamp(color, 0.0, 1.0), vec3(1.0 / pc.gamma));
+    }
+
+    imageStore(out_img, pos, vec4(color, 1.0));
+}
+"
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn json_roundtrip_keeps_the_colormap() {
+        let settings = PostSettings {
+            bloom: true,
+            exposure: 3.0,
+            colormap: Colormap::Viridis,
+            ..PostSettings::default()
+        };
+        let json = serde_json::to_string(&settings).unwrap();
+        let read: PostSettings = serde_json::from_str(&json).unwrap();
+        assert_eq!(read, settings);
+    }
+
+    #[test]
+    fn json_values_out_of_range_are_clamped() {
+        let read: PostSettings =
+            serde_json::from_str(r#"{"gamma": 10, "bloom_radius": 0, "colormap": "Fire"}"#)
+                .unwrap();
+        assert_eq!(read.gamma, 3.0);
+        assert_eq!(read.bloom_radius, 1);
+        assert_eq!(read.colormap, Colormap::Fire);
+        assert_eq!(read.threshold, PostSettings::default().threshold);
+    }
+}
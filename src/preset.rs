@@ -0,0 +1,107 @@
+use crate::colony::ColonySettings;
+use crate::device::TrailFormat;
+use crate::parameters::ParameterSet;
+use crate::post::PostSettings;
+use crate::screenshot;
+use crate::simulation;
+use crate::simulation::{AgentParameters, FadeParameters, SimulationConfig};
+use serde::{Deserialize, Serialize};
+use std::error::Error;
+use std::fs;
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::Path;
+
+/// Everything needed to start a simulation, stored as a json file.
+/// Fields that are missing from a file get their default value.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Preset {
+    pub width: u32,
+    pub height: u32,
+    pub agent_amount: u32,
+    /// Precision the trails are simulated in.
+    pub trail_format: TrailFormat,
+    /// A random seed is used when this is not given.
+    pub seed: Option<u64>,
+    /// One field per entry of `parameters::PARAMETERS`, like `agent_speed`.
+    #[serde(flatten)]
+    pub parameters: ParameterSet,
+    /// How the window shows the trails, with one field per entry of `post::PARAMETERS`.
+    /// Left out when the preset does not set it, like for screenshots, which record the
+    /// post-processing of the image separately.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub post: Option<PostSettings>,
+    /// The nest and food of the ant mode, with one field per entry of `colony::PARAMETERS`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub colony: Option<ColonySettings>,
+}
+
+impl Default for Preset {
+    fn default() -> Self {
+        let config = SimulationConfig::default();
+        Preset::from_parameters(
+            &config,
+            &simulation::default_sim_parameters(),
+            &simulation::default_fade_parameters(),
+        )
+        .without_seed()
+    }
+}
+
+impl Preset {
+    pub fn from_parameters(
+        config: &SimulationConfig,
+        sim_parameters: &AgentParameters,
+        fade_parameters: &FadeParameters,
+    ) -> Preset {
+        Preset {
+            width: config.width,
+            height: config.height,
+            agent_amount: config.agent_amount,
+            trail_format: config.trail_format,
+            seed: Some(config.seed),
+            parameters: ParameterSet::from_parameters(sim_parameters, fade_parameters),
+            post: None,
+            colony: None,
+        }
+    }
+
+    pub fn without_seed(mut self) -> Preset {
+        self.seed = None;
+        self
+    }
+
+    /// Loads a json preset, or the parameters embedded in a screenshot.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Preset, Box<dyn Error>> {
+        let bytes = fs::read(path)?;
+        if bytes.starts_with(&screenshot::PNG_SIGNATURE) {
+            return Ok(screenshot::read_info(&bytes)?.preset);
+        }
+        Ok(serde_json::from_slice(&bytes)?)
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), Box<dyn Error>> {
+        let file = File::create(path)?;
+        serde_json::to_writer_pretty(BufWriter::new(file), self)?;
+        Ok(())
+    }
+
+    pub fn config(&self) -> SimulationConfig {
+        SimulationConfig {
+            width: self.width,
+            height: self.height,
+            agent_amount: self.agent_amount,
+            seed: self.seed.unwrap_or_else(rand::random),
+            trail_format: self.trail_format,
+        }
+    }
+
+    pub fn sim_parameters(&self) -> AgentParameters {
+        self.parameters.sim
+    }
+
+    pub fn fade_parameters(&self) -> FadeParameters {
+        self.parameters.fade
+    }
+}
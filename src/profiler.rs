@@ -0,0 +1,331 @@
+use crate::error::SimError;
+#[cfg(feature = "ui")]
+use imgui::{im_str, Condition, Slider, Ui, Window};
+use std::collections::VecDeque;
+use std::sync::Arc;
+use std::time::Instant;
+use std::{mem, ptr};
+use vk_sys as vk;
+use vulkano::command_buffer::pool::UnsafeCommandPool;
+use vulkano::command_buffer::CommandBuffer;
+use vulkano::device::{Device, Queue};
+use vulkano::query::{QueryType, UnsafeQueryPool};
+use vulkano::sync::GpuFuture;
+use vulkano::{SynchronizedVulkanObject, VulkanObject};
+
+/// How many frames are kept for the rolling graph.
+const HISTORY_LENGTH: usize = 240;
+/// Upper end of the steps per frame slider.
+#[cfg(feature = "ui")]
+const MAX_STEPS_PER_FRAME: u32 = 16;
+
+/// How long each of the passes of a single frame took, in milliseconds.
+///
+/// These are measured with gpu timestamps, see `GpuTimer`.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct PassTimings {
+    pub clear_ms: f32,
+    pub agent_ms: f32,
+    pub blur_ms: f32,
+    pub display_ms: f32,
+}
+
+impl PassTimings {
+    pub fn total_ms(&self) -> f32 {
+        self.clear_ms + self.agent_ms + self.blur_ms + self.display_ms
+    }
+}
+
+/// Writes gpu timestamps between the submissions to a queue, to time the passes in between.
+///
+/// Vulkano 0.20 can not record timestamp queries into an `AutoCommandBuffer`, so every
+/// timestamp is written by a small command buffer of its own, recorded once with the raw
+/// vulkan functions. A timestamp is written once all commands submitted to the queue before
+/// it have finished, so the difference between two timestamps is the gpu time of the passes
+/// submitted between them. Those passes therefore have to be flushed one by one, see
+/// `then_flush`, which is why the passes are only timed while profiling is enabled.
+pub struct GpuTimer {
+    device: Arc<Device>,
+    queue: Arc<Queue>,
+    query_pool: UnsafeQueryPool,
+    /// Frees the command buffers when the timer is dropped.
+    _command_pool: UnsafeCommandPool,
+    /// Writes the timestamp with the same index.
+    command_buffers: Vec<vk::CommandBuffer>,
+    /// Nanoseconds per timestamp tick.
+    period: f32,
+}
+
+impl GpuTimer {
+    /// A timer for `count` timestamps on the queue.
+    /// Returns `None` if the device can not write timestamps on its compute and graphics queues.
+    pub fn new(
+        device: Arc<Device>,
+        queue: Arc<Queue>,
+        count: u32,
+    ) -> Result<Option<GpuTimer>, SimError> {
+        let limits = device.physical_device().limits();
+        if limits.timestamp_compute_and_graphics() == 0 {
+            return Ok(None);
+        }
+        let period = limits.timestamp_period();
+
+        let query_pool = UnsafeQueryPool::new(device.clone(), QueryType::Timestamp, count)
+            .map_err(|e| SimError::OutOfMemory(e.to_string()))?;
+        let command_pool = UnsafeCommandPool::new(device.clone(), queue.family(), false, false)?;
+        let command_buffers: Vec<vk::CommandBuffer> = command_pool
+            .alloc_command_buffers(false, count)?
+            .map(|command_buffer| command_buffer.internal_object())
+            .collect();
+
+        let vk = device.pointers();
+        for (index, &command_buffer) in command_buffers.iter().enumerate() {
+            let begin_info = vk::CommandBufferBeginInfo {
+                sType: vk::STRUCTURE_TYPE_COMMAND_BUFFER_BEGIN_INFO,
+                pNext: ptr::null(),
+                flags: 0,
+                pInheritanceInfo: ptr::null(),
+            };
+            // The query is reset right before it is written, so the command buffer can be
+            // submitted again for the next measurement.
+            unsafe {
+                check(vk.BeginCommandBuffer(command_buffer, &begin_info))?;
+                vk.CmdResetQueryPool(
+                    command_buffer,
+                    query_pool.internal_object(),
+                    index as u32,
+                    1,
+                );
+                vk.CmdWriteTimestamp(
+                    command_buffer,
+                    vk::PIPELINE_STAGE_BOTTOM_OF_PIPE_BIT,
+                    query_pool.internal_object(),
+                    index as u32,
+                );
+                check(vk.EndCommandBuffer(command_buffer))?;
+            }
+        }
+
+        Ok(Some(GpuTimer {
+            device,
+            queue,
+            query_pool,
+            _command_pool: command_pool,
+            command_buffers,
+            period,
+        }))
+    }
+
+    /// Submits the command buffer that writes timestamp `index`, once everything submitted
+    /// to the queue before it has finished.
+    /// The previous measurement should have been read before a timestamp is written again.
+    pub fn write(&self, index: usize) -> Result<(), SimError> {
+        let submit_info = vk::SubmitInfo {
+            sType: vk::STRUCTURE_TYPE_SUBMIT_INFO,
+            pNext: ptr::null(),
+            waitSemaphoreCount: 0,
+            pWaitSemaphores: ptr::null(),
+            pWaitDstStageMask: ptr::null(),
+            commandBufferCount: 1,
+            pCommandBuffers: &self.command_buffers[index],
+            signalSemaphoreCount: 0,
+            pSignalSemaphores: ptr::null(),
+        };
+        let queue = self.queue.internal_object_guard();
+        unsafe {
+            check(
+                self.device
+                    .pointers()
+                    .QueueSubmit(*queue, 1, &submit_info, 0),
+            )
+        }
+    }
+
+    /// Waits until every timestamp has been written, and returns the milliseconds between
+    /// each timestamp and the next one.
+    /// Every timestamp has to have been written with `write` first.
+    pub fn read_intervals(&self) -> Result<Vec<f32>, SimError> {
+        let mut ticks = vec![0u64; self.command_buffers.len()];
+        unsafe {
+            check(self.device.pointers().GetQueryPoolResults(
+                self.device.internal_object(),
+                self.query_pool.internal_object(),
+                0,
+                ticks.len() as u32,
+                ticks.len() * mem::size_of::<u64>(),
+                ticks.as_mut_ptr() as *mut _,
+                mem::size_of::<u64>() as u64,
+                vk::QUERY_RESULT_64_BIT | vk::QUERY_RESULT_WAIT_BIT,
+            ))?;
+        }
+
+        Ok(ticks
+            .windows(2)
+            .map(|pair| pair[1].wrapping_sub(pair[0]) as f32 * self.period / 1_000_000.0)
+            .collect())
+    }
+}
+
+/// Executes the command buffer after the future, and submits it right away,
+/// so that a timestamp written after this only follows the passes submitted so far.
+pub fn then_flush<C>(
+    future: Box<dyn GpuFuture>,
+    queue: &Arc<Queue>,
+    command_buffer: C,
+) -> Result<Box<dyn GpuFuture>, SimError>
+where
+    C: CommandBuffer + Send + Sync + 'static,
+{
+    Ok(future
+        .then_execute(queue.clone(), command_buffer)?
+        .then_signal_fence_and_flush()?
+        .boxed())
+}
+
+fn check(result: vk::Result) -> Result<(), SimError> {
+    match result {
+        vk::SUCCESS => Ok(()),
+        vk::ERROR_OUT_OF_HOST_MEMORY | vk::ERROR_OUT_OF_DEVICE_MEMORY => Err(
+            SimError::OutOfMemory("while writing timestamps".to_string()),
+        ),
+        code => Err(SimError::Execution(format!(
+            "writing timestamps failed with vulkan error {}",
+            code
+        ))),
+    }
+}
+
+/// Keeps track of frame times, simulation steps and (when enabled) the time per pass.
+pub struct Profiler {
+    /// Whether the individual passes should be timed.
+    pub enabled: bool,
+    pass_history: VecDeque<PassTimings>,
+    frame_history: VecDeque<f32>,
+    step_history: VecDeque<u32>,
+    last_frame: Option<Instant>,
+}
+
+impl Default for Profiler {
+    fn default() -> Self {
+        Profiler::new()
+    }
+}
+
+impl Profiler {
+    pub fn new() -> Profiler {
+        Profiler {
+            enabled: false,
+            pass_history: VecDeque::with_capacity(HISTORY_LENGTH),
+            frame_history: VecDeque::with_capacity(HISTORY_LENGTH),
+            step_history: VecDeque::with_capacity(HISTORY_LENGTH),
+            last_frame: None,
+        }
+    }
+
+    /// Call once per frame, with the amount of simulation steps that were run in that frame.
+    /// `timings` should be given when the passes have been timed.
+    pub fn record_frame(&mut self, steps: u32, timings: Option<PassTimings>) {
+        let now = Instant::now();
+        if let Some(last_frame) = self.last_frame {
+            push_limited(
+                &mut self.frame_history,
+                now.duration_since(last_frame).as_secs_f32() * 1000.0,
+            );
+            push_limited(&mut self.step_history, steps);
+        }
+        self.last_frame = Some(now);
+
+        if let Some(timings) = timings {
+            push_limited(&mut self.pass_history, timings);
+        }
+    }
+
+    /// The most recently measured pass timings, if any.
+    pub fn latest(&self) -> Option<PassTimings> {
+        self.pass_history.back().copied()
+    }
+
+    /// The pass timings averaged over the history.
+    pub fn average(&self) -> PassTimings {
+        let mut sum = PassTimings::default();
+        for timings in &self.pass_history {
+            sum.clear_ms += timings.clear_ms;
+            sum.agent_ms += timings.agent_ms;
+            sum.blur_ms += timings.blur_ms;
+            sum.display_ms += timings.display_ms;
+        }
+
+        let count = self.pass_history.len().max(1) as f32;
+        PassTimings {
+            clear_ms: sum.clear_ms / count,
+            agent_ms: sum.agent_ms / count,
+            blur_ms: sum.blur_ms / count,
+            display_ms: sum.display_ms / count,
+        }
+    }
+
+    pub fn fps(&self) -> f32 {
+        let total_ms: f32 = self.frame_history.iter().sum();
+        if total_ms <= 0.0 {
+            return 0.0;
+        }
+        self.frame_history.len() as f32 * 1000.0 / total_ms
+    }
+
+    pub fn steps_per_second(&self) -> f32 {
+        let total_ms: f32 = self.frame_history.iter().sum();
+        if total_ms <= 0.0 {
+            return 0.0;
+        }
+        let total_steps: u32 = self.step_history.iter().sum();
+        total_steps as f32 * 1000.0 / total_ms
+    }
+
+    /// Shows the measurements, and lets the user change how many steps run per frame.
+    #[cfg(feature = "ui")]
+    pub fn draw_overlay(&mut self, ui: &Ui, steps_per_frame: &mut u32) {
+        let fps = self.fps();
+        let steps_per_second = self.steps_per_second();
+        let frame_times: Vec<f32> = self.frame_history.iter().copied().collect();
+        let pass_times: Vec<f32> = self.pass_history.iter().map(|t| t.total_ms()).collect();
+        let average = self.average();
+        let enabled = &mut self.enabled;
+
+        Window::new(im_str!("Performance"))
+            .position([10.0, 220.0], Condition::FirstUseEver)
+            .size([300.0, 260.0], Condition::FirstUseEver)
+            .build(ui, || {
+                ui.text(format!("FPS: {:.1}", fps));
+                ui.text(format!("Steps/s: {:.1}", steps_per_second));
+                ui.plot_lines(im_str!("Frame (ms)"), &frame_times)
+                    .graph_size([0.0, 40.0])
+                    .scale_min(0.0)
+                    .build();
+                Slider::new(im_str!("Steps per frame"))
+                    .range(1..=MAX_STEPS_PER_FRAME)
+                    .build(ui, steps_per_frame);
+
+                ui.separator();
+                ui.checkbox(im_str!("Time passes"), enabled);
+
+                if *enabled {
+                    ui.text_disabled("Each pass is submitted and timed on its own.");
+                    ui.text(format!("Clear:   {:.3} ms", average.clear_ms));
+                    ui.text(format!("Agents:  {:.3} ms", average.agent_ms));
+                    ui.text(format!("Blur:    {:.3} ms", average.blur_ms));
+                    ui.text(format!("Display: {:.3} ms", average.display_ms));
+                    ui.plot_lines(im_str!("Passes (ms)"), &pass_times)
+                        .graph_size([0.0, 40.0])
+                        .scale_min(0.0)
+                        .build();
+                }
+            });
+    }
+}
+
+fn push_limited<T>(history: &mut VecDeque<T>, value: T) {
+    if history.len() >= HISTORY_LENGTH {
+        history.pop_front();
+    }
+    history.push_back(value);
+}
@@ -0,0 +1,714 @@
+use crate::device;
+use crate::device::TrailFormat;
+use crate::error::SimError;
+use crate::model::Model;
+use crate::parameters;
+use crate::parameters::{Kind, Parameter, Value};
+use crate::screenshot;
+#[cfg(feature = "ui")]
+use imgui::{im_str, Condition, MouseButton, Slider, Ui, Window};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::collections::HashMap;
+use std::error::Error;
+use std::path::Path;
+use std::sync::Arc;
+use vulkano::buffer::{BufferUsage, CpuAccessibleBuffer, DeviceLocalBuffer};
+use vulkano::command_buffer::{AutoCommandBuffer, AutoCommandBufferBuilder};
+use vulkano::descriptor::descriptor_set::{DescriptorSet, PersistentDescriptorSet};
+use vulkano::descriptor::PipelineLayoutAbstract;
+use vulkano::device::{Device, Queue};
+use vulkano::format::Format;
+use vulkano::image::{Dimensions, StorageImage};
+use vulkano::pipeline::ComputePipeline;
+use vulkano::sync;
+use vulkano::sync::GpuFuture;
+
+/// Every step runs this many updates, so patterns grow at a watchable speed. It is even, so
+/// every step ends in the same state image.
+const UPDATES_PER_STEP: u32 = 8;
+
+/// The keyword of the png text chunk the state of a screenshot is stored in.
+pub const REACTION_KEYWORD: &str = "slime:reaction";
+
+/// The parts of the reaction-diffusion that are fixed once it has been created.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ReactionConfig {
+    pub width: u32,
+    pub height: u32,
+    /// Seed for where the first spots of B are placed.
+    pub seed: u64,
+}
+
+/// Where B is painted in while the mouse is held down.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Brush {
+    /// In pixels.
+    pub centre: [f32; 2],
+    pub radius: f32,
+}
+
+/// The parameters of the Gray-Scott model, which can be changed every step.
+///
+/// Two chemicals, A and B, diffuse at their own rate. A is fed in at the `feed` rate, B is
+/// removed at the `kill` rate, and B turns A into more B where they meet.
+/// Everything but the brush is in `PARAMETERS`, which also makes up its json.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ReactionParameters {
+    pub feed: f32,
+    pub kill: f32,
+    pub diffusion_a: f32,
+    pub diffusion_b: f32,
+    /// Per update. Above 1 the simulation becomes unstable.
+    pub delta_time: f32,
+    /// Not saved, the brush only paints while the mouse is held down.
+    pub brush: Option<Brush>,
+}
+
+/// The parameters of the reaction-diffusion, in the order they are shown in.
+/// The feed and kill rates default to those of the Coral preset.
+pub static PARAMETERS: [Parameter<ReactionParameters>; 5] = [
+    Parameter {
+        key: "feed",
+        flag: "feed",
+        label: "Feed",
+        units: "",
+        tooltip: "How fast A is fed in.",
+        group: "Reaction",
+        kind: Kind::Float,
+        range: (0.0, 0.1),
+        default: Value::Float(0.0545),
+        get: |reaction| Value::Float(reaction.feed),
+        set: |reaction, value| reaction.feed = value.as_float(),
+    },
+    Parameter {
+        key: "kill",
+        flag: "kill",
+        label: "Kill",
+        units: "",
+        tooltip: "How fast B is removed.",
+        group: "Reaction",
+        kind: Kind::Float,
+        range: (0.0, 0.1),
+        default: Value::Float(0.062),
+        get: |reaction| Value::Float(reaction.kill),
+        set: |reaction, value| reaction.kill = value.as_float(),
+    },
+    Parameter {
+        key: "diffusion_a",
+        flag: "diffusion-a",
+        label: "Diffusion A",
+        units: "",
+        tooltip: "How fast A spreads.",
+        group: "Reaction",
+        kind: Kind::Float,
+        range: (0.0, 1.0),
+        default: Value::Float(1.0),
+        get: |reaction| Value::Float(reaction.diffusion_a),
+        set: |reaction, value| reaction.diffusion_a = value.as_float(),
+    },
+    Parameter {
+        key: "diffusion_b",
+        flag: "diffusion-b",
+        label: "Diffusion B",
+        units: "",
+        tooltip: "How fast B spreads.",
+        group: "Reaction",
+        kind: Kind::Float,
+        range: (0.0, 1.0),
+        default: Value::Float(0.5),
+        get: |reaction| Value::Float(reaction.diffusion_b),
+        set: |reaction, value| reaction.diffusion_b = value.as_float(),
+    },
+    Parameter {
+        key: "delta_time",
+        flag: "reaction-time-step",
+        label: "Time step",
+        units: "",
+        tooltip: "Time step of every update. Above 1 the simulation becomes unstable.",
+        group: "Reaction",
+        kind: Kind::Float,
+        range: (0.1, 1.0),
+        default: Value::Float(1.0),
+        get: |reaction| Value::Float(reaction.delta_time),
+        set: |reaction, value| reaction.delta_time = value.as_float(),
+    },
+];
+
+impl Default for ReactionParameters {
+    /// Every parameter at its `default`, without painting.
+    fn default() -> Self {
+        // The values of the registry are filled in below.
+        let mut parameters = ReactionParameters {
+            feed: 0.0,
+            kill: 0.0,
+            diffusion_a: 0.0,
+            diffusion_b: 0.0,
+            delta_time: 0.0,
+            brush: None,
+        };
+        parameters::set_defaults(&PARAMETERS, &mut parameters);
+        parameters
+    }
+}
+
+impl Serialize for ReactionParameters {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        parameters::serialize(&PARAMETERS, self, serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for ReactionParameters {
+    /// Missing fields get their default value, and values outside of their range are clamped.
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        parameters::deserialize(&PARAMETERS, deserializer)
+    }
+}
+
+impl ReactionParameters {
+    fn shader_parameters(&self) -> reaction_shader::ty::Parameters {
+        let (brush_centre, brush_radius) = match self.brush {
+            Some(brush) => (brush.centre, brush.radius),
+            None => ([0.0; 2], 0.0),
+        };
+        reaction_shader::ty::Parameters {
+            brush_centre,
+            brush_radius,
+            feed: self.feed,
+            kill: self.kill,
+            diffusion_a: self.diffusion_a,
+            diffusion_b: self.diffusion_b,
+            delta_time: self.delta_time,
+        }
+    }
+}
+
+/// Feed and kill rates that give one of the classic patterns.
+pub struct ReactionPreset {
+    pub name: &'static str,
+    pub feed: f32,
+    pub kill: f32,
+}
+
+pub const PRESETS: [ReactionPreset; 6] = [
+    ReactionPreset {
+        name: "Mitosis",
+        feed: 0.0367,
+        kill: 0.0649,
+    },
+    ReactionPreset {
+        name: "Coral",
+        feed: 0.0545,
+        kill: 0.062,
+    },
+    ReactionPreset {
+        name: "Worms",
+        feed: 0.078,
+        kill: 0.061,
+    },
+    ReactionPreset {
+        name: "Maze",
+        feed: 0.029,
+        kill: 0.057,
+    },
+    ReactionPreset {
+        name: "Holes",
+        feed: 0.039,
+        kill: 0.058,
+    },
+    ReactionPreset {
+        name: "Waves",
+        feed: 0.014,
+        kill: 0.045,
+    },
+];
+
+/// The preset with the given name, ignoring case.
+pub fn find_preset(name: &str) -> Option<&'static ReactionPreset> {
+    PRESETS
+        .iter()
+        .find(|preset| preset.name.eq_ignore_ascii_case(name))
+}
+
+/// Everything needed to get back to the state of a screenshot, like `ScreenshotInfo`.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ReactionInfo {
+    pub config: ReactionConfig,
+    pub parameters: ReactionParameters,
+    pub step: u64,
+}
+
+impl ReactionInfo {
+    /// Reads the info embedded in a screenshot of the reaction-diffusion.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<ReactionInfo, Box<dyn Error>> {
+        ReactionInfo::from_texts(&screenshot::read_text_chunks(&std::fs::read(path)?)?)
+    }
+
+    /// The info in the text chunks of a png file, as read by `screenshot::read_text_chunks`.
+    pub fn from_texts(texts: &HashMap<String, String>) -> Result<ReactionInfo, Box<dyn Error>> {
+        let info = texts
+            .get(REACTION_KEYWORD)
+            .ok_or("The image has no embedded reaction-diffusion parameters")?;
+        Ok(serde_json::from_str(info)?)
+    }
+}
+
+/// Gray-Scott reaction-diffusion on two float channels.
+///
+/// Shows the amount of B, which is what the colormap of the post-processing is applied to.
+pub struct ReactionDiffusion {
+    pub config: ReactionConfig,
+    pub device: Arc<Device>,
+    pub queue: Arc<Queue>,
+    /// The queue the display image is shared with, kept to recreate it when resizing.
+    display_queue: Arc<Queue>,
+    /// A in red and B in green. Each update reads one and writes the other.
+    state_images: [Arc<StorageImage<Format>>; 2],
+    /// B in `device::DISPLAY_FORMAT`, converted after every batch of steps.
+    display_image: Arc<StorageImage<Format>>,
+    parameters_buffer: Arc<DeviceLocalBuffer<reaction_shader::ty::Parameters>>,
+    /// Runs `UPDATES_PER_STEP` updates, starting and ending in the first state image.
+    step_command: Arc<AutoCommandBuffer>,
+    /// Converts the first state image to the display image.
+    convert_command: Arc<AutoCommandBuffer>,
+}
+
+impl ReactionDiffusion {
+    pub fn init(
+        device: Arc<Device>,
+        queue: Arc<Queue>,
+        config: &ReactionConfig,
+    ) -> Result<ReactionDiffusion, SimError> {
+        ReactionDiffusion::with_display_queue(device, queue.clone(), &queue, config)
+    }
+
+    /// Like `Simulation::with_display_queue`, the display image is shared with
+    /// `display_queue`.
+    pub fn with_display_queue(
+        device: Arc<Device>,
+        queue: Arc<Queue>,
+        display_queue: &Arc<Queue>,
+        config: &ReactionConfig,
+    ) -> Result<ReactionDiffusion, SimError> {
+        let image_size = Dimensions::Dim2d {
+            width: config.width,
+            height: config.height,
+        };
+
+        // The chemicals need the precision of floats, and rgba32f is the float format
+        // every device can store to.
+        device::check_trail_format(device.physical_device(), TrailFormat::Rgba32f)?;
+        let state_format = TrailFormat::Rgba32f.format();
+        let state_images = [
+            StorageImage::new(
+                device.clone(),
+                image_size,
+                state_format,
+                Some(queue.family()),
+            )?,
+            StorageImage::new(
+                device.clone(),
+                image_size,
+                state_format,
+                Some(queue.family()),
+            )?,
+        ];
+
+        let mut display_families = vec![queue.family()];
+        if display_queue.family().id() != queue.family().id() {
+            display_families.push(display_queue.family());
+        }
+        let display_image = StorageImage::new(
+            device.clone(),
+            image_size,
+            device::DISPLAY_FORMAT,
+            display_families,
+        )?;
+
+        let parameters_buffer = DeviceLocalBuffer::new(
+            device.clone(),
+            BufferUsage {
+                uniform_buffer: true,
+                transfer_destination: true,
+                ..BufferUsage::none()
+            },
+            Some(queue.family()),
+        )?;
+
+        let reaction_shader = reaction_shader::Shader::load(device.clone())?;
+        let reaction_pipeline = Arc::new(ComputePipeline::new(
+            device.clone(),
+            &reaction_shader.main_entry_point(),
+            &(),
+            None,
+        )?);
+        let reaction_set = |from: &Arc<StorageImage<Format>>,
+                            to: &Arc<StorageImage<Format>>|
+         -> Result<Arc<dyn DescriptorSet + Send + Sync>, SimError> {
+            Ok(Arc::new(
+                PersistentDescriptorSet::start(
+                    reaction_pipeline.descriptor_set_layout(0).unwrap().clone(),
+                )
+                .add_image(from.clone())?
+                .add_image(to.clone())?
+                .add_buffer(parameters_buffer.clone())?
+                .build()?,
+            ))
+        };
+        let reaction_sets = [
+            reaction_set(&state_images[0], &state_images[1])?,
+            reaction_set(&state_images[1], &state_images[0])?,
+        ];
+
+        let convert_shader = reaction_convert_shader::Shader::load(device.clone())?;
+        let convert_pipeline = Arc::new(ComputePipeline::new(
+            device.clone(),
+            &convert_shader.main_entry_point(),
+            &(),
+            None,
+        )?);
+        let convert_set: Arc<dyn DescriptorSet + Send + Sync> = Arc::new(
+            PersistentDescriptorSet::start(
+                convert_pipeline.descriptor_set_layout(0).unwrap().clone(),
+            )
+            .add_image(state_images[0].clone())?
+            .add_image(display_image.clone())?
+            .build()?,
+        );
+
+        let groups = [(config.width + 7) / 8, (config.height + 7) / 8, 1];
+
+        let mut step_builder =
+            AutoCommandBufferBuilder::primary_simultaneous_use(device.clone(), queue.family())?;
+        for update in 0..UPDATES_PER_STEP {
+            step_builder.dispatch(
+                groups,
+                reaction_pipeline.clone(),
+                reaction_sets[(update % 2) as usize].clone(),
+                (),
+            )?;
+        }
+        let step_command = Arc::new(step_builder.build()?);
+
+        let mut convert_builder =
+            AutoCommandBufferBuilder::primary_simultaneous_use(device.clone(), queue.family())?;
+        convert_builder.dispatch(groups, convert_pipeline, convert_set, ())?;
+        let convert_command = Arc::new(convert_builder.build()?);
+
+        let mut reaction = ReactionDiffusion {
+            config: *config,
+            device,
+            queue,
+            display_queue: display_queue.clone(),
+            state_images,
+            display_image,
+            parameters_buffer,
+            step_command,
+            convert_command,
+        };
+        reaction.reset(config.seed)?;
+
+        Ok(reaction)
+    }
+}
+
+impl Model for ReactionDiffusion {
+    type Config = ReactionConfig;
+    type Parameters = ReactionParameters;
+
+    fn init(
+        device: Arc<Device>,
+        queue: Arc<Queue>,
+        display_queue: &Arc<Queue>,
+        config: &ReactionConfig,
+    ) -> Result<ReactionDiffusion, SimError> {
+        ReactionDiffusion::with_display_queue(device, queue, display_queue, config)
+    }
+
+    fn device(&self) -> &Arc<Device> {
+        &self.device
+    }
+
+    fn queue(&self) -> &Arc<Queue> {
+        &self.queue
+    }
+
+    fn size(&self) -> [u32; 2] {
+        [self.config.width, self.config.height]
+    }
+
+    fn steps_after<F>(
+        &mut self,
+        future: F,
+        steps: u32,
+        parameters: &ReactionParameters,
+    ) -> Result<Box<dyn GpuFuture>, SimError>
+    where
+        F: GpuFuture + 'static,
+    {
+        let mut builder = AutoCommandBufferBuilder::new(self.device.clone(), self.queue.family())?;
+        builder.update_buffer(
+            self.parameters_buffer.clone(),
+            parameters.shader_parameters(),
+        )?;
+        let mut future = future
+            .then_execute(self.queue.clone(), builder.build()?)?
+            .boxed();
+
+        for _ in 0..steps {
+            future = future
+                .then_execute(self.queue.clone(), self.step_command.clone())?
+                .boxed();
+        }
+
+        Ok(future
+            .then_execute(self.queue.clone(), self.convert_command.clone())?
+            .boxed())
+    }
+
+    fn result_image(&self) -> &Arc<StorageImage<Format>> {
+        &self.display_image
+    }
+
+    /// Fills everything with A, and places a few random squares of B to start from.
+    fn reset(&mut self, seed: u64) -> Result<(), SimError> {
+        let ReactionConfig { width, height, .. } = self.config;
+        let mut state = vec![0.0f32; width as usize * height as usize * 4];
+        for pixel in state.chunks_mut(4) {
+            pixel[0] = 1.0;
+        }
+
+        let mut rng = StdRng::seed_from_u64(seed);
+        for _ in 0..20 {
+            let size = rng.gen_range(4..16).min(width).min(height);
+            let left = rng.gen_range(0..=width - size);
+            let top = rng.gen_range(0..=height - size);
+            for y in top..top + size {
+                for x in left..left + size {
+                    state[((y * width + x) * 4 + 1) as usize] = 1.0;
+                }
+            }
+        }
+
+        let buffer = CpuAccessibleBuffer::from_iter(
+            self.device.clone(),
+            BufferUsage::transfer_source(),
+            false,
+            state.into_iter(),
+        )?;
+        let mut builder = AutoCommandBufferBuilder::new(self.device.clone(), self.queue.family())?;
+        builder.copy_buffer_to_image(buffer, self.state_images[0].clone())?;
+        let command_buffer = builder.build()?;
+
+        sync::now(self.device.clone())
+            .then_execute(self.queue.clone(), command_buffer)?
+            .then_execute(self.queue.clone(), self.convert_command.clone())?
+            .then_signal_fence_and_flush()?
+            .wait(None)?;
+
+        Ok(())
+    }
+
+    fn resize(&mut self, width: u32, height: u32) -> Result<(), SimError> {
+        let config = ReactionConfig {
+            width,
+            height,
+            ..self.config
+        };
+        let display_queue = self.display_queue.clone();
+        *self = ReactionDiffusion::with_display_queue(
+            self.device.clone(),
+            self.queue.clone(),
+            &display_queue,
+            &config,
+        )?;
+        Ok(())
+    }
+
+    fn screenshot_texts(
+        &self,
+        parameters: &ReactionParameters,
+        step: u64,
+    ) -> Result<Vec<(&'static str, String)>, SimError> {
+        let info = ReactionInfo {
+            config: self.config,
+            parameters: *parameters,
+            step,
+        };
+        Ok(vec![(REACTION_KEYWORD, serde_json::to_string(&info)?)])
+    }
+
+    /// The resolution can only be restored by starting at the resolution of the screenshot.
+    fn restore(
+        &mut self,
+        texts: &HashMap<String, String>,
+        parameters: &mut ReactionParameters,
+    ) -> Result<String, Box<dyn Error>> {
+        let info = ReactionInfo::from_texts(texts)?;
+        *parameters = info.parameters;
+        self.config.seed = info.config.seed;
+        self.reset(info.config.seed)?;
+
+        if info.config.width != self.config.width || info.config.height != self.config.height {
+            Ok(format!(
+                "Restored the parameters of step {}. Start with --resolution {}x{} \
+                 to also restore the resolution.",
+                info.step, info.config.width, info.config.height
+            ))
+        } else {
+            Ok(format!("Restored the parameters of step {}", info.step))
+        }
+    }
+}
+
+/// The parameters of the reaction-diffusion, its presets and the brush.
+#[cfg(feature = "ui")]
+pub struct ReactionWindow {
+    pub brush_radius: f32,
+}
+
+#[cfg(feature = "ui")]
+impl Default for ReactionWindow {
+    fn default() -> Self {
+        ReactionWindow::new()
+    }
+}
+
+#[cfg(feature = "ui")]
+impl ReactionWindow {
+    pub fn new() -> ReactionWindow {
+        ReactionWindow { brush_radius: 10.0 }
+    }
+
+    /// Also paints B with the brush while the left mouse button is held down outside of the
+    /// windows.
+    pub fn draw(&mut self, ui: &Ui, parameters: &mut ReactionParameters) {
+        let window = self;
+
+        Window::new(im_str!("Reaction-diffusion"))
+            .position([10.0, 10.0], Condition::FirstUseEver)
+            .size([300.0, 330.0], Condition::FirstUseEver)
+            .build(ui, || {
+                ui.text("Presets");
+                for (index, preset) in PRESETS.iter().enumerate() {
+                    if index % 3 != 0 {
+                        ui.same_line(0.0);
+                    }
+                    let label = imgui::ImString::new(preset.name);
+                    if ui.button(&label, [80.0, 0.0]) {
+                        parameters.feed = preset.feed;
+                        parameters.kill = preset.kill;
+                    }
+                }
+
+                parameters::draw_parameters(ui, &PARAMETERS, parameters);
+
+                ui.separator();
+                ui.text("Drag on the image to paint B.");
+                Slider::new(im_str!("Brush radius"))
+                    .range(1.0..=100.0)
+                    .build(ui, &mut window.brush_radius);
+            });
+
+        // The image is shown one pixel per physical pixel, imgui uses logical ones.
+        let io = ui.io();
+        let painting = ui.is_mouse_down(MouseButton::Left) && !io.want_capture_mouse;
+        parameters.brush = if painting {
+            let scale = io.display_framebuffer_scale;
+            Some(Brush {
+                centre: [io.mouse_pos[0] * scale[0], io.mouse_pos[1] * scale[1]],
+                radius: window.brush_radius,
+            })
+        } else {
+            None
+        };
+    }
+}
+
+mod reaction_shader {
+    vulkano_shaders::shader! {
+        ty: "compute",
+        src:
+"
+#version 450
+
+layout(local_size_x = 8, local_size_y = 8, local_size_z = 1) in;
+
+// A in red and B in green.
+layout(set = 0, binding = 0, rgba32f) uniform readonly image2D in_img;
+layout(set = 0, binding = 1, rgba32f) uniform writeonly image2D out_img;
+
+// Written before the steps that use them, like the parameters of the agents.
+layout(set = 0, binding = 2) uniform Parameters {
+    // In pixels. Nothing is painted while the radius is 0.
+    vec2 brush_centre;
+    float brush_radius;
+    float feed;
+    float kill;
+    float diffusion_a;
+    float diffusion_b;
+    float delta_time;
+} params;
+
+void main() {
+    ivec2 pos = ivec2(gl_GlobalInvocationID.xy);
+    ivec2 size = imageSize(in_img);
+    if (pos.x >= size.x || pos.y >= size.y) {
+        return;
+    }
+
+    // Direct neighbours weigh 0.2 and diagonal ones 0.05. The edges wrap around.
+    vec2 centre = imageLoad(in_img, pos).xy;
+    vec2 laplacian = -centre;
+    for (int x = -1; x <= 1; x++) {
+        for (int y = -1; y <= 1; y++) {
+            if (x == 0 && y == 0) {
+                continue;
+            }
+            float weight = (x == 0 || y == 0) ? 0.2 : 0.05;
+            laplacian += weight * imageLoad(in_img, (pos + ivec2(x, y) + size) % size).xy;
+        }
+    }
+
+    float a = centre.x;
+    float b = centre.y;
+    float reaction = a * b * b;
+    a += (params.diffusion_a * laplacian.x - reaction + params.feed * (1.0 - a)) * params.delta_time;
+    b += (params.diffusion_b * laplacian.y + reaction - (params.kill + params.feed) * b) * params.delta_time;
+
+    if (params.brush_radius > 0.0 && distance(vec2(pos), params.brush_centre) < params.brush_radius) {
+        b = 1.0;
+    }
+
+    imageStore(out_img, pos, vec4(clamp(a, 0.0, 1.0), clamp(b, 0.0, 1.0), 0.0, 1.0));
+}
+"
+    }
+}
+
+mod reaction_convert_shader {
+    vulkano_shaders::shader! {
+        ty: "compute",
+        src:
+"
+#version 450
+
+layout(local_size_x = 8, local_size_y = 8, local_size_z = 1) in;
+
+layout(set = 0, binding = 0, rgba32f) uniform readonly image2D state_img;
+layout(set = 0, binding = 1, rgba8) uniform writeonly image2D display_img;
+
+void main() {
+    ivec2 pos = ivec2(gl_GlobalInvocationID.xy);
+    if (pos.x >= imageSize(state_img).x || pos.y >= imageSize(state_img).y) {
+        return;
+    }
+
+    // B rarely goes above 0.4, so it is stretched to use the whole range.
+    float b = imageLoad(state_img, pos).y;
+    imageStore(display_img, pos, vec4(vec3(clamp(b * 2.5, 0.0, 1.0)), 1.0));
+}
+"
+    }
+}
@@ -0,0 +1,554 @@
+use crate::colony;
+use crate::colony::ColonySettings;
+use crate::model::Model;
+use crate::parameters;
+use crate::parameters::{Kind, Parameter, ParameterSet};
+use crate::post;
+use crate::post::PostSettings;
+use crate::preset::Preset;
+use crate::screenshot;
+use crate::screenshot::ScreenshotInfo;
+use crate::simulation::{Simulation, SimulationConfig};
+use crate::statistics::StatisticsRecorder;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::io::{BufRead, BufReader, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::thread;
+
+/// Preset fields that are fixed once the simulation has been created.
+const FIXED_PARAMETERS: [&str; 5] = ["width", "height", "agent_amount", "trail_format", "seed"];
+
+/// Everything a remote client can read and change, each described by its own registry:
+/// `parameters::PARAMETERS`, `colony::PARAMETERS` and `post::PARAMETERS`.
+pub struct Settings<'a> {
+    pub parameters: &'a mut ParameterSet,
+    pub colony: &'a mut ColonySettings,
+    pub post: &'a mut PostSettings,
+}
+
+/// A single request, sent as one line of json.
+/// For example: `{"command": "set", "parameter": "agent_speed", "value": 120.0}`
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(tag = "command", rename_all = "snake_case")]
+pub enum Request {
+    /// Returns all parameters, with the same names as in a preset file.
+    /// The colony and post-processing settings are in its `colony` and `post` objects.
+    GetAll,
+    /// Describes the parameters that can be set: their units, range, default and tooltip.
+    Describe,
+    Get {
+        parameter: String,
+    },
+    Set {
+        parameter: String,
+        value: Value,
+    },
+    /// Moves `count` agents into a circle around `x`, `y`, which have to be inside the trails.
+    Spawn {
+        x: f32,
+        y: f32,
+        radius: u32,
+        count: u32,
+    },
+    /// Places the agents back in the centre and clears the trails.
+    Reset {
+        seed: Option<u64>,
+    },
+    /// Saves the trail image as a png with the parameters embedded, like a screenshot.
+    /// The path is a file name in the snapshot directory of the server.
+    Snapshot {
+        path: String,
+    },
+    /// Returns the most recent statistics.
+    Stats,
+}
+
+impl Request {
+    /// Whether the gpu has to be done with the previous frame before handling this request.
+    pub fn needs_idle_gpu(&self) -> bool {
+        matches!(
+            self,
+            Request::Spawn { .. } | Request::Reset { .. } | Request::Snapshot { .. }
+        )
+    }
+}
+
+/// The answer to a request, sent back as one line of json.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Response {
+    pub ok: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub value: Option<Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl Response {
+    pub fn ok(value: Option<Value>) -> Response {
+        Response {
+            ok: true,
+            value,
+            error: None,
+        }
+    }
+
+    pub fn error<S: Into<String>>(message: S) -> Response {
+        Response {
+            ok: false,
+            value: None,
+            error: Some(message.into()),
+        }
+    }
+}
+
+/// A request that still has to be answered by the main loop.
+pub struct PendingRequest {
+    pub request: Request,
+    respond_to: Sender<Response>,
+}
+
+impl PendingRequest {
+    pub fn respond(self, response: Response) {
+        // The connection might already be closed, in which case nobody cares about the answer.
+        let _ = self.respond_to.send(response);
+    }
+}
+
+/// Accepts json-over-tcp connections on a background thread.
+/// The requests are handed to the main loop through `poll`.
+pub struct RemoteServer {
+    requests: Receiver<PendingRequest>,
+    address: SocketAddr,
+    snapshot_dir: PathBuf,
+}
+
+impl RemoteServer {
+    /// Snapshots can only be saved in `snapshot_dir`, which is created if it does not exist.
+    pub fn start<A: ToSocketAddrs>(
+        address: A,
+        snapshot_dir: PathBuf,
+    ) -> std::io::Result<RemoteServer> {
+        std::fs::create_dir_all(&snapshot_dir)?;
+        let listener = TcpListener::bind(address)?;
+        let address = listener.local_addr()?;
+
+        let (sender, requests) = channel();
+
+        thread::spawn(move || {
+            // A connection that fails to be accepted only affects its client,
+            // which can connect again.
+            for stream in listener.incoming().flatten() {
+                let sender = sender.clone();
+                thread::spawn(move || handle_connection(stream, sender));
+            }
+        });
+
+        Ok(RemoteServer {
+            requests,
+            address,
+            snapshot_dir,
+        })
+    }
+
+    /// The address the server accepts connections on, with the actual port when it was 0.
+    pub fn local_addr(&self) -> SocketAddr {
+        self.address
+    }
+
+    pub fn snapshot_dir(&self) -> &Path {
+        &self.snapshot_dir
+    }
+
+    /// All requests that came in since the last call.
+    pub fn poll(&self) -> Vec<PendingRequest> {
+        self.requests.try_iter().collect()
+    }
+}
+
+fn handle_connection(stream: TcpStream, requests: Sender<PendingRequest>) {
+    let mut writer = match stream.try_clone() {
+        Ok(writer) => writer,
+        Err(_) => return,
+    };
+
+    for line in BufReader::new(stream).lines() {
+        let line = match line {
+            Ok(line) => line,
+            Err(_) => return,
+        };
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let response = match serde_json::from_str::<Request>(&line) {
+            Ok(request) => {
+                let (respond_to, response) = channel();
+                if requests
+                    .send(PendingRequest {
+                        request,
+                        respond_to,
+                    })
+                    .is_err()
+                {
+                    // The main loop has stopped.
+                    return;
+                }
+                response
+                    .recv()
+                    .unwrap_or_else(|_| Response::error("No response from the simulation"))
+            }
+            Err(e) => Response::error(format!("Invalid request: {}", e)),
+        };
+
+        let mut json = serde_json::to_string(&response).unwrap();
+        json.push('\n');
+        if writer.write_all(json.as_bytes()).is_err() {
+            return;
+        }
+    }
+}
+
+/// Handles a request in the main loop. Resetting starts `step` over.
+/// Requests for which `needs_idle_gpu` is true should only be handled while the gpu is idle.
+pub fn handle(
+    request: &Request,
+    simulation: &mut Simulation,
+    settings: &mut Settings,
+    statistics: &mut StatisticsRecorder,
+    step: &mut u64,
+    snapshot_dir: &Path,
+) -> Response {
+    match request {
+        Request::Spawn {
+            x,
+            y,
+            radius,
+            count,
+        } => {
+            let (width, height) = (simulation.config.width, simulation.config.height);
+            if !(0.0..width as f32).contains(x) || !(0.0..height as f32).contains(y) {
+                return Response::error(format!(
+                    "({}, {}) is outside of the trails, which are {}x{}",
+                    x, y, width, height
+                ));
+            }
+            match simulation.spawn([*x, *y], *radius, *count) {
+                Ok(()) => Response::ok(None),
+                Err(e) => Response::error(format!("Failed to spawn agents: {}", e)),
+            }
+        }
+        Request::Reset { seed } => match simulation.reset(seed.unwrap_or_else(rand::random)) {
+            Ok(()) => {
+                *step = 0;
+                Response::ok(None)
+            }
+            Err(e) => Response::error(format!("Failed to reset: {}", e)),
+        },
+        Request::Snapshot { path } => {
+            let full_path = match snapshot_path(snapshot_dir, path) {
+                Ok(full_path) => full_path,
+                Err(e) => return Response::error(e),
+            };
+            let info = ScreenshotInfo {
+                preset: Preset::from_parameters(
+                    &simulation.config,
+                    &settings.parameters.sim,
+                    &settings.parameters.fade,
+                ),
+                step: *step,
+                post: None,
+            };
+            match simulation.read_result_image() {
+                Ok(image) => match screenshot::save(&full_path, &image, &info) {
+                    Ok(()) => Response::ok(Some(Value::String(full_path.display().to_string()))),
+                    Err(e) => Response::error(format!("Failed to save '{}': {}", path, e)),
+                },
+                Err(e) => Response::error(format!("Failed to read the trails: {}", e)),
+            }
+        }
+        Request::Stats => {
+            if !statistics.enabled {
+                statistics.enabled = true;
+                return Response::error(
+                    "Statistics were not being measured. They are now, try again next frame.",
+                );
+            }
+            match statistics.latest() {
+                Some(latest) => Response::ok(Some(serde_json::to_value(latest).unwrap())),
+                None => Response::error("No statistics measured yet, try again next frame."),
+            }
+        }
+        _ => handle_parameters(request, &simulation.config, settings),
+    }
+}
+
+/// Handles the requests that only read or change the parameters, which need no gpu.
+fn handle_parameters(
+    request: &Request,
+    config: &SimulationConfig,
+    settings: &mut Settings,
+) -> Response {
+    let mut preset =
+        Preset::from_parameters(config, &settings.parameters.sim, &settings.parameters.fade);
+    preset.colony = Some(*settings.colony);
+    preset.post = Some(*settings.post);
+    let values = serde_json::to_value(&preset).unwrap();
+
+    match request {
+        Request::GetAll => Response::ok(Some(values)),
+        Request::Get { parameter } => {
+            let name = parameter.as_str();
+            let found = values
+                .get(name)
+                .or_else(|| values["colony"].get(name))
+                .or_else(|| values["post"].get(name));
+            match found {
+                Some(value) => Response::ok(Some(value.clone())),
+                None => Response::error(format!("Unknown parameter '{}'", parameter)),
+            }
+        }
+        Request::Describe => {
+            let described = (parameters::PARAMETERS.iter().map(describe))
+                .chain(colony::PARAMETERS.iter().map(describe))
+                .chain(post::PARAMETERS.iter().map(describe));
+            Response::ok(Some(Value::Array(described.collect())))
+        }
+        Request::Set { parameter, value } => {
+            let response = set_in(
+                &parameters::PARAMETERS,
+                settings.parameters,
+                parameter,
+                value,
+            )
+            .or_else(|| set_in(&colony::PARAMETERS, settings.colony, parameter, value))
+            .or_else(|| set_in(&post::PARAMETERS, settings.post, parameter, value));
+            match response {
+                Some(response) => response,
+                None if FIXED_PARAMETERS.contains(&parameter.as_str()) => Response::error(format!(
+                    "'{}' can not be changed while the simulation is running",
+                    parameter
+                )),
+                None => Response::error(format!("Unknown parameter '{}'", parameter)),
+            }
+        }
+        _ => Response::error("This request needs the simulation"),
+    }
+}
+
+/// Sets the parameter if it is in `table`, or returns `None` if it is not.
+fn set_in<T>(
+    table: &'static [Parameter<T>],
+    target: &mut T,
+    key: &str,
+    json: &Value,
+) -> Option<Response> {
+    let found = parameters::find_in(table, key)?;
+    Some(match found.from_json(json) {
+        // Out of range values are clamped, the response tells what was actually set.
+        Ok(new_value) => {
+            found.set_value(target, new_value);
+            Response::ok(Some(found.to_json(found.value(target))))
+        }
+        Err(e) => Response::error(format!("Invalid value for '{}': {}", key, e)),
+    })
+}
+
+/// Where a snapshot with the given name is saved. Only plain png file names are accepted,
+/// so that remote clients can not write anywhere else.
+fn snapshot_path(snapshot_dir: &Path, name: &str) -> Result<PathBuf, String> {
+    let file_name = Path::new(name).file_name();
+    if file_name.map_or(true, |file_name| file_name != name) {
+        return Err(format!(
+            "'{}' is not a plain file name, snapshots are saved in '{}'",
+            name,
+            snapshot_dir.display()
+        ));
+    }
+    let is_png = Path::new(name)
+        .extension()
+        .map_or(false, |e| e.eq_ignore_ascii_case("png"));
+    if !is_png {
+        return Err(format!("'{}' is not a .png file name", name));
+    }
+    Ok(snapshot_dir.join(name))
+}
+
+/// A parameter from the registry, for the `describe` request.
+fn describe<T>(parameter: &Parameter<T>) -> Value {
+    let kind = match parameter.kind {
+        Kind::Float => "float",
+        Kind::Int => "int",
+        Kind::Color => "color",
+        Kind::Bool => "bool",
+    };
+    serde_json::json!({
+        "parameter": parameter.key,
+        "label": parameter.label,
+        "kind": kind,
+        "units": parameter.units,
+        "min": parameter.range.0,
+        "max": parameter.range.1,
+        "default": parameter.to_json(parameter.default),
+        "tooltip": parameter.tooltip,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::device::DeviceSelector;
+    use crate::headless;
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn requests_roundtrip_over_tcp() {
+        let server = RemoteServer::start("127.0.0.1:0", std::env::temp_dir()).unwrap();
+        let address = server.local_addr();
+
+        let requests = vec![
+            r#"{"command": "describe"}"#,
+            r#"{"command": "set", "parameter": "agent_speed", "value": 120.0}"#,
+            r#"{"command": "get", "parameter": "agent_speed"}"#,
+            r#"{"command": "set", "parameter": "agent_speed", "value": 5000.0}"#,
+            r#"{"command": "set", "parameter": "width", "value": 10}"#,
+            r#"{"command": "get", "parameter": "no_such_parameter"}"#,
+            r#"{"command": "get_all"}"#,
+            r#"{"command": "set", "parameter": "pile_amount", "value": 500}"#,
+            r#"{"command": "set", "parameter": "bloom", "value": true}"#,
+            r#"{"command": "get", "parameter": "exposure"}"#,
+        ];
+        let request_count = requests.len();
+
+        let client = thread::spawn(move || {
+            let stream = TcpStream::connect(address).unwrap();
+            let mut writer = stream.try_clone().unwrap();
+            let mut lines = BufReader::new(stream).lines();
+            let mut send = |request: &str| -> Response {
+                writeln!(writer, "{}", request).unwrap();
+                serde_json::from_str(&lines.next().unwrap().unwrap()).unwrap()
+            };
+
+            // Invalid requests are answered without reaching the main loop.
+            let invalid = send(r#"{"command": "fly"}"#);
+            assert!(!invalid.ok);
+
+            requests.into_iter().map(send).collect::<Vec<_>>()
+        });
+
+        // Answer the requests like the main loop does.
+        let config = SimulationConfig::default();
+        let mut parameters = ParameterSet::default();
+        let mut colony_settings = ColonySettings::default();
+        let mut post_settings = PostSettings::default();
+        let mut settings = Settings {
+            parameters: &mut parameters,
+            colony: &mut colony_settings,
+            post: &mut post_settings,
+        };
+        let start = Instant::now();
+        let mut handled = 0;
+        while handled < request_count {
+            assert!(start.elapsed() < Duration::from_secs(10), "timed out");
+            for pending in server.poll() {
+                let response = handle_parameters(&pending.request, &config, &mut settings);
+                pending.respond(response);
+                handled += 1;
+            }
+            thread::sleep(Duration::from_millis(1));
+        }
+        let responses = client.join().unwrap();
+
+        let described = responses[0].value.as_ref().unwrap().as_array().unwrap();
+        let registered =
+            parameters::PARAMETERS.len() + colony::PARAMETERS.len() + post::PARAMETERS.len();
+        assert_eq!(described.len(), registered);
+        assert!(described.iter().any(|d| d["parameter"] == "agent_speed"));
+        assert!(described
+            .iter()
+            .any(|d| d["parameter"] == "bloom" && d["kind"] == "bool"));
+
+        assert_eq!(responses[1].value, Some(serde_json::json!(120.0)));
+        assert_eq!(responses[2].value, Some(serde_json::json!(120.0)));
+        // Clamped to the range of the parameter.
+        assert_eq!(responses[3].value, Some(serde_json::json!(1000.0)));
+        assert!(!responses[4].ok);
+        assert!(!responses[5].ok);
+
+        let all = responses[6].value.as_ref().unwrap();
+        assert_eq!(all["agent_speed"], 1000.0);
+        assert_eq!(all["width"], config.width);
+        assert_eq!(all["colony"]["pile_amount"], 2000);
+        assert_eq!(all["post"]["exposure"], 1.5);
+        assert_eq!(parameters.sim.agent_speed, 1000.0);
+
+        assert_eq!(responses[7].value, Some(serde_json::json!(500)));
+        assert_eq!(colony_settings.pile_amount, 500);
+        assert_eq!(responses[8].value, Some(serde_json::json!(true)));
+        assert!(post_settings.bloom);
+        assert_eq!(responses[9].value, Some(serde_json::json!(1.5)));
+    }
+
+    #[test]
+    fn snapshots_stay_in_their_directory() {
+        let dir = Path::new("snapshots");
+        assert_eq!(snapshot_path(dir, "trails.png"), Ok(dir.join("trails.png")));
+        assert!(snapshot_path(dir, "../trails.png").is_err());
+        assert!(snapshot_path(dir, "sub/trails.png").is_err());
+        assert!(snapshot_path(dir, "/tmp/trails.png").is_err());
+        assert!(snapshot_path(dir, "..").is_err());
+        assert!(snapshot_path(dir, "").is_err());
+        assert!(snapshot_path(dir, "trails.exe").is_err());
+    }
+
+    #[test]
+    fn snapshots_after_a_random_reset_embed_the_new_seed() {
+        // Needs a vulkan device, which not every machine running the tests has.
+        let (device, queue) = match headless::init_device(&DeviceSelector::Auto) {
+            Ok(device_and_queue) => device_and_queue,
+            Err(_) => return,
+        };
+        let config = SimulationConfig {
+            width: 64,
+            height: 64,
+            agent_amount: 100,
+            seed: 1,
+            ..SimulationConfig::default()
+        };
+        let mut simulation = Simulation::init(device, queue, &config).unwrap();
+        let mut parameters = ParameterSet::default();
+        let mut colony_settings = ColonySettings::default();
+        let mut post_settings = PostSettings::default();
+        let mut settings = Settings {
+            parameters: &mut parameters,
+            colony: &mut colony_settings,
+            post: &mut post_settings,
+        };
+        let mut statistics = StatisticsRecorder::new();
+        let mut step = 10;
+        let snapshot_dir = std::env::temp_dir();
+
+        let mut handle_request = |request: Request, simulation: &mut Simulation| {
+            handle(
+                &request,
+                simulation,
+                &mut settings,
+                &mut statistics,
+                &mut step,
+                &snapshot_dir,
+            )
+        };
+        assert!(handle_request(Request::Reset { seed: None }, &mut simulation).ok);
+        let name = format!("remote_reset_{}.png", std::process::id());
+        let response = handle_request(Request::Snapshot { path: name.clone() }, &mut simulation);
+        assert!(response.ok, "{:?}", response.error);
+
+        let path = snapshot_dir.join(&name);
+        let info = screenshot::load(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(info.preset.seed, Some(simulation.config.seed));
+        assert_eq!(info.step, 0);
+    }
+}
@@ -0,0 +1,330 @@
+use crate::error::SimError;
+use crate::post::PostSettings;
+use crate::preset::Preset;
+use image::RgbaImage;
+#[cfg(feature = "ui")]
+use imgui::{im_str, Condition, Ui, Window};
+use std::collections::HashMap;
+use std::error::Error;
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use vulkano::buffer::{BufferUsage, CpuAccessibleBuffer};
+use vulkano::command_buffer::AutoCommandBufferBuilder;
+use vulkano::device::{Device, Queue};
+use vulkano::format::Format;
+use vulkano::image::StorageImage;
+use vulkano::sync;
+use vulkano::sync::GpuFuture;
+
+/// The first bytes of every png file.
+pub const PNG_SIGNATURE: [u8; 8] = [137, 80, 78, 71, 13, 10, 26, 10];
+
+// Keywords of the png text chunks the parameters are stored in.
+const SOFTWARE_KEYWORD: &str = "Software";
+const PRESET_KEYWORD: &str = "slime:preset";
+const STEP_KEYWORD: &str = "slime:step";
+const POST_KEYWORD: &str = "slime:post";
+
+/// Everything needed to get back to the state a screenshot was taken in.
+#[derive(Clone, Debug)]
+pub struct ScreenshotInfo {
+    /// The parameters, including the seed.
+    pub preset: Preset,
+    /// How many steps had been simulated since the agents were placed.
+    pub step: u64,
+    /// The post-processing, when it was applied to the image.
+    pub post: Option<PostSettings>,
+}
+
+/// Saves the image as a png, with the info embedded in text chunks.
+pub fn save<P: AsRef<Path>>(
+    path: P,
+    image: &RgbaImage,
+    info: &ScreenshotInfo,
+) -> Result<(), Box<dyn Error>> {
+    save_with_texts(path, image, &info_texts(info)?)
+}
+
+/// The text chunks `save` embeds the info in, as keyword and text.
+pub fn info_texts(info: &ScreenshotInfo) -> Result<Vec<(&'static str, String)>, serde_json::Error> {
+    let mut texts = vec![
+        (PRESET_KEYWORD, serde_json::to_string(&info.preset)?),
+        (STEP_KEYWORD, info.step.to_string()),
+    ];
+    if let Some(post) = &info.post {
+        texts.push(post_text(post)?);
+    }
+    Ok(texts)
+}
+
+/// The text chunk that records the post-processing applied to an image.
+pub fn post_text(post: &PostSettings) -> Result<(&'static str, String), serde_json::Error> {
+    Ok((POST_KEYWORD, serde_json::to_string(post)?))
+}
+
+/// Saves the image as a png with the given text chunks, as keyword and text.
+/// The texts have to be ascii, which is valid for the latin-1 text chunks.
+pub fn save_with_texts<P: AsRef<Path>>(
+    path: P,
+    image: &RgbaImage,
+    texts: &[(&str, String)],
+) -> Result<(), Box<dyn Error>> {
+    let file = File::create(path)?;
+    let mut encoder = png::Encoder::new(BufWriter::new(file), image.width(), image.height());
+    encoder.set_color(png::ColorType::RGBA);
+    encoder.set_depth(png::BitDepth::Eight);
+    let mut writer = encoder.write_header()?;
+
+    let mut write_text = |keyword: &str, text: &str| {
+        // A keyword, a zero byte and the text.
+        let mut data = keyword.as_bytes().to_vec();
+        data.push(0);
+        data.extend_from_slice(text.as_bytes());
+        writer.write_chunk(*b"tEXt", &data)
+    };
+    write_text(SOFTWARE_KEYWORD, "Slime Simulation")?;
+    for (keyword, text) in texts {
+        write_text(keyword, text)?;
+    }
+    writer.write_image_data(image)?;
+
+    Ok(())
+}
+
+/// Reads the info embedded by `save`, without decoding the image itself.
+pub fn load<P: AsRef<Path>>(path: P) -> Result<ScreenshotInfo, Box<dyn Error>> {
+    read_info(&std::fs::read(path)?)
+}
+
+/// Like `load`, for a png file that has already been read.
+pub fn read_info(bytes: &[u8]) -> Result<ScreenshotInfo, Box<dyn Error>> {
+    info_from_texts(&read_text_chunks(bytes)?)
+}
+
+/// The info in the text chunks of a png file, as read by `read_text_chunks`.
+pub fn info_from_texts(texts: &HashMap<String, String>) -> Result<ScreenshotInfo, Box<dyn Error>> {
+    let preset = texts
+        .get(PRESET_KEYWORD)
+        .ok_or("The image has no embedded parameters")?;
+    let step = match texts.get(STEP_KEYWORD) {
+        Some(step) => step.parse()?,
+        None => 0,
+    };
+
+    Ok(ScreenshotInfo {
+        preset: serde_json::from_str(preset)?,
+        step,
+        post: post_from_texts(texts)?,
+    })
+}
+
+/// The post-processing recorded by `post_text`, if it was applied to the image.
+pub fn post_from_texts(
+    texts: &HashMap<String, String>,
+) -> Result<Option<PostSettings>, serde_json::Error> {
+    match texts.get(POST_KEYWORD) {
+        Some(post) => Ok(Some(serde_json::from_str(post)?)),
+        None => Ok(None),
+    }
+}
+
+/// The keywords and texts of all `tEXt` chunks in a png file.
+pub fn read_text_chunks(bytes: &[u8]) -> Result<HashMap<String, String>, Box<dyn Error>> {
+    if !bytes.starts_with(&PNG_SIGNATURE) {
+        return Err("Not a png image".into());
+    }
+
+    // Every chunk is a big endian length, a type, the data and a checksum.
+    let mut texts = HashMap::new();
+    let mut rest = &bytes[PNG_SIGNATURE.len()..];
+    while rest.len() >= 12 {
+        let length = u32::from_be_bytes([rest[0], rest[1], rest[2], rest[3]]) as usize;
+        let chunk_type = &rest[4..8];
+        let data = rest
+            .get(8..8 + length)
+            .ok_or("The png image is truncated")?;
+
+        if chunk_type == b"tEXt" {
+            if let Some(separator) = data.iter().position(|&b| b == 0) {
+                // Latin-1 maps directly onto the first 256 unicode code points.
+                let latin1 =
+                    |bytes: &[u8]| -> String { bytes.iter().map(|&b| b as char).collect() };
+                texts.insert(latin1(&data[..separator]), latin1(&data[separator + 1..]));
+            }
+        } else if chunk_type == b"IEND" {
+            break;
+        }
+
+        rest = &rest[(12 + length).min(rest.len())..];
+    }
+
+    Ok(texts)
+}
+
+/// Copies the top left `size` pixels of an image in `device::DISPLAY_FORMAT` back to the cpu,
+/// and waits for it. `queue` has to be able to use the image.
+pub fn read_image(
+    device: &Arc<Device>,
+    queue: &Arc<Queue>,
+    image: &Arc<StorageImage<Format>>,
+    size: [u32; 2],
+) -> Result<RgbaImage, SimError> {
+    let [width, height] = size;
+
+    let buffer = CpuAccessibleBuffer::from_iter(
+        device.clone(),
+        BufferUsage::all(),
+        false,
+        (0..width as usize * height as usize * 4).map(|_| 0u8),
+    )?;
+
+    let mut builder = AutoCommandBufferBuilder::new(device.clone(), queue.family())?;
+    builder.copy_image_to_buffer_dimensions(
+        image.clone(),
+        buffer.clone(),
+        [0; 3],
+        [width, height, 1],
+        0,
+        1,
+        0,
+    )?;
+    let command_buffer = builder.build()?;
+
+    sync::now(device.clone())
+        .then_execute(queue.clone(), command_buffer)?
+        .then_signal_fence_and_flush()?
+        .wait(None)?;
+
+    let pixels = buffer.read()?.to_vec();
+    Ok(RgbaImage::from_raw(width, height, pixels).unwrap())
+}
+
+/// Where the screenshots taken from the window go, and how they are taken.
+pub struct Screenshots {
+    /// Save what the window shows, post-processing included, instead of the whole trails.
+    pub display_resolution: bool,
+    status: String,
+}
+
+impl Default for Screenshots {
+    fn default() -> Self {
+        Screenshots::new()
+    }
+}
+
+impl Screenshots {
+    pub fn new() -> Screenshots {
+        Screenshots {
+            display_resolution: false,
+            status: String::new(),
+        }
+    }
+
+    /// The first of `screenshot_00000.png`, `screenshot_00001.png`, ... that does not exist yet.
+    pub fn next_path(&self) -> PathBuf {
+        (0..)
+            .map(|number| PathBuf::from(format!("screenshot_{:05}.png", number)))
+            .find(|path| !path.exists())
+            .unwrap()
+    }
+
+    /// Shows the result of saving a screenshot or restoring one.
+    pub fn set_status(&mut self, status: String) {
+        self.status = status;
+    }
+
+    /// Returns whether a screenshot should be taken.
+    #[cfg(feature = "ui")]
+    pub fn draw_window(&mut self, ui: &Ui) -> bool {
+        let screenshots = self;
+        let mut take = false;
+
+        Window::new(im_str!("Screenshot"))
+            .position([320.0, 760.0], Condition::FirstUseEver)
+            .size([300.0, 110.0], Condition::FirstUseEver)
+            .build(ui, || {
+                ui.checkbox(
+                    im_str!("Display resolution"),
+                    &mut screenshots.display_resolution,
+                );
+                take = ui.button(im_str!("Save (F12)"), [0.0, 0.0]);
+                ui.text(&screenshots.status);
+            });
+
+        take
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A png with the given text chunks, as written by `save_with_texts`.
+    fn png_bytes(name: &str, texts: &[(&str, String)]) -> Vec<u8> {
+        let path = std::env::temp_dir().join(format!(
+            "slime_screenshot_{}_{}.png",
+            name,
+            std::process::id()
+        ));
+        let image = RgbaImage::from_pixel(4, 4, image::Rgba([10, 20, 30, 255]));
+        save_with_texts(&path, &image, texts).unwrap();
+        let bytes = std::fs::read(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        bytes
+    }
+
+    #[test]
+    fn text_chunks_roundtrip() {
+        let bytes = png_bytes(
+            "roundtrip",
+            &[
+                (STEP_KEYWORD, "42".to_owned()),
+                ("comment", "a text\nwith two lines".to_owned()),
+            ],
+        );
+        let texts = read_text_chunks(&bytes).unwrap();
+        assert_eq!(texts.len(), 3);
+        assert_eq!(texts[SOFTWARE_KEYWORD], "Slime Simulation");
+        assert_eq!(texts[STEP_KEYWORD], "42");
+        assert_eq!(texts["comment"], "a text\nwith two lines");
+
+        // The image itself is still readable.
+        let image = image::load_from_memory(&bytes).unwrap().to_rgba8();
+        assert_eq!(image.dimensions(), (4, 4));
+    }
+
+    #[test]
+    fn info_roundtrip() {
+        let mut preset = Preset::default();
+        preset.seed = Some(1234);
+        preset.agent_amount = 777;
+        let info = ScreenshotInfo {
+            preset,
+            step: 99,
+            post: None,
+        };
+        let bytes = png_bytes("info", &info_texts(&info).unwrap());
+
+        let read = read_info(&bytes).unwrap();
+        assert_eq!(read.preset.seed, Some(1234));
+        assert_eq!(read.preset.agent_amount, 777);
+        assert_eq!(read.step, 99);
+        assert!(read.post.is_none());
+    }
+
+    #[test]
+    fn truncated_png_is_an_error() {
+        let bytes = png_bytes("truncated", &[(STEP_KEYWORD, "1".to_owned())]);
+        // Cuts into the image data, after the text chunks.
+        assert!(read_text_chunks(&bytes[..bytes.len() - 20]).is_err());
+    }
+
+    #[test]
+    fn other_files_are_an_error() {
+        assert!(read_text_chunks(b"GIF89a, not a png at all").is_err());
+        assert!(read_text_chunks(&[]).is_err());
+        assert!(read_info(&png_bytes("no_info", &[])).is_err());
+    }
+}
@@ -1,234 +1,947 @@
-use rand::Rng;
+use crate::colony::{Colony, ColonySettings, ColonyStatus, MAX_FOOD_PILES};
+use crate::device;
+use crate::device::TrailFormat;
+use crate::error::SimError;
+use crate::model::Model;
+use crate::neighbours::NeighbourGrid;
+use crate::parameters::ParameterSet;
+use crate::preset::Preset;
+use crate::profiler;
+use crate::profiler::{GpuTimer, PassTimings};
+use crate::screenshot;
+use crate::screenshot::ScreenshotInfo;
+use image::imageops::FilterType;
+use image::{imageops, DynamicImage, RgbaImage};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use std::collections::HashMap;
+use std::error::Error;
 use std::f32::consts::PI;
 use std::sync::Arc;
-use vulkano::buffer::{BufferUsage, CpuAccessibleBuffer};
+use vulkano::buffer::{BufferUsage, CpuAccessibleBuffer, DeviceLocalBuffer};
 use vulkano::command_buffer::{AutoCommandBuffer, AutoCommandBufferBuilder};
 use vulkano::descriptor::descriptor_set::{
-    PersistentDescriptorSet, PersistentDescriptorSetBuf, PersistentDescriptorSetImg,
+    DescriptorSet, PersistentDescriptorSet, PersistentDescriptorSetBuf, PersistentDescriptorSetImg,
 };
-use vulkano::descriptor::pipeline_layout::PipelineLayout;
 use vulkano::descriptor::PipelineLayoutAbstract;
 use vulkano::device::{Device, Queue};
 use vulkano::format::Format;
 use vulkano::image::{Dimensions, StorageImage};
-use vulkano::pipeline::ComputePipeline;
+use vulkano::pipeline::{ComputePipeline, ComputePipelineAbstract};
+use vulkano::sync;
+use vulkano::sync::GpuFuture;
 
-pub struct Simulation {
-    pub result_image: Arc<StorageImage<Format>>,
-    pub device: Arc<Device>,
-    pub queue: Arc<Queue>,
-    agent_amount: u32,
-    agent_sim_pipeline: Arc<ComputePipeline<PipelineLayout<agent_shader::Layout>>>,
-    agent_sim_set: Arc<
-        PersistentDescriptorSet<(
+/// The parts of the simulation that are fixed once it has been created.
+#[derive(Clone, Copy, Debug)]
+pub struct SimulationConfig {
+    pub width: u32,
+    pub height: u32,
+    pub agent_amount: u32,
+    /// Seed for the initial placement of the agents.
+    pub seed: u64,
+    pub trail_format: TrailFormat,
+}
+
+impl Default for SimulationConfig {
+    /// Uses a random seed.
+    fn default() -> Self {
+        SimulationConfig {
+            width: 2000,
+            height: 1400,
+            agent_amount: 500_000,
+            seed: rand::random(),
+            trail_format: TrailFormat::default(),
+        }
+    }
+}
+
+/// The parameters of the agents, which can be changed every step.
+pub type AgentParameters = agent_shader::ty::Parameters;
+/// The parameters of the trail fading and blurring, which can be changed every step.
+pub type FadeParameters = blur_fade_shader::ty::Parameters;
+
+/// Seconds per step, at 60 steps per second.
+pub const DEFAULT_DELTA_TIME: f32 = 0.016667;
+
+/// Every agent parameter at the default of `parameters::PARAMETERS`.
+pub fn default_sim_parameters() -> AgentParameters {
+    ParameterSet::default().sim
+}
+
+/// Every fade parameter at the default of `parameters::PARAMETERS`.
+pub fn default_fade_parameters() -> FadeParameters {
+    ParameterSet::default().fade
+}
+
+type TrailImage = Arc<StorageImage<Format>>;
+
+type Pipeline = Arc<dyn ComputePipelineAbstract + Send + Sync>;
+
+macro_rules! compute_pipeline {
+    ($device:expr, $shader:ident) => {{
+        let shader = $shader::Shader::load($device.clone())?;
+        Arc::new(ComputePipeline::new(
+            $device.clone(),
+            &shader.main_entry_point(),
+            &(),
+            None,
+        )?) as Pipeline
+    }};
+}
+
+/// Loads the variant of a shader module that matches the trail format.
+macro_rules! trail_pipeline {
+    ($device:expr, $format:expr, $rgba8:ident, $rgba16f:ident, $rgba32f:ident) => {
+        match $format {
+            TrailFormat::Rgba8 => compute_pipeline!($device, $rgba8),
+            TrailFormat::Rgba16f => compute_pipeline!($device, $rgba16f),
+            TrailFormat::Rgba32f => compute_pipeline!($device, $rgba32f),
+        }
+    };
+}
+
+type AgentSimSet = Arc<
+    PersistentDescriptorSet<(
+        (
             (
                 (
-                    (),
-                    PersistentDescriptorSetImg<
-                        Arc<vulkano::image::StorageImage<vulkano::format::Format>>,
-                    >,
+                    (
+                        (
+                            (
+                                ((), PersistentDescriptorSetImg<TrailImage>),
+                                PersistentDescriptorSetBuf<Arc<DeviceLocalBuffer<[u32]>>>,
+                            ),
+                            PersistentDescriptorSetBuf<
+                                Arc<CpuAccessibleBuffer<[agent_shader::ty::Agent]>>,
+                            >,
+                        ),
+                        PersistentDescriptorSetBuf<Arc<DeviceLocalBuffer<AgentParameters>>>,
+                    ),
+                    PersistentDescriptorSetImg<Arc<StorageImage<Format>>>,
                 ),
-                PersistentDescriptorSetImg<
-                    Arc<vulkano::image::StorageImage<vulkano::format::Format>>,
-                >,
+                PersistentDescriptorSetBuf<Arc<CpuAccessibleBuffer<agent_shader::ty::Colony>>>,
             ),
-            PersistentDescriptorSetBuf<Arc<CpuAccessibleBuffer<[agent_shader::ty::Agent]>>>,
-        )>,
-    >,
-    agent_sim_image: Arc<StorageImage<Format>>,
-    blur_pipeline: Arc<ComputePipeline<PipelineLayout<blur_fade_shader::Layout>>>,
-    blur_set: Arc<
-        PersistentDescriptorSet<(
+            PersistentDescriptorSetBuf<Arc<DeviceLocalBuffer<[u32]>>>,
+        ),
+        PersistentDescriptorSetBuf<Arc<DeviceLocalBuffer<[u32]>>>,
+    )>,
+>;
+
+type BlurSet = Arc<
+    PersistentDescriptorSet<(
+        (
             (
-                (),
-                PersistentDescriptorSetImg<
-                    Arc<vulkano::image::StorageImage<vulkano::format::Format>>,
-                >,
+                ((), PersistentDescriptorSetImg<TrailImage>),
+                PersistentDescriptorSetImg<TrailImage>,
             ),
-            PersistentDescriptorSetImg<Arc<vulkano::image::StorageImage<vulkano::format::Format>>>,
-        )>,
-    >,
+            PersistentDescriptorSetBuf<Arc<DeviceLocalBuffer<[u32]>>>,
+        ),
+        PersistentDescriptorSetBuf<Arc<DeviceLocalBuffer<FadeParameters>>>,
+    )>,
+>;
+
+/// The recorded passes for one of the two trail images.
+/// They only depend on which image is current, so they are recorded once and resubmitted.
+struct StepCommands {
+    /// Clears the deposits, moves the agents and blurs, with barriers in between.
+    step: Arc<AutoCommandBuffer>,
+    /// The agent and blur passes on their own, so they can be timed separately.
+    agent: Arc<AutoCommandBuffer>,
+    blur: Arc<AutoCommandBuffer>,
+    /// Converts this trail image to the display image.
+    convert: Arc<AutoCommandBuffer>,
+}
+
+pub struct Simulation {
+    pub config: SimulationConfig,
+    pub device: Arc<Device>,
+    pub queue: Arc<Queue>,
+    /// The queue the `result_image` is shared with, kept to recreate it when resizing.
+    display_queue: Arc<Queue>,
+    pub agents_buffer: Arc<CpuAccessibleBuffer<[agent_shader::ty::Agent]>>,
+    pub agent_amount: u32,
+    /// The two trail images take turns: each step blurs the current one into the other,
+    /// which then becomes the current one. This saves copying the trails every step.
+    trail_images: [TrailImage; 2],
+    /// Index of the trail image holding the latest trails.
+    current: usize,
+    /// The latest trails in `device::DISPLAY_FORMAT`, converted after every batch of steps.
+    display_image: Arc<StorageImage<Format>>,
+    /// A fixed image the agents sense alongside the trails, black when there is none.
+    attractant_image: Arc<StorageImage<Format>>,
+    /// The nest and food of the ant mode. The agents take food from it and count what they
+    /// bring home, so it is read back after the steps.
+    colony_buffer: Arc<CpuAccessibleBuffer<agent_shader::ty::Colony>>,
+    /// Lets the agents find their neighbours for flocking. Only rebuilt while flocking.
+    neighbour_grid: NeighbourGrid,
+    /// The parameters the shaders read, written before the steps that use them.
+    sim_parameters_buffer: Arc<DeviceLocalBuffer<AgentParameters>>,
+    fade_parameters_buffer: Arc<DeviceLocalBuffer<FadeParameters>>,
+    /// Clears the deposits of the previous step.
+    clear_command: Arc<AutoCommandBuffer>,
+    /// Reads the trail image with the same index and writes the other one.
+    step_commands: [StepCommands; 2],
+    /// Times the passes of `step_profiled`, if the device can write timestamps.
+    timer: Option<GpuTimer>,
 }
 
 impl Simulation {
-    pub fn init(device: Arc<Device>, queue: Arc<Queue>) -> Simulation {
+    pub fn init(
+        device: Arc<Device>,
+        queue: Arc<Queue>,
+        config: &SimulationConfig,
+    ) -> Result<Simulation, SimError> {
+        Simulation::with_display_queue(device, queue.clone(), &queue, config)
+    }
+
+    /// Runs the simulation on `queue`, while the `result_image` is displayed from
+    /// `display_queue`, which may be of another queue family.
+    ///
+    /// Vulkano 0.20 can not record queue family ownership transfers, so instead the
+    /// display image is shared concurrently between both families. Work on the two queues
+    /// should be synchronised with a semaphore, like `then_signal_semaphore_and_flush`.
+    pub fn with_display_queue(
+        device: Arc<Device>,
+        queue: Arc<Queue>,
+        display_queue: &Arc<Queue>,
+        config: &SimulationConfig,
+    ) -> Result<Simulation, SimError> {
         let image_size = Dimensions::Dim2d {
-            width: 2000,
-            height: 1400,
+            width: config.width,
+            height: config.height,
         };
-        let agent_amount = 500_000;
+        let agent_amount = config.agent_amount;
 
-        let image_format = Format::R8G8B8A8Unorm;
+        device::check_trail_format(device.physical_device(), config.trail_format)?;
+        let trail_format = config.trail_format.format();
 
-        let agent_sim_image = StorageImage::new(
+        let trail_images = [
+            StorageImage::new(
+                device.clone(),
+                image_size,
+                trail_format,
+                Some(queue.family()),
+            )?,
+            StorageImage::new(
+                device.clone(),
+                image_size,
+                trail_format,
+                Some(queue.family()),
+            )?,
+        ];
+
+        // Only the display image is displayed, so only it is shared with the display queue.
+        let mut display_families = vec![queue.family()];
+        if display_queue.family().id() != queue.family().id() {
+            display_families.push(display_queue.family());
+        }
+        let display_image = StorageImage::new(
             device.clone(),
             image_size,
-            image_format,
-            Some(queue.family()),
-        )
-        .unwrap();
-        let result_image = StorageImage::new(
+            device::DISPLAY_FORMAT,
+            display_families,
+        )?;
+
+        let attractant_image = StorageImage::new(
             device.clone(),
             image_size,
-            image_format,
+            device::DISPLAY_FORMAT,
             Some(queue.family()),
-        )
-        .unwrap();
+        )?;
 
-        let mut rng = rand::thread_rng();
+        // One red, green, blue and alpha value per pixel.
+        let deposit_buffer = DeviceLocalBuffer::array(
+            device.clone(),
+            config.width as usize * config.height as usize * 4,
+            BufferUsage {
+                storage_buffer: true,
+                transfer_destination: true,
+                ..BufferUsage::none()
+            },
+            Some(queue.family()),
+        )?;
+
+        let mut rng = StdRng::seed_from_u64(config.seed);
 
         // Distribute the agents randomly in a circle in the centre, in a random direction.
-        let agent_iter = (0..agent_amount).map(|_i| {
-            let distance_from_centre = rng.gen_range(0..image_size.height() / 3) as f32;
-            let angle_form_centre = (rng.gen_range(0..100) as f32 * 2.0 * PI) / 100.0;
-
-            agent_shader::ty::Agent {
-                // No clue what the dummy is for.
-                _dummy0: [0u8; 4],
-                pos: [
-                    image_size.width() as f32 / 2.0
-                        + (angle_form_centre.cos() * distance_from_centre),
-                    image_size.height() as f32 / 2.0
-                        + (angle_form_centre.sin() * distance_from_centre),
-                ],
-                angle: rng.gen::<f32>() * 2.0 * PI,
-            }
-        });
+        let centre = [
+            image_size.width() as f32 / 2.0,
+            image_size.height() as f32 / 2.0,
+        ];
+        let radius = image_size.height() / 3;
+        let size = [image_size.width(), image_size.height()];
+        let agent_iter = (0..agent_amount).map(|_i| random_agent(&mut rng, centre, radius, size));
         let agents_buffer =
-            CpuAccessibleBuffer::from_iter(device.clone(), BufferUsage::all(), false, agent_iter)
-                .unwrap();
+            CpuAccessibleBuffer::from_iter(device.clone(), BufferUsage::all(), false, agent_iter)?;
 
-        let noise_shader =
-            agent_shader::Shader::load(device.clone()).expect("failed to create shader module");
+        let neighbour_grid =
+            NeighbourGrid::new(&device, &queue, &agents_buffer, config.width, config.height)?;
 
-        let agent_sim_pipeline = Arc::new(
-            ComputePipeline::new(device.clone(), &noise_shader.main_entry_point(), &(), None)
-                .expect("failed to create compute pipeline"),
-        );
+        let colony_buffer = CpuAccessibleBuffer::from_data(
+            device.clone(),
+            BufferUsage::all(),
+            false,
+            colony_data(&Colony::new(config, ColonySettings::default())),
+        )?;
 
-        let agent_sim_set = Arc::new(
-            PersistentDescriptorSet::start(
-                agent_sim_pipeline
-                    .layout()
-                    .descriptor_set_layout(0)
-                    .unwrap()
-                    .clone(),
-            )
-            .add_image(result_image.clone())
-            .unwrap()
-            .add_image(agent_sim_image.clone())
-            .unwrap()
-            .add_buffer(agents_buffer)
-            .unwrap()
-            .build()
-            .unwrap(),
+        let agent_sim_pipeline = trail_pipeline!(
+            device,
+            config.trail_format,
+            agent_shader,
+            agent_shader_rgba16f,
+            agent_shader_rgba32f
         );
 
-        let blur_shader =
-            blur_fade_shader::Shader::load(device.clone()).expect("failed to create shader module");
+        let uniform_usage = BufferUsage {
+            uniform_buffer: true,
+            transfer_destination: true,
+            ..BufferUsage::none()
+        };
+        let sim_parameters_buffer =
+            DeviceLocalBuffer::new(device.clone(), uniform_usage, Some(queue.family()))?;
+        let fade_parameters_buffer =
+            DeviceLocalBuffer::new(device.clone(), uniform_usage, Some(queue.family()))?;
+
+        let agent_sim_set = |trails: &TrailImage| -> Result<AgentSimSet, SimError> {
+            Ok(Arc::new(
+                PersistentDescriptorSet::start(
+                    agent_sim_pipeline.descriptor_set_layout(0).unwrap().clone(),
+                )
+                .add_image(trails.clone())?
+                .add_buffer(deposit_buffer.clone())?
+                .add_buffer(agents_buffer.clone())?
+                .add_buffer(sim_parameters_buffer.clone())?
+                .add_image(attractant_image.clone())?
+                .add_buffer(colony_buffer.clone())?
+                .add_buffer(neighbour_grid.cells_buffer.clone())?
+                .add_buffer(neighbour_grid.sorted_buffer.clone())?
+                .build()?,
+            ))
+        };
+        let agent_sim_sets = [
+            agent_sim_set(&trail_images[0])?,
+            agent_sim_set(&trail_images[1])?,
+        ];
 
-        let blur_pipeline = Arc::new(
-            ComputePipeline::new(device.clone(), &blur_shader.main_entry_point(), &(), None)
-                .expect("failed to create compute pipeline"),
+        let blur_pipeline = trail_pipeline!(
+            device,
+            config.trail_format,
+            blur_fade_shader,
+            blur_fade_shader_rgba16f,
+            blur_fade_shader_rgba32f
         );
 
-        let blur_set = Arc::new(
-            PersistentDescriptorSet::start(
-                blur_pipeline
-                    .layout()
-                    .descriptor_set_layout(0)
-                    .unwrap()
-                    .clone(),
-            )
-            .add_image(agent_sim_image.clone())
-            .unwrap()
-            .add_image(result_image.clone())
-            .unwrap()
-            .build()
-            .unwrap(),
+        let blur_set = |from: &TrailImage, to: &TrailImage| -> Result<BlurSet, SimError> {
+            Ok(Arc::new(
+                PersistentDescriptorSet::start(
+                    blur_pipeline.descriptor_set_layout(0).unwrap().clone(),
+                )
+                .add_image(from.clone())?
+                .add_image(to.clone())?
+                .add_buffer(deposit_buffer.clone())?
+                .add_buffer(fade_parameters_buffer.clone())?
+                .build()?,
+            ))
+        };
+        let blur_sets = [
+            blur_set(&trail_images[0], &trail_images[1])?,
+            blur_set(&trail_images[1], &trail_images[0])?,
+        ];
+
+        let convert_pipeline = trail_pipeline!(
+            device,
+            config.trail_format,
+            convert_shader,
+            convert_shader_rgba16f,
+            convert_shader_rgba32f
         );
 
-        Simulation {
-            result_image,
+        let convert_set =
+            |trails: &TrailImage| -> Result<Arc<dyn DescriptorSet + Send + Sync>, SimError> {
+                Ok(Arc::new(
+                    PersistentDescriptorSet::start(
+                        convert_pipeline.descriptor_set_layout(0).unwrap().clone(),
+                    )
+                    .add_image(trails.clone())?
+                    .add_image(display_image.clone())?
+                    .build()?,
+                ))
+            };
+        let convert_sets = [
+            convert_set(&trail_images[0])?,
+            convert_set(&trail_images[1])?,
+        ];
+
+        let mut clear_builder =
+            AutoCommandBufferBuilder::primary_simultaneous_use(device.clone(), queue.family())?;
+        clear_builder.fill_buffer(deposit_buffer.clone(), 0)?;
+        let clear_command = Arc::new(clear_builder.build()?);
+
+        let agent_groups = [(agent_amount + 63) / 64, 1, 1];
+        // The blur and convert passes both work on 8 by 8 pixels at a time.
+        let blur_groups = [(config.width + 7) / 8, (config.height + 7) / 8, 1];
+
+        let record_step = |index: usize| -> Result<StepCommands, SimError> {
+            let new_builder = || {
+                AutoCommandBufferBuilder::primary_simultaneous_use(device.clone(), queue.family())
+            };
+
+            let mut step_builder = new_builder()?;
+            step_builder
+                .fill_buffer(deposit_buffer.clone(), 0)?
+                .dispatch(
+                    agent_groups,
+                    agent_sim_pipeline.clone(),
+                    agent_sim_sets[index].clone(),
+                    (),
+                )?
+                .dispatch(
+                    blur_groups,
+                    blur_pipeline.clone(),
+                    blur_sets[index].clone(),
+                    (),
+                )?;
+
+            let mut agent_builder = new_builder()?;
+            agent_builder.dispatch(
+                agent_groups,
+                agent_sim_pipeline.clone(),
+                agent_sim_sets[index].clone(),
+                (),
+            )?;
+
+            let mut blur_builder = new_builder()?;
+            blur_builder.dispatch(
+                blur_groups,
+                blur_pipeline.clone(),
+                blur_sets[index].clone(),
+                (),
+            )?;
+
+            let mut convert_builder = new_builder()?;
+            convert_builder.dispatch(
+                blur_groups,
+                convert_pipeline.clone(),
+                convert_sets[index].clone(),
+                (),
+            )?;
+
+            Ok(StepCommands {
+                step: Arc::new(step_builder.build()?),
+                agent: Arc::new(agent_builder.build()?),
+                blur: Arc::new(blur_builder.build()?),
+                convert: Arc::new(convert_builder.build()?),
+            })
+        };
+        let step_commands = [record_step(0)?, record_step(1)?];
+        // Written before the clear, and after the clear, agent, blur and convert passes.
+        let timer = GpuTimer::new(device.clone(), queue.clone(), 5)?;
+
+        let simulation = Simulation {
+            config: *config,
             device,
             queue,
+            display_queue: display_queue.clone(),
+            agents_buffer,
             agent_amount,
-            agent_sim_pipeline,
-            agent_sim_set,
-            agent_sim_image,
-            blur_pipeline,
-            blur_set,
+            trail_images,
+            current: 0,
+            display_image,
+            attractant_image,
+            colony_buffer,
+            neighbour_grid,
+            sim_parameters_buffer,
+            fade_parameters_buffer,
+            clear_command,
+            step_commands,
+            timer,
+        };
+        simulation.set_attractant(None)?;
+
+        Ok(simulation)
+    }
+
+    /// Places all agents back in the centre, as if the simulation was created with the given
+    /// seed, and clears the trails. In the ant mode they start in the nest instead, and the
+    /// collected food starts over. Later restarts, like those of the ant colony, use the seed
+    /// as well, and snapshots embed it.
+    /// The gpu should not be using the agents buffer or the images when this is called.
+    pub fn reset(&mut self, seed: u64) -> Result<(), SimError> {
+        self.config.seed = seed;
+        let mut rng = StdRng::seed_from_u64(seed);
+        let mut centre = [
+            self.config.width as f32 / 2.0,
+            self.config.height as f32 / 2.0,
+        ];
+        let mut radius = self.config.height / 3;
+        let size = [self.config.width, self.config.height];
+
+        {
+            let mut colony = self.colony_buffer.write()?;
+            colony.collected = 0;
+            if colony.enabled != 0 {
+                centre = [colony.nest[0], colony.nest[1]];
+                radius = (colony.nest[2] as u32).max(1);
+            }
+        }
+
+        {
+            let mut agents = self.agents_buffer.write()?;
+            for agent in agents.iter_mut() {
+                *agent = random_agent(&mut rng, centre, radius, size);
+            }
         }
+
+        let mut builder = AutoCommandBufferBuilder::new(self.device.clone(), self.queue.family())?;
+        builder
+            .clear_color_image(self.trail_images[0].clone(), [0.0; 4].into())?
+            .clear_color_image(self.trail_images[1].clone(), [0.0; 4].into())?
+            .clear_color_image(self.display_image.clone(), [0.0, 0.0, 0.0, 1.0].into())?;
+        let command_buffer = builder.build()?;
+
+        sync::now(self.device.clone())
+            .then_execute(self.queue.clone(), command_buffer)?
+            .then_signal_fence_and_flush()?
+            .wait(None)?;
+
+        Ok(())
+    }
+
+    /// Moves `count` randomly chosen agents into a circle around `centre`.
+    /// Agents that would end up outside of the trails are placed on their edge.
+    /// The gpu should not be using the agents buffer when this is called.
+    pub fn spawn(&self, centre: [f32; 2], radius: u32, count: u32) -> Result<(), SimError> {
+        let mut rng = rand::thread_rng();
+        let size = [self.config.width, self.config.height];
+        let mut agents = self.agents_buffer.write()?;
+
+        for _ in 0..count.min(self.agent_amount) {
+            let index = rng.gen_range(0..agents.len());
+            agents[index] = random_agent(&mut rng, centre, radius, size);
+        }
+
+        Ok(())
+    }
+
+    /// Replaces the attractant with the given image, or clears it when there is none.
+    /// The image is scaled to fit inside the simulation and centred, leaving the rest black.
+    /// Bright parts attract the agents, in proportion to the `attractant_weight`.
+    /// The gpu should not be running steps when this is called.
+    pub fn set_attractant(&self, image: Option<&DynamicImage>) -> Result<(), SimError> {
+        let mut builder = AutoCommandBufferBuilder::new(self.device.clone(), self.queue.family())?;
+        match image {
+            Some(image) => {
+                let fitted = fit_image(image, self.config.width, self.config.height);
+                let buffer = CpuAccessibleBuffer::from_iter(
+                    self.device.clone(),
+                    BufferUsage::transfer_source(),
+                    false,
+                    fitted.into_raw().into_iter(),
+                )?;
+                builder.copy_buffer_to_image(buffer, self.attractant_image.clone())?;
+            }
+            None => {
+                builder.clear_color_image(self.attractant_image.clone(), [0.0; 4].into())?;
+            }
+        }
+        let command_buffer = builder.build()?;
+
+        sync::now(self.device.clone())
+            .then_execute(self.queue.clone(), command_buffer)?
+            .then_signal_fence_and_flush()?
+            .wait(None)?;
+
+        Ok(())
     }
 
-    /// The command buffers should be executed in the order given.
-    pub fn create_command_buffers(
+    /// Sets up the nest and food of the ant mode, or switches back to slime mold. The food
+    /// collected so far is kept, `reset` starts it over.
+    /// The gpu should not be running steps when this is called.
+    pub fn set_colony(&self, colony: &Colony) -> Result<(), SimError> {
+        let mut data = self.colony_buffer.write()?;
+        let collected = data.collected;
+        *data = colony_data(colony);
+        data.collected = collected;
+
+        Ok(())
+    }
+
+    /// How much food the ants collected, and how much is left in each pile.
+    /// The gpu should not be running steps when this is called.
+    pub fn colony_status(&self) -> Result<ColonyStatus, SimError> {
+        let data = self.colony_buffer.read()?;
+        let pile_count = data.food_pile_count as usize;
+
+        Ok(ColonyStatus {
+            collected: data.collected,
+            food_left: data.food_left[..pile_count]
+                .iter()
+                .map(|&left| left.max(0) as u32)
+                .collect(),
+        })
+    }
+
+    /// Records the upload of the parameters for the following steps. This is the only
+    /// command buffer that is recorded per call, the steps themselves are prebuilt.
+    fn upload_parameters(
         &self,
-        sim_parameters: &agent_shader::ty::PushConstantData,
-        fade_parameters: &blur_fade_shader::ty::PushConstantData,
-    ) -> (AutoCommandBuffer, AutoCommandBuffer, AutoCommandBuffer) {
-        let mut copy_builder =
-            AutoCommandBufferBuilder::new(self.device.clone(), self.queue.family())
-                .expect("Failed to create command buffer");
-        // Transfer old trails.
-        copy_builder
-            .copy_image(
-                self.result_image.clone(),
-                [0; 3],
-                0,
-                0,
-                self.agent_sim_image.clone(),
-                [0; 3],
-                0,
-                0,
-                [
-                    self.result_image.dimensions().width(),
-                    self.result_image.dimensions().height(),
-                    1,
-                ],
-                1,
-            )
-            .unwrap();
-        let copy_buffer = copy_builder.build().unwrap();
-
-        let mut sim_builder =
-            AutoCommandBufferBuilder::new(self.device.clone(), self.queue.family())
-                .expect("Failed to create command buffer");
-        sim_builder
-            .dispatch(
-                [self.agent_amount / 64, 1, 1],
-                self.agent_sim_pipeline.clone(),
-                self.agent_sim_set.clone(),
-                sim_parameters.clone(),
-            )
-            .unwrap();
-        let sim_buffer = sim_builder.build().unwrap();
-
-        let mut blur_builder =
-            AutoCommandBufferBuilder::new(self.device.clone(), self.queue.family())
-                .expect("Failed to create command buffer");
-        blur_builder
-            .dispatch(
-                [
-                    self.result_image.dimensions().width() / 8,
-                    self.result_image.dimensions().height() / 8,
-                    1,
-                ],
-                self.blur_pipeline.clone(),
-                self.blur_set.clone(),
-                fade_parameters.clone(),
-            )
-            .unwrap();
-        let blur_buffer = blur_builder.build().unwrap();
-
-        (copy_buffer, sim_buffer, blur_buffer)
+        sim_parameters: &AgentParameters,
+        fade_parameters: &FadeParameters,
+    ) -> Result<AutoCommandBuffer, SimError> {
+        let mut builder = AutoCommandBufferBuilder::new(self.device.clone(), self.queue.family())?;
+        builder
+            .update_buffer(self.sim_parameters_buffer.clone(), sim_parameters.clone())?
+            .update_buffer(self.fade_parameters_buffer.clone(), fade_parameters.clone())?;
+        Ok(builder.build()?)
+    }
+
+    fn swap_trails(&mut self) {
+        self.current = 1 - self.current;
+    }
+
+    /// Schedules a single simulation step after the given future, without waiting for it.
+    /// This lets the caller chain the step with its own gpu work, like drawing the
+    /// `result_image`, and decide when to flush and wait.
+    pub fn step_after<F>(
+        &mut self,
+        future: F,
+        sim_parameters: &AgentParameters,
+        fade_parameters: &FadeParameters,
+    ) -> Result<Box<dyn GpuFuture>, SimError>
+    where
+        F: GpuFuture + 'static,
+    {
+        self.steps_after(future, 1, sim_parameters, fade_parameters)
+    }
+
+    /// Schedules `steps` simulation steps with the same parameters after the given future,
+    /// followed by converting the trails to the `result_image`.
+    /// Nothing is submitted until the returned future is flushed, so all steps end up in
+    /// a single submission.
+    pub fn steps_after<F>(
+        &mut self,
+        future: F,
+        steps: u32,
+        sim_parameters: &AgentParameters,
+        fade_parameters: &FadeParameters,
+    ) -> Result<Box<dyn GpuFuture>, SimError>
+    where
+        F: GpuFuture + 'static,
+    {
+        let upload = self.upload_parameters(sim_parameters, fade_parameters)?;
+        let mut future = future.then_execute(self.queue.clone(), upload)?.boxed();
+
+        let flocking = is_flocking(sim_parameters);
+        for _ in 0..steps {
+            if flocking {
+                future = future
+                    .then_execute(self.queue.clone(), self.neighbour_grid.command.clone())?
+                    .boxed();
+            }
+            future = future
+                .then_execute(
+                    self.queue.clone(),
+                    self.step_commands[self.current].step.clone(),
+                )?
+                .boxed();
+            self.swap_trails();
+        }
+
+        let future = future
+            .then_execute(
+                self.queue.clone(),
+                self.step_commands[self.current].convert.clone(),
+            )?
+            .boxed();
+
+        Ok(future)
+    }
+
+    /// Runs a single simulation step, submitting each of the passes on its own with a gpu
+    /// timestamp in between, and waits for it to finish. This is slower than submitting them
+    /// together, but it allows every pass to be timed. The `display_ms` of the result only
+    /// includes converting the trails to the `result_image`, not displaying it.
+    /// Returns `None` if the device can not write timestamps, after running the step anyway.
+    pub fn step_profiled(
+        &mut self,
+        parameters: &ParameterSet,
+    ) -> Result<Option<PassTimings>, SimError> {
+        let timer = match &self.timer {
+            Some(timer) => timer,
+            None => {
+                self.run_steps(1, parameters)?;
+                return Ok(None);
+            }
+        };
+
+        sync::now(self.device.clone())
+            .then_execute(
+                self.queue.clone(),
+                self.upload_parameters(&parameters.sim, &parameters.fade)?,
+            )?
+            .then_signal_fence_and_flush()?
+            .wait(None)?;
+
+        // Every pass is flushed on its own, so that the timestamps can go in between.
+        // The blur writes the other trail image, which the convert pass reads.
+        let commands = &self.step_commands[self.current];
+        let (agent, blur) = (commands.agent.clone(), commands.blur.clone());
+        let convert = self.step_commands[1 - self.current].convert.clone();
+        timer.write(0)?;
+        let mut future = profiler::then_flush(
+            sync::now(self.device.clone()).boxed(),
+            &self.queue,
+            self.clear_command.clone(),
+        )?;
+        timer.write(1)?;
+        // Sorting the agents into the neighbour grid counts as part of the agent pass.
+        if is_flocking(&parameters.sim) {
+            future =
+                profiler::then_flush(future, &self.queue, self.neighbour_grid.command.clone())?;
+        }
+        future = profiler::then_flush(future, &self.queue, agent)?;
+        timer.write(2)?;
+        future = profiler::then_flush(future, &self.queue, blur)?;
+        timer.write(3)?;
+        future = profiler::then_flush(future, &self.queue, convert)?;
+        timer.write(4)?;
+
+        future.then_signal_fence_and_flush()?.wait(None)?;
+        let intervals = timer.read_intervals()?;
+        self.swap_trails();
+        Ok(Some(PassTimings {
+            clear_ms: intervals[0],
+            agent_ms: intervals[1],
+            blur_ms: intervals[2],
+            display_ms: intervals[3],
+        }))
     }
 }
 
-pub mod agent_shader {
-    vulkano_shaders::shader! {
-        ty: "compute",
-        src:
+impl Model for Simulation {
+    type Config = SimulationConfig;
+    type Parameters = ParameterSet;
+
+    fn init(
+        device: Arc<Device>,
+        queue: Arc<Queue>,
+        display_queue: &Arc<Queue>,
+        config: &SimulationConfig,
+    ) -> Result<Simulation, SimError> {
+        Simulation::with_display_queue(device, queue, display_queue, config)
+    }
+
+    fn device(&self) -> &Arc<Device> {
+        &self.device
+    }
+
+    fn queue(&self) -> &Arc<Queue> {
+        &self.queue
+    }
+
+    fn size(&self) -> [u32; 2] {
+        [self.config.width, self.config.height]
+    }
+
+    fn steps_after<F>(
+        &mut self,
+        future: F,
+        steps: u32,
+        parameters: &ParameterSet,
+    ) -> Result<Box<dyn GpuFuture>, SimError>
+    where
+        F: GpuFuture + 'static,
+    {
+        Simulation::steps_after(self, future, steps, &parameters.sim, &parameters.fade)
+    }
+
+    /// Adds up the timings of every step.
+    fn run_steps_profiled(
+        &mut self,
+        steps: u32,
+        parameters: &ParameterSet,
+    ) -> Result<Option<PassTimings>, SimError> {
+        // Without timestamps the steps are submitted together as usual.
+        if self.timer.is_none() {
+            self.run_steps(steps, parameters)?;
+            return Ok(None);
+        }
+
+        let mut total = PassTimings::default();
+        for _ in 0..steps {
+            if let Some(timings) = self.step_profiled(parameters)? {
+                total.clear_ms += timings.clear_ms;
+                total.agent_ms += timings.agent_ms;
+                total.blur_ms += timings.blur_ms;
+                total.display_ms += timings.display_ms;
+            }
+        }
+        Ok(Some(total))
+    }
+
+    /// The latest trails in `device::DISPLAY_FORMAT`. The trails themselves are simulated in
+    /// `config.trail_format`.
+    fn result_image(&self) -> &Arc<StorageImage<Format>> {
+        &self.display_image
+    }
+
+    fn reset(&mut self, seed: u64) -> Result<(), SimError> {
+        Simulation::reset(self, seed)
+    }
+
+    /// The agents start over in the centre, and the attractant and colony are cleared.
+    fn resize(&mut self, width: u32, height: u32) -> Result<(), SimError> {
+        let config = SimulationConfig {
+            width,
+            height,
+            ..self.config
+        };
+        let display_queue = self.display_queue.clone();
+        *self = Simulation::with_display_queue(
+            self.device.clone(),
+            self.queue.clone(),
+            &display_queue,
+            &config,
+        )?;
+        Ok(())
+    }
+
+    fn screenshot_texts(
+        &self,
+        parameters: &ParameterSet,
+        step: u64,
+    ) -> Result<Vec<(&'static str, String)>, SimError> {
+        let texts = screenshot::info_texts(&ScreenshotInfo {
+            preset: Preset::from_parameters(&self.config, &parameters.sim, &parameters.fade),
+            step,
+            post: None,
+        })?;
+        Ok(texts)
+    }
+
+    /// The resolution, agents and trail format can only be restored by starting with the
+    /// screenshot as preset.
+    fn restore(
+        &mut self,
+        texts: &HashMap<String, String>,
+        parameters: &mut ParameterSet,
+    ) -> Result<String, Box<dyn Error>> {
+        let info = screenshot::info_from_texts(texts)?;
+
+        // Keep the time step of the frame rate, like remote control does.
+        let delta_time = parameters.sim.delta_time;
+        parameters.sim = info.preset.sim_parameters();
+        parameters.fade = info.preset.fade_parameters();
+        parameters.sim.delta_time = delta_time;
+        parameters.fade.delta_time = delta_time;
+
+        let config = info.preset.config();
+        Simulation::reset(self, config.seed)?;
+
+        let running = &self.config;
+        if config.width != running.width
+            || config.height != running.height
+            || config.agent_amount != running.agent_amount
+            || config.trail_format != running.trail_format
+        {
+            Ok(format!(
+                "Restored the parameters of step {}. Start with the screenshot as --preset \
+                 to also restore the resolution, agents and trail format.",
+                info.step
+            ))
+        } else {
+            Ok(format!("Restored the parameters of step {}", info.step))
+        }
+    }
+}
+
+/// Whether the agents flock, which needs the neighbour grid.
+fn is_flocking(sim_parameters: &AgentParameters) -> bool {
+    sim_parameters.separation_weight > 0.0
+        || sim_parameters.alignment_weight > 0.0
+        || sim_parameters.cohesion_weight > 0.0
+}
+
+/// Scales the image to the largest size that fits in `width` by `height` without cropping,
+/// keeping its aspect ratio, and centres it on a black background.
+fn fit_image(image: &DynamicImage, width: u32, height: u32) -> RgbaImage {
+    let image = image.to_rgba8();
+    let scale = (width as f32 / image.width() as f32).min(height as f32 / image.height() as f32);
+    let fitted_width = ((image.width() as f32 * scale) as u32).clamp(1, width);
+    let fitted_height = ((image.height() as f32 * scale) as u32).clamp(1, height);
+    let scaled = imageops::resize(&image, fitted_width, fitted_height, FilterType::Triangle);
+
+    let mut fitted = RgbaImage::new(width, height);
+    imageops::overlay(
+        &mut fitted,
+        &scaled,
+        (width - fitted_width) / 2,
+        (height - fitted_height) / 2,
+    );
+    fitted
+}
+
+/// An agent somewhere in a circle around `centre`, facing a random direction,
+/// clamped to the trails of the given size.
+fn random_agent<R: Rng>(
+    rng: &mut R,
+    centre: [f32; 2],
+    radius: u32,
+    size: [u32; 2],
+) -> agent_shader::ty::Agent {
+    let distance_from_centre = rng.gen_range(0..radius.max(1)) as f32;
+    let angle_form_centre = (rng.gen_range(0..100) as f32 * 2.0 * PI) / 100.0;
+    // Just inside the far edges, like the agents bounce off them in the shader.
+    let inside = |value: f32, size: u32| value.max(0.0).min(size as f32 - 0.01);
+
+    agent_shader::ty::Agent {
+        pos: [
+            inside(
+                centre[0] + (angle_form_centre.cos() * distance_from_centre),
+                size[0],
+            ),
+            inside(
+                centre[1] + (angle_form_centre.sin() * distance_from_centre),
+                size[1],
+            ),
+        ],
+        angle: rng.gen::<f32>() * 2.0 * PI,
+        carrying: 0,
+    }
+}
+
+/// The colony as the `agent_shader` reads it, with nothing collected yet.
+fn colony_data(colony: &Colony) -> agent_shader::ty::Colony {
+    let mut data = agent_shader::ty::Colony {
+        food_piles: [[0.0; 4]; MAX_FOOD_PILES],
+        nest: [
+            colony.nest[0],
+            colony.nest[1],
+            colony.settings.nest_radius,
+            0.0,
+        ],
+        enabled: colony.enabled as i32,
+        food_pile_count: colony.food.len().min(MAX_FOOD_PILES) as i32,
+        collected: 0,
+        food_left: [0; MAX_FOOD_PILES],
+    };
+    for (index, pile) in colony.food.iter().take(MAX_FOOD_PILES).enumerate() {
+        data.food_piles[index] = [pile.centre[0], pile.centre[1], pile.radius, 0.0];
+        data.food_left[index] = pile.amount.min(i32::MAX as u32) as i32;
+    }
+    data
+}
+
+// The shaders that access the trail images are compiled once per `TrailFormat`, since the
+// format of a storage image is part of the shader. The modules for `rgba8` keep the plain
+// names, and define the types (like `Parameters`) that are shared by all variants.
+
+macro_rules! agent_shader_module {
+    ($format:tt) => {
+        vulkano_shaders::shader! {
+            ty: "compute",
+            define: [("TRAIL_FORMAT", $format)],
+            src:
 "
 #version 450
 
@@ -238,17 +951,28 @@ const float HALF_PI = PI / 0.5;
 struct Agent {
     vec2 pos;
     float angle;
+    // 1 while an ant carries food back to the nest. Always 0 for slime mold.
+    uint carrying;
 };
 
 layout(local_size_x = 64, local_size_y = 1, local_size_z = 1) in;
 
-layout(set = 0, binding = 0, rgba8) uniform readonly image2D trail_img;
-layout(set = 0, binding = 1, rgba8) uniform writeonly image2D out_img;
+// Has to match DEPOSIT_SCALE in the blur_fade_shader.
+const float DEPOSIT_SCALE = 4096.0;
+
+layout(set = 0, binding = 0, TRAIL_FORMAT) uniform readonly image2D trail_img;
+// Fixed point red, green, blue and alpha per pixel, added to by all agents.
+layout(set = 0, binding = 1) buffer Deposits {
+    uint data[];
+} deposits;
 layout(set = 0, binding = 2) buffer Agents {
     Agent data[];
 } buf;
 
-layout(push_constant) uniform PushConstantData {
+// Written by `Simulation::upload_parameters`, so the recorded command buffers can be reused.
+layout(set = 0, binding = 3) uniform Parameters {
+    // Color the agents leave behind.
+    vec4 trail_color;
     // In pixels / second.
     float agent_speed;
     // In radians / second.
@@ -258,10 +982,62 @@ layout(push_constant) uniform PushConstantData {
     float sensor_angle_spacing;
     // How many time is passed per frame.
     float delta_time;
-} pc;
+    // How much of the trail color each agent adds per step.
+    float deposit_amount;
+    // How strongly the attractant counts, compared to the trails.
+    float attractant_weight;
+    // How strongly the agents steer towards trails, compared to flocking.
+    float trail_weight;
+    // How strongly the agents steer away from close neighbours, along with their heading
+    // and towards their centre.
+    float separation_weight;
+    float alignment_weight;
+    float cohesion_weight;
+    // In pixels, at most CELL_SIZE.
+    float flock_radius;
+} params;
+
+// Written by `Simulation::set_attractant`. Black where nothing attracts the agents.
+layout(set = 0, binding = 4, rgba8) uniform readonly image2D attractant_img;
+
+// Has to match MAX_FOOD_PILES in colony.rs.
+const int MAX_FOOD_PILES = 16;
+
+// Written by `Simulation::set_colony`, only used in the ant mode.
+layout(set = 0, binding = 5) buffer Colony {
+    // The centre in xy and the radius in z.
+    vec4 food_piles[MAX_FOOD_PILES];
+    vec4 nest;
+    // Ants instead of slime mold when not 0.
+    int enabled;
+    int food_pile_count;
+    // How much food the ants have brought to the nest.
+    uint collected;
+    int food_left[MAX_FOOD_PILES];
+} colony;
+
+// Has to match CELL_SIZE in neighbours.rs.
+const float CELL_SIZE = 32.0;
+// Flocking looks at no more neighbours than this, so crowded cells do not stall the step.
+const uint MAX_NEIGHBOURS = 32;
+
+// Written by the `NeighbourGrid` before every step with flocking.
+layout(set = 0, binding = 6) readonly buffer Cells {
+    // The first index into `sorted` and the agent count of every cell, alternating.
+    uint data[];
+} cells;
+layout(set = 0, binding = 7) readonly buffer SortedAgents {
+    uint data[];
+} sorted;
 
-int width = imageSize(out_img).x;
-int height = imageSize(out_img).y;
+// Ants leave home trails while looking for food, and food trails while bringing it home.
+const vec4 HOME_TRAIL = vec4(1.0, 0.0, 0.0, 1.0);
+const vec4 FOOD_TRAIL = vec4(0.0, 1.0, 0.0, 1.0);
+// How strongly ants smell the nest or the food they look for, per sensed pixel.
+const float TARGET_SCENT = 4.0;
+
+int width = imageSize(trail_img).x;
+int height = imageSize(trail_img).y;
 
 uint hash(uint state) {
     state ^= 2747636419u;
@@ -278,7 +1054,25 @@ float normalize_from_hash(uint hash_val) {
 }
 
 
-float sense(Agent agent, float sensor_angle_offset) {
+bool inside(vec4 circle, vec2 pos) {
+    return distance(pos, circle.xy) <= circle.z;
+}
+
+// Whether an ant smells what it is looking for at the position.
+bool smells_target(Agent agent, vec2 pos) {
+    if (agent.carrying != 0) {
+        return inside(colony.nest, pos);
+    }
+    for (int i = 0; i < colony.food_pile_count; i++) {
+        if (colony.food_left[i] > 0 && inside(colony.food_piles[i], pos)) {
+            return true;
+        }
+    }
+    return false;
+}
+
+// Adds up the trail channels selected by `channels` around the sensor.
+float sense(Agent agent, float sensor_angle_offset, vec4 channels) {
     float sensor_centre_distance = 9.0;
     
     float sensor_angle = agent.angle + sensor_angle_offset;
@@ -286,20 +1080,92 @@ float sense(Agent agent, float sensor_angle_offset) {
     ivec2 sensor_centre = ivec2(agent.pos + (sensor_dir_norm * sensor_centre_distance));
     
     float sum = 0;
-    for (int x = -pc.sensor_radius; x <= pc.sensor_radius; x++) {
-        for (int y = -pc.sensor_radius; y <= pc.sensor_radius; y++) {
+    for (int x = -params.sensor_radius; x <= params.sensor_radius; x++) {
+        for (int y = -params.sensor_radius; y <= params.sensor_radius; y++) {
             ivec2 sample_pos = ivec2(sensor_centre.x + x, sensor_centre.y + y);
 
             if (sample_pos.x >= 0 && sample_pos.x < width && sample_pos.y >= 0 && sample_pos.y < height) {
                 vec4 value = imageLoad(trail_img, sample_pos);
-                sum += value.x + value.y + value.z;
+                sum += dot(value, channels);
+                vec4 attractant = imageLoad(attractant_img, sample_pos);
+                sum += params.attractant_weight * (attractant.x + attractant.y + attractant.z);
             }
         }
     }
+
+    if (colony.enabled != 0 && smells_target(agent, vec2(sensor_centre))) {
+        int sensor_size = 2 * params.sensor_radius + 1;
+        sum += TARGET_SCENT * float(sensor_size * sensor_size);
+    }
     
     return sum;
 }
 
+// The direction flocking steers an agent in, longer for a stronger pull.
+// Neighbours may already have moved in this step, which is a fraction of a pixel at most.
+vec2 flock(uint id, Agent agent) {
+    int columns = (width + int(CELL_SIZE) - 1) / int(CELL_SIZE);
+    int rows = (height + int(CELL_SIZE) - 1) / int(CELL_SIZE);
+    ivec2 cell = clamp(ivec2(agent.pos / CELL_SIZE), ivec2(0), ivec2(columns - 1, rows - 1));
+
+    vec2 separation = vec2(0.0);
+    vec2 heading = vec2(0.0);
+    vec2 centre = vec2(0.0);
+    uint neighbours = 0;
+    for (int y = max(cell.y - 1, 0); y <= min(cell.y + 1, rows - 1); y++) {
+        for (int x = max(cell.x - 1, 0); x <= min(cell.x + 1, columns - 1); x++) {
+            uint index = uint(y * columns + x);
+            uint first = cells.data[index * 2];
+            uint last = first + cells.data[index * 2 + 1];
+            for (uint i = first; i < last && neighbours < MAX_NEIGHBOURS; i++) {
+                uint other_id = sorted.data[i];
+                Agent other = buf.data[other_id];
+                vec2 offset = other.pos - agent.pos;
+                float dist = length(offset);
+                if (other_id == id || dist == 0.0 || dist > params.flock_radius) {
+                    continue;
+                }
+
+                // Closer neighbours push harder.
+                separation -= offset / (dist * dist);
+                heading += vec2(cos(other.angle), sin(other.angle));
+                centre += other.pos;
+                neighbours++;
+            }
+        }
+    }
+
+    if (neighbours == 0) {
+        return vec2(0.0);
+    }
+    float count = float(neighbours);
+    return params.separation_weight * separation * params.flock_radius / count
+        + params.alignment_weight * heading / count
+        + params.cohesion_weight * (centre / count - agent.pos) / params.flock_radius;
+}
+
+// Picks up food at a food pile, or drops it off at the nest. Either way the ant turns around.
+void forage(uint id, vec2 pos) {
+    Agent agent = buf.data[id];
+    if (agent.carrying == 0) {
+        for (int i = 0; i < colony.food_pile_count; i++) {
+            if (colony.food_left[i] > 0 && inside(colony.food_piles[i], pos)) {
+                // Other ants may take the last of the food in the same step.
+                if (atomicAdd(colony.food_left[i], -1) > 0) {
+                    buf.data[id].carrying = 1;
+                    buf.data[id].angle += PI;
+                    return;
+                }
+                atomicAdd(colony.food_left[i], 1);
+            }
+        }
+    } else if (inside(colony.nest, pos)) {
+        atomicAdd(colony.collected, 1);
+        buf.data[id].carrying = 0;
+        buf.data[id].angle += PI;
+    }
+}
+
 
 void main() {
     uint id = gl_GlobalInvocationID.x;
@@ -307,35 +1173,58 @@ void main() {
         return;
     }
     
-    int width = imageSize(out_img).x;
-    int height = imageSize(out_img).y;
+    int width = imageSize(trail_img).x;
+    int height = imageSize(trail_img).y;
 
     Agent agent = buf.data[id];
     uint random = hash(uint(agent.pos.y * width + agent.pos.x + hash(id)));
     
+    // Slime mold follows all trails. Ants follow the food trails to find food, and the home
+    // trails to bring it back.
+    vec4 channels = vec4(1.0, 1.0, 1.0, 0.0);
+    vec4 trail_color = params.trail_color;
+    if (colony.enabled != 0) {
+        channels = agent.carrying != 0 ? HOME_TRAIL : FOOD_TRAIL;
+        channels.a = 0.0;
+        trail_color = agent.carrying != 0 ? FOOD_TRAIL : HOME_TRAIL;
+    }
+
+    float turn_step = params.agent_turn_speed * params.delta_time;
+
     // Decide which way to turn.
-    float sense_forward = sense(agent, 0);
-    float sense_left = sense(agent, pc.sensor_angle_spacing);
-    float sense_right = sense(agent, -pc.sensor_angle_spacing);
-    
-    float random_steer_strength = normalize_from_hash(random);
-    
-    if (sense_forward > sense_left && sense_forward > sense_right) {
-        // Continue straight.
-    } else if (sense_forward < sense_left && sense_forward < sense_right) {
-        // Don't know whether to go left or right? Go random.
-        buf.data[id].angle += (random_steer_strength - 0.5) * 2 * pc.agent_turn_speed * pc.delta_time;
-    } else if (sense_left > sense_right) {
-        // Go left.
-        buf.data[id].angle += random_steer_strength * pc.agent_turn_speed * pc.delta_time;
-    } else if (sense_left < sense_right) {
-        // Go right.
-        buf.data[id].angle -= random_steer_strength * pc.agent_turn_speed * pc.delta_time;
+    if (params.trail_weight > 0.0) {
+        float sense_forward = sense(agent, 0, channels);
+        float sense_left = sense(agent, params.sensor_angle_spacing, channels);
+        float sense_right = sense(agent, -params.sensor_angle_spacing, channels);
+
+        float random_steer_strength = normalize_from_hash(random);
+        float trail_turn = params.trail_weight * turn_step;
+
+        if (sense_forward > sense_left && sense_forward > sense_right) {
+            // Continue straight.
+        } else if (sense_forward < sense_left && sense_forward < sense_right) {
+            // Don't know whether to go left or right? Go random.
+            buf.data[id].angle += (random_steer_strength - 0.5) * 2 * trail_turn;
+        } else if (sense_left > sense_right) {
+            // Go left.
+            buf.data[id].angle += random_steer_strength * trail_turn;
+        } else if (sense_left < sense_right) {
+            // Go right.
+            buf.data[id].angle -= random_steer_strength * trail_turn;
+        }
+    }
+
+    // Turn towards where the neighbours steer the agent, as far as the turn speed allows.
+    if (params.separation_weight > 0.0 || params.alignment_weight > 0.0 || params.cohesion_weight > 0.0) {
+        vec2 steer = flock(id, agent);
+        vec2 direction = vec2(cos(agent.angle), sin(agent.angle));
+        float angle_to_steer = atan(direction.x * steer.y - direction.y * steer.x, dot(direction, steer));
+        buf.data[id].angle += clamp(angle_to_steer, -1.0, 1.0) * min(length(steer), 1.0) * turn_step;
     }
     
     // Move agent according to angle and speed.
     vec2 unit_direction = vec2(cos(agent.angle), sin(agent.angle));
-    vec2 new_pos = agent.pos + unit_direction * pc.agent_speed * pc.delta_time;
+    vec2 new_pos = agent.pos + unit_direction * params.agent_speed * params.delta_time;
     
     // How far to move from the edge when bouncing against it.
     float edge_holdout = 0.01;
@@ -379,38 +1268,79 @@ void main() {
     
     
     buf.data[id].pos = new_pos;
+    if (colony.enabled != 0) {
+        forage(id, new_pos);
+    }
 
-    // Draw trail.
-    imageStore(out_img, ivec2(agent.pos), vec4(1.0, 0.0, 0.5, 1.0));
+    // Draw trail, where the agent ended up after bouncing.
+    // Agents on the same pixel add up, so dense streams leave stronger trails.
+    ivec2 trail_pos = ivec2(new_pos);
+    if (trail_pos.x < 0 || trail_pos.y < 0 || trail_pos.x >= width || trail_pos.y >= height) {
+        return;
+    }
+    uint index = uint(trail_pos.y * width + trail_pos.x) * 4;
+    uvec4 deposit = uvec4(trail_color * params.deposit_amount * DEPOSIT_SCALE + 0.5);
+    atomicAdd(deposits.data[index], deposit.r);
+    atomicAdd(deposits.data[index + 1], deposit.g);
+    atomicAdd(deposits.data[index + 2], deposit.b);
+    atomicAdd(deposits.data[index + 3], deposit.a);
 }
 "
-    }
+        }
+    };
 }
 
-pub mod blur_fade_shader {
-    vulkano_shaders::shader! {
+pub mod agent_shader {
+    agent_shader_module!("rgba8");
+}
+
+pub mod agent_shader_rgba16f {
+    agent_shader_module!("rgba16f");
+}
+
+pub mod agent_shader_rgba32f {
+    agent_shader_module!("rgba32f");
+}
+
+macro_rules! blur_fade_shader_module {
+    ($format:tt) => {
+        vulkano_shaders::shader! {
             ty: "compute",
+            define: [("TRAIL_FORMAT", $format)],
             src:
 "
 #version 450
 
 layout(local_size_x = 8, local_size_y = 8, local_size_z = 1) in;
 
-layout(set = 0, binding = 0, rgba8) uniform readonly image2D in_img;
-layout(set = 0, binding = 1, rgba8) uniform writeonly image2D out_img;
+// Has to match DEPOSIT_SCALE in the agent_shader.
+const float DEPOSIT_SCALE = 4096.0;
+
+layout(set = 0, binding = 0, TRAIL_FORMAT) uniform readonly image2D in_img;
+layout(set = 0, binding = 1, TRAIL_FORMAT) uniform writeonly image2D out_img;
+layout(set = 0, binding = 2) readonly buffer Deposits {
+    uint data[];
+} deposits;
 
-layout(push_constant) uniform PushConstantData {
+// Written by `Simulation::upload_parameters`, so the recorded command buffers can be reused.
+layout(set = 0, binding = 3) uniform Parameters {
     // How many time is passed per frame.
     float delta_time;
     // How much color is 'evaporated' per second.
     float evaporate_speed;
-} pc;
+    // In pixels.
+    int blur_radius;
+} params;
 
 void main() {
     int width = imageSize(in_img).x;
     int height = imageSize(in_img).y;
     
-    int blur_radius = 1;
+    if (gl_GlobalInvocationID.x >= width || gl_GlobalInvocationID.y >= height) {
+        return;
+    }
+    
+    int blur_radius = params.blur_radius;
     
     // ---- Blur ----
     vec4 sum = vec4(0.0, 0.0, 0.0, 0.0);
@@ -419,7 +1349,13 @@ void main() {
             ivec2 sample_pos = ivec2(gl_GlobalInvocationID.x + x, gl_GlobalInvocationID.y + y);
             
             if (sample_pos.x >= 0 && sample_pos.x < width && sample_pos.y >= 0 && sample_pos.y < height) {
-                sum += imageLoad(in_img, sample_pos);
+                uint index = uint(sample_pos.y * width + sample_pos.x) * 4;
+                vec4 deposit = vec4(deposits.data[index],
+                                deposits.data[index + 1],
+                                deposits.data[index + 2],
+                                deposits.data[index + 3]) / DEPOSIT_SCALE;
+                
+                sum += imageLoad(in_img, sample_pos) + deposit;
             }
         }
     }
@@ -427,13 +1363,70 @@ void main() {
     vec4 blurred = sum / ((blur_radius * 2 + 1) * (blur_radius * 2 + 1));
     
     // ---- Evaporate ----
-    vec4 result = vec4(max(0.0, blurred.x - pc.evaporate_speed * pc.delta_time),
-                    max(0.0, blurred.y - pc.evaporate_speed * pc.delta_time),
-                    max(0.0, blurred.z - pc.evaporate_speed * pc.delta_time),
-                    max(0.0, blurred.w - pc.evaporate_speed * pc.delta_time));
+    vec4 result = vec4(max(0.0, blurred.x - params.evaporate_speed * params.delta_time),
+                    max(0.0, blurred.y - params.evaporate_speed * params.delta_time),
+                    max(0.0, blurred.z - params.evaporate_speed * params.delta_time),
+                    max(0.0, blurred.w - params.evaporate_speed * params.delta_time));
     
-    imageStore(out_img, ivec2(gl_GlobalInvocationID.xy), result);
+    // Saturate like rgba8 does, so the agents behave the same in every trail format.
+    imageStore(out_img, ivec2(gl_GlobalInvocationID.xy), min(result, vec4(1.0)));
 }
 "
+        }
+    };
+}
+
+pub mod blur_fade_shader {
+    blur_fade_shader_module!("rgba8");
+}
+
+pub mod blur_fade_shader_rgba16f {
+    blur_fade_shader_module!("rgba16f");
+}
+
+pub mod blur_fade_shader_rgba32f {
+    blur_fade_shader_module!("rgba32f");
+}
+
+macro_rules! convert_shader_module {
+    ($format:tt) => {
+        vulkano_shaders::shader! {
+            ty: "compute",
+            define: [("TRAIL_FORMAT", $format)],
+            src:
+        "
+#version 450
+
+layout(local_size_x = 8, local_size_y = 8, local_size_z = 1) in;
+
+layout(set = 0, binding = 0, TRAIL_FORMAT) uniform readonly image2D trail_img;
+layout(set = 0, binding = 1, rgba8) uniform writeonly image2D display_img;
+
+void main() {
+    ivec2 pos = ivec2(gl_GlobalInvocationID.xy);
+    ivec2 size = imageSize(trail_img);
+    if (pos.x >= size.x || pos.y >= size.y) {
+        return;
     }
+
+    // The trails fade out the alpha channel as well, but the displayed and saved images
+    // should be opaque.
+    vec3 color = clamp(imageLoad(trail_img, pos).rgb, 0.0, 1.0);
+    imageStore(display_img, pos, vec4(color, 1.0));
+}
+"
+        }
+    };
+}
+
+pub mod convert_shader {
+    convert_shader_module!("rgba8");
+}
+
+pub mod convert_shader_rgba16f {
+    convert_shader_module!("rgba16f");
+}
+
+pub mod convert_shader_rgba32f {
+    convert_shader_module!("rgba32f");
 }
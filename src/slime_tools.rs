@@ -0,0 +1,230 @@
+use crate::attractant_window::{AttractantChange, AttractantWindow};
+use crate::colony::{ColonyChange, ColonySettings, ColonyWindow};
+use crate::error::SimError;
+use crate::parameters::ParameterSet;
+use crate::post::PostSettings;
+use crate::remote;
+use crate::remote::{RemoteServer, Settings};
+use crate::simulation::Simulation;
+use crate::statistics::{Statistics, StatisticsRecorder};
+use crate::statistics_window::StatisticsWindow;
+use crate::system::Tools;
+use imgui::Ui;
+use vulkano::sync::GpuFuture;
+
+/// The statistics, attractant, ant colony and remote control of the slime simulation.
+pub struct SlimeTools {
+    statistics: Statistics,
+    statistics_recorder: StatisticsRecorder,
+    statistics_window: StatisticsWindow,
+    /// The step whose statistics are still being computed on the gpu, if any.
+    pending_statistics: Option<u64>,
+    attractant_window: AttractantWindow,
+    /// Applied at the start of the next frame, once the gpu is idle.
+    pending_attractant: Option<AttractantChange>,
+    colony_window: ColonyWindow,
+    // Clicks on the trails place the nest and the food, once the gpu is idle.
+    pending_click: Option<[f32; 2]>,
+    pending_colony: Option<ColonyChange>,
+    frames_since_colony_status: u32,
+    remote_server: Option<RemoteServer>,
+}
+
+impl SlimeTools {
+    /// The nest and food piles are laid out with `colony_settings` once the colony is enabled.
+    pub fn new(
+        simulation: &Simulation,
+        colony_settings: ColonySettings,
+        remote_server: Option<RemoteServer>,
+    ) -> Result<SlimeTools, SimError> {
+        Ok(SlimeTools {
+            statistics: Statistics::new(simulation)?,
+            statistics_recorder: StatisticsRecorder::new(),
+            statistics_window: StatisticsWindow::new(),
+            pending_statistics: None,
+            attractant_window: AttractantWindow::new(),
+            pending_attractant: None,
+            colony_window: ColonyWindow::new(&simulation.config, colony_settings),
+            pending_click: None,
+            pending_colony: None,
+            frames_since_colony_status: 0,
+            remote_server,
+        })
+    }
+
+    fn record_statistics(&mut self, step: u64) -> Result<(), SimError> {
+        let result = self.statistics.measure(step)?;
+        if let Err(e) = self.statistics_recorder.record(result) {
+            self.statistics_window
+                .set_status(format!("Stopped recording: {}", e));
+        }
+        Ok(())
+    }
+}
+
+impl Tools<Simulation> for SlimeTools {
+    fn before_steps(
+        &mut self,
+        simulation: &mut Simulation,
+        parameters: &mut ParameterSet,
+        post_settings: &mut PostSettings,
+        step: &mut u64,
+        idle_gpu: &mut dyn FnMut() -> Result<(), SimError>,
+    ) -> Result<(), SimError> {
+        // ---- Collect statistics of a previous frame ----
+
+        if let Some(statistics_step) = self.pending_statistics {
+            if let Some(result) = self.statistics.read(statistics_step) {
+                if let Err(e) = self.statistics_recorder.record(result) {
+                    self.statistics_window
+                        .set_status(format!("Stopped recording: {}", e));
+                }
+                self.pending_statistics = None;
+            }
+        }
+
+        // ---- Handle remote control requests ----
+
+        if let Some(server) = &self.remote_server {
+            let requests = server.poll();
+            if requests.iter().any(|r| r.request.needs_idle_gpu()) {
+                idle_gpu()?;
+            }
+
+            let colony_settings = self.colony_window.colony.settings;
+            for pending in requests {
+                let mut settings = Settings {
+                    parameters,
+                    colony: &mut self.colony_window.colony.settings,
+                    post: post_settings,
+                };
+                let response = remote::handle(
+                    &pending.request,
+                    simulation,
+                    &mut settings,
+                    &mut self.statistics_recorder,
+                    step,
+                    server.snapshot_dir(),
+                );
+                pending.respond(response);
+            }
+
+            // The piles are laid out again, like when they are changed in the window.
+            if self.colony_window.colony.settings != colony_settings {
+                self.pending_colony = self.pending_colony.or(Some(ColonyChange::Layout));
+            }
+        }
+
+        // ---- Replace the attractant ----
+
+        if let Some(change) = self.pending_attractant.take() {
+            idle_gpu()?;
+            let status = match change {
+                AttractantChange::Load(path) => match image::open(&path) {
+                    Ok(image) => match simulation.set_attractant(Some(&image)) {
+                        Ok(()) => format!("Loaded '{}'", path.display()),
+                        Err(e) => format!("Failed to upload the image: {}", e),
+                    },
+                    Err(e) => format!("Failed to load '{}': {}", path.display(), e),
+                },
+                AttractantChange::Clear => match simulation.set_attractant(None) {
+                    Ok(()) => "Cleared".to_owned(),
+                    Err(e) => format!("Failed to clear: {}", e),
+                },
+            };
+            self.attractant_window.set_status(status);
+        }
+
+        // ---- Update the ant colony ----
+
+        if let Some(position) = self.pending_click.take() {
+            if let Some(change) = self.colony_window.click(position) {
+                self.pending_colony = self.pending_colony.or(Some(change));
+            }
+        }
+
+        self.frames_since_colony_status += 1;
+        let read_status = self.colony_window.colony.enabled
+            && (self.pending_colony.is_some() || self.frames_since_colony_status >= 30);
+        if read_status {
+            self.frames_since_colony_status = 0;
+            idle_gpu()?;
+
+            // Read first, so the piles keep the food the ants already took.
+            self.colony_window.set_status(simulation.colony_status()?);
+        }
+
+        if let Some(change) = self.pending_colony.take() {
+            idle_gpu()?;
+
+            simulation.set_colony(&self.colony_window.colony)?;
+            if change == ColonyChange::Restart {
+                *step = 0;
+                simulation.reset(simulation.config.seed)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn after_steps(
+        &mut self,
+        simulation: &Simulation,
+        future: Box<dyn GpuFuture>,
+        step: u64,
+        gpu_idle: bool,
+    ) -> Result<Box<dyn GpuFuture>, SimError> {
+        if !self.statistics_recorder.enabled {
+            return Ok(future);
+        }
+
+        if gpu_idle {
+            self.pending_statistics = None;
+            self.record_statistics(step)?;
+            return Ok(future);
+        }
+
+        // Only measure when the previous results have been read,
+        // so the gpu does not overwrite them.
+        if self.pending_statistics.is_some() {
+            return Ok(future);
+        }
+        let command_buffer = self.statistics.create_command_buffer()?;
+        self.pending_statistics = Some(step);
+        Ok(future
+            .then_execute(simulation.queue.clone(), command_buffer)?
+            .boxed())
+    }
+
+    fn draw(&mut self, ui: &Ui, parameters: &mut ParameterSet) {
+        self.statistics_window
+            .draw(ui, &mut self.statistics_recorder);
+        if let Some(change) = self
+            .attractant_window
+            .draw(ui, &mut parameters.sim.attractant_weight)
+        {
+            self.pending_attractant = Some(change);
+        }
+        if let Some(change) = self.colony_window.draw(ui) {
+            // Restarting takes precedence over a changed layout.
+            if self.pending_colony != Some(ColonyChange::Restart) {
+                self.pending_colony = Some(change);
+            }
+        }
+    }
+
+    fn click(&mut self, position: [f32; 2]) {
+        self.pending_click = Some(position);
+    }
+
+    /// The statistics are measured on the new buffers. The colony is laid out again in the
+    /// new simulation, the attractant is not.
+    fn resized(&mut self, simulation: &Simulation) -> Result<(), SimError> {
+        self.statistics = Statistics::new(simulation)?;
+        self.pending_statistics = None;
+        simulation.set_colony(&self.colony_window.colony)?;
+        self.attractant_window
+            .set_status("Cleared by resizing".to_owned());
+        Ok(())
+    }
+}
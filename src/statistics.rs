@@ -0,0 +1,444 @@
+use crate::error::SimError;
+use crate::model::Model;
+use crate::simulation::Simulation;
+use serde::Serialize;
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+use std::sync::Arc;
+use vulkano::buffer::{BufferUsage, CpuAccessibleBuffer};
+use vulkano::command_buffer::{AutoCommandBuffer, AutoCommandBufferBuilder};
+use vulkano::descriptor::descriptor_set::{DescriptorSet, PersistentDescriptorSet};
+use vulkano::descriptor::pipeline_layout::PipelineLayout;
+use vulkano::descriptor::PipelineLayoutAbstract;
+use vulkano::device::{Device, Queue};
+use vulkano::pipeline::ComputePipeline;
+use vulkano::sync;
+use vulkano::sync::GpuFuture;
+
+/// Has to match the size of the histogram in `trail_stats_shader`.
+pub const HISTOGRAM_BINS: usize = 32;
+/// Has to match the size of the bins in `heading_stats_shader`.
+pub const HEADING_BINS: usize = 16;
+
+/// Intensity above which a pixel counts as covered by trails.
+const COVERAGE_THRESHOLD: f32 = 0.05;
+/// How many samples are kept for the live graphs.
+const HISTORY_LENGTH: usize = 600;
+
+/// Measurements of the trail image and the agents after a single step.
+#[derive(Clone, Copy, Debug, Serialize)]
+pub struct StepStatistics {
+    pub step: u64,
+    /// Fraction of the pixels that have trails on them.
+    pub coverage: f32,
+    /// Intensity is the average of the red, green and blue channels, in the range [0 - 1].
+    pub mean_intensity: f32,
+    pub max_intensity: f32,
+    /// The summed intensity of all pixels.
+    pub total_mass: f32,
+    /// Amount of pixels per intensity range.
+    pub histogram: [u32; HISTOGRAM_BINS],
+    /// Amount of agents per heading range, starting at 0 radians.
+    pub headings: [u32; HEADING_BINS],
+}
+
+/// Reduces the trail image and the agents buffer to `StepStatistics` on the gpu.
+pub struct Statistics {
+    device: Arc<Device>,
+    queue: Arc<Queue>,
+    pixel_count: u32,
+    image_dimensions: [u32; 2],
+    agent_amount: u32,
+    trail_pipeline: Arc<ComputePipeline<PipelineLayout<trail_stats_shader::Layout>>>,
+    /// Reads the simulation's `result_image`.
+    trail_set: Arc<dyn DescriptorSet + Send + Sync>,
+    trail_buffer: Arc<CpuAccessibleBuffer<trail_stats_shader::ty::Stats>>,
+    heading_pipeline: Arc<ComputePipeline<PipelineLayout<heading_stats_shader::Layout>>>,
+    heading_set: Arc<dyn DescriptorSet + Send + Sync>,
+    heading_buffer: Arc<CpuAccessibleBuffer<heading_stats_shader::ty::Headings>>,
+}
+
+impl Statistics {
+    pub fn new(simulation: &Simulation) -> Result<Statistics, SimError> {
+        let device = simulation.device.clone();
+        let dimensions = simulation.result_image().dimensions();
+
+        let trail_buffer = CpuAccessibleBuffer::from_data(
+            device.clone(),
+            BufferUsage::all(),
+            false,
+            trail_stats_shader::ty::Stats {
+                covered: 0,
+                intensity_sum_low: 0,
+                intensity_sum_high: 0,
+                intensity_max: 0,
+                histogram: [0; HISTOGRAM_BINS],
+            },
+        )?;
+
+        let heading_buffer = CpuAccessibleBuffer::from_data(
+            device.clone(),
+            BufferUsage::all(),
+            false,
+            heading_stats_shader::ty::Headings {
+                bins: [0; HEADING_BINS],
+            },
+        )?;
+
+        let trail_shader = trail_stats_shader::Shader::load(device.clone())?;
+        let trail_pipeline = Arc::new(ComputePipeline::new(
+            device.clone(),
+            &trail_shader.main_entry_point(),
+            &(),
+            None,
+        )?);
+        let trail_set = Arc::new(
+            PersistentDescriptorSet::start(
+                trail_pipeline
+                    .layout()
+                    .descriptor_set_layout(0)
+                    .unwrap()
+                    .clone(),
+            )
+            .add_image(simulation.result_image().clone())?
+            .add_buffer(trail_buffer.clone())?
+            .build()?,
+        );
+
+        let heading_shader = heading_stats_shader::Shader::load(device.clone())?;
+        let heading_pipeline = Arc::new(ComputePipeline::new(
+            device.clone(),
+            &heading_shader.main_entry_point(),
+            &(),
+            None,
+        )?);
+        let heading_set = Arc::new(
+            PersistentDescriptorSet::start(
+                heading_pipeline
+                    .layout()
+                    .descriptor_set_layout(0)
+                    .unwrap()
+                    .clone(),
+            )
+            .add_buffer(simulation.agents_buffer.clone())?
+            .add_buffer(heading_buffer.clone())?
+            .build()?,
+        );
+
+        Ok(Statistics {
+            device,
+            queue: simulation.queue.clone(),
+            pixel_count: dimensions.width() * dimensions.height(),
+            image_dimensions: [dimensions.width(), dimensions.height()],
+            agent_amount: simulation.agent_amount,
+            trail_pipeline,
+            trail_set,
+            trail_buffer,
+            heading_pipeline,
+            heading_set,
+            heading_buffer,
+        })
+    }
+
+    /// Clears the previous results and runs the reduction passes over the `result_image`
+    /// of the simulation. The results can be read with `read` once the command buffer
+    /// has finished.
+    pub fn create_command_buffer(&self) -> Result<AutoCommandBuffer, SimError> {
+        let mut builder = AutoCommandBufferBuilder::new(self.device.clone(), self.queue.family())?;
+        builder
+            .fill_buffer(self.trail_buffer.clone(), 0)?
+            .fill_buffer(self.heading_buffer.clone(), 0)?
+            .dispatch(
+                [
+                    (self.image_dimensions[0] + 15) / 16,
+                    (self.image_dimensions[1] + 15) / 16,
+                    1,
+                ],
+                self.trail_pipeline.clone(),
+                self.trail_set.clone(),
+                trail_stats_shader::ty::PushConstantData {
+                    coverage_threshold: COVERAGE_THRESHOLD,
+                },
+            )?
+            .dispatch(
+                [(self.agent_amount + 63) / 64, 1, 1],
+                self.heading_pipeline.clone(),
+                self.heading_set.clone(),
+                (),
+            )?;
+        Ok(builder.build()?)
+    }
+
+    /// Reads the results of the last executed command buffer.
+    /// Returns `None` when the gpu is still busy with them.
+    pub fn read(&self, step: u64) -> Option<StepStatistics> {
+        self.try_read(step).ok()
+    }
+
+    fn try_read(&self, step: u64) -> Result<StepStatistics, SimError> {
+        let trail = self.trail_buffer.read()?;
+        let headings = self.heading_buffer.read()?;
+
+        let pixel_count = self.pixel_count.max(1) as f32;
+        let intensity_sum =
+            ((trail.intensity_sum_high as u64) << 32) | trail.intensity_sum_low as u64;
+        let total_mass = intensity_sum as f32 / 255.0;
+
+        Ok(StepStatistics {
+            step,
+            coverage: trail.covered as f32 / pixel_count,
+            mean_intensity: total_mass / pixel_count,
+            max_intensity: trail.intensity_max as f32 / 255.0,
+            total_mass,
+            histogram: trail.histogram,
+            headings: headings.bins,
+        })
+    }
+
+    /// Runs the reduction passes and waits for the results.
+    pub fn measure(&self, step: u64) -> Result<StepStatistics, SimError> {
+        sync::now(self.device.clone())
+            .then_execute(self.queue.clone(), self.create_command_buffer()?)?
+            .then_signal_fence_and_flush()?
+            .wait(None)?;
+
+        self.try_read(step)
+    }
+}
+
+/// Streams statistics to a csv file, one row per step.
+pub struct CsvWriter {
+    writer: BufWriter<File>,
+}
+
+impl CsvWriter {
+    pub fn create<P: AsRef<Path>>(path: P) -> std::io::Result<CsvWriter> {
+        let mut writer = BufWriter::new(File::create(path)?);
+
+        write!(
+            writer,
+            "step,coverage,mean_intensity,max_intensity,total_mass"
+        )?;
+        for i in 0..HISTOGRAM_BINS {
+            write!(writer, ",histogram_{}", i)?;
+        }
+        for i in 0..HEADING_BINS {
+            write!(writer, ",heading_{}", i)?;
+        }
+        writeln!(writer)?;
+
+        Ok(CsvWriter { writer })
+    }
+
+    pub fn write(&mut self, statistics: &StepStatistics) -> std::io::Result<()> {
+        write!(
+            self.writer,
+            "{},{},{},{},{}",
+            statistics.step,
+            statistics.coverage,
+            statistics.mean_intensity,
+            statistics.max_intensity,
+            statistics.total_mass
+        )?;
+        for count in statistics.histogram.iter() {
+            write!(self.writer, ",{}", count)?;
+        }
+        for count in statistics.headings.iter() {
+            write!(self.writer, ",{}", count)?;
+        }
+        writeln!(self.writer)
+    }
+}
+
+/// Keeps the recent statistics for the live graphs, and optionally records them to csv.
+pub struct StatisticsRecorder {
+    /// Whether statistics should be measured at all.
+    pub enabled: bool,
+    history: VecDeque<StepStatistics>,
+    csv: Option<CsvWriter>,
+}
+
+impl Default for StatisticsRecorder {
+    fn default() -> Self {
+        StatisticsRecorder::new()
+    }
+}
+
+impl StatisticsRecorder {
+    pub fn new() -> StatisticsRecorder {
+        StatisticsRecorder {
+            enabled: false,
+            history: VecDeque::with_capacity(HISTORY_LENGTH),
+            csv: None,
+        }
+    }
+
+    /// The recorded statistics, oldest first.
+    pub fn history(&self) -> &VecDeque<StepStatistics> {
+        &self.history
+    }
+
+    pub fn latest(&self) -> Option<&StepStatistics> {
+        self.history.back()
+    }
+
+    pub fn is_recording(&self) -> bool {
+        self.csv.is_some()
+    }
+
+    /// Starts writing every recorded step to the csv file.
+    pub fn start_recording<P: AsRef<Path>>(&mut self, path: P) -> std::io::Result<()> {
+        self.csv = Some(CsvWriter::create(path)?);
+        Ok(())
+    }
+
+    pub fn stop_recording(&mut self) {
+        self.csv = None;
+    }
+
+    /// Stops recording when writing to the csv file fails.
+    pub fn record(&mut self, statistics: StepStatistics) -> std::io::Result<()> {
+        if self.history.len() >= HISTORY_LENGTH {
+            self.history.pop_front();
+        }
+        self.history.push_back(statistics);
+
+        if let Some(csv) = &mut self.csv {
+            if let Err(e) = csv.write(&statistics) {
+                self.csv = None;
+                return Err(e);
+            }
+        }
+        Ok(())
+    }
+}
+
+pub mod trail_stats_shader {
+    vulkano_shaders::shader! {
+        ty: "compute",
+        src:
+"
+#version 450
+
+const uint HISTOGRAM_BINS = 32;
+
+layout(local_size_x = 16, local_size_y = 16, local_size_z = 1) in;
+
+layout(set = 0, binding = 0, rgba8) uniform readonly image2D trail_img;
+layout(set = 0, binding = 1) buffer Stats {
+    uint covered;
+    // Intensities are summed as fixed point, with 255 meaning 1.0. The sum would overflow
+    // 32 bits from about 4096x4096 bright pixels, so it carries into a second word.
+    uint intensity_sum_low;
+    uint intensity_sum_high;
+    uint intensity_max;
+    uint histogram[HISTOGRAM_BINS];
+} stats;
+
+layout(push_constant) uniform PushConstantData {
+    // Intensity above which a pixel counts as covered.
+    float coverage_threshold;
+} pc;
+
+// Every work group reduces its own pixels first, so that only one atomic operation
+// per work group has to go to the global buffer.
+shared uint local_covered;
+shared uint local_sum;
+shared uint local_max;
+shared uint local_histogram[HISTOGRAM_BINS];
+
+void main() {
+    uint local_index = gl_LocalInvocationIndex;
+    if (local_index == 0) {
+        local_covered = 0;
+        local_sum = 0;
+        local_max = 0;
+    }
+    if (local_index < HISTOGRAM_BINS) {
+        local_histogram[local_index] = 0;
+    }
+    barrier();
+
+    ivec2 pos = ivec2(gl_GlobalInvocationID.xy);
+    ivec2 size = imageSize(trail_img);
+    if (pos.x < size.x && pos.y < size.y) {
+        vec4 value = imageLoad(trail_img, pos);
+        float intensity = clamp((value.x + value.y + value.z) / 3.0, 0.0, 1.0);
+        uint fixed_intensity = uint(intensity * 255.0 + 0.5);
+
+        if (intensity > pc.coverage_threshold) {
+            atomicAdd(local_covered, 1);
+        }
+        atomicAdd(local_sum, fixed_intensity);
+        atomicMax(local_max, fixed_intensity);
+        atomicAdd(local_histogram[min(uint(intensity * HISTOGRAM_BINS), HISTOGRAM_BINS - 1)], 1);
+    }
+    barrier();
+
+    if (local_index == 0) {
+        atomicAdd(stats.covered, local_covered);
+        uint previous_sum = atomicAdd(stats.intensity_sum_low, local_sum);
+        if (previous_sum + local_sum < previous_sum) {
+            atomicAdd(stats.intensity_sum_high, 1);
+        }
+        atomicMax(stats.intensity_max, local_max);
+    }
+    if (local_index < HISTOGRAM_BINS) {
+        atomicAdd(stats.histogram[local_index], local_histogram[local_index]);
+    }
+}
+"
+    }
+}
+
+pub mod heading_stats_shader {
+    vulkano_shaders::shader! {
+        ty: "compute",
+        src:
+"
+#version 450
+
+const float PI = 3.1415926535897932384626433832795;
+const uint HEADING_BINS = 16;
+
+struct Agent {
+    vec2 pos;
+    float angle;
+    uint carrying;
+};
+
+layout(local_size_x = 64, local_size_y = 1, local_size_z = 1) in;
+
+layout(set = 0, binding = 0) readonly buffer Agents {
+    Agent data[];
+} agents;
+layout(set = 0, binding = 1) buffer Headings {
+    uint bins[HEADING_BINS];
+} headings;
+
+shared uint local_bins[HEADING_BINS];
+
+void main() {
+    uint local_index = gl_LocalInvocationIndex;
+    if (local_index < HEADING_BINS) {
+        local_bins[local_index] = 0;
+    }
+    barrier();
+
+    uint id = gl_GlobalInvocationID.x;
+    if (id < agents.data.length()) {
+        float angle = mod(agents.data[id].angle, 2.0 * PI);
+        uint bin = min(uint(angle / (2.0 * PI) * HEADING_BINS), HEADING_BINS - 1);
+        atomicAdd(local_bins[bin], 1);
+    }
+    barrier();
+
+    if (local_index < HEADING_BINS) {
+        atomicAdd(headings.bins[local_index], local_bins[local_index]);
+    }
+}
+"
+    }
+}
@@ -0,0 +1,112 @@
+use crate::statistics::{StatisticsRecorder, StepStatistics};
+use imgui::{im_str, Condition, ImString, Ui, Window};
+
+/// Plots the recorded statistics, and lets the user record them to a csv file.
+pub struct StatisticsWindow {
+    path: ImString,
+    status: String,
+}
+
+impl Default for StatisticsWindow {
+    fn default() -> Self {
+        StatisticsWindow::new()
+    }
+}
+
+impl StatisticsWindow {
+    pub fn new() -> StatisticsWindow {
+        let mut path = ImString::with_capacity(256);
+        path.push_str("statistics.csv");
+
+        StatisticsWindow {
+            path,
+            status: String::new(),
+        }
+    }
+
+    /// Shows an error from recording to csv.
+    pub fn set_status(&mut self, status: String) {
+        self.status = status;
+    }
+
+    pub fn draw(&mut self, ui: &Ui, recorder: &mut StatisticsRecorder) {
+        let window = self;
+
+        Window::new(im_str!("Statistics"))
+            .position([790.0, 10.0], Condition::FirstUseEver)
+            .size([360.0, 480.0], Condition::FirstUseEver)
+            .build(ui, || {
+                ui.checkbox(im_str!("Measure"), &mut recorder.enabled);
+                if !recorder.enabled {
+                    return;
+                }
+
+                let series = |f: fn(&StepStatistics) -> f32| -> Vec<f32> {
+                    recorder.history().iter().map(f).collect()
+                };
+                let coverage = series(|s| s.coverage);
+                let mean = series(|s| s.mean_intensity);
+                let max = series(|s| s.max_intensity);
+                let mass = series(|s| s.total_mass);
+
+                if let Some(latest) = recorder.latest() {
+                    ui.text(format!("Step: {}", latest.step));
+                    ui.text(format!("Coverage: {:.3}", latest.coverage));
+                    ui.text(format!(
+                        "Intensity: mean {:.3}, max {:.3}",
+                        latest.mean_intensity, latest.max_intensity
+                    ));
+                    ui.text(format!("Total mass: {:.0}", latest.total_mass));
+                }
+
+                ui.plot_lines(im_str!("Coverage"), &coverage)
+                    .graph_size([0.0, 40.0])
+                    .scale_min(0.0)
+                    .scale_max(1.0)
+                    .build();
+                ui.plot_lines(im_str!("Mean"), &mean)
+                    .graph_size([0.0, 40.0])
+                    .scale_min(0.0)
+                    .build();
+                ui.plot_lines(im_str!("Max"), &max)
+                    .graph_size([0.0, 40.0])
+                    .scale_min(0.0)
+                    .scale_max(1.0)
+                    .build();
+                ui.plot_lines(im_str!("Mass"), &mass)
+                    .graph_size([0.0, 40.0])
+                    .scale_min(0.0)
+                    .build();
+
+                if let Some(latest) = recorder.latest() {
+                    // The empty bin dwarfs the rest, so it is left out.
+                    let histogram: Vec<f32> =
+                        latest.histogram[1..].iter().map(|&c| c as f32).collect();
+                    let headings: Vec<f32> = latest.headings.iter().map(|&c| c as f32).collect();
+                    ui.plot_histogram(im_str!("Intensities"), &histogram)
+                        .graph_size([0.0, 50.0])
+                        .scale_min(0.0)
+                        .build();
+                    ui.plot_histogram(im_str!("Headings"), &headings)
+                        .graph_size([0.0, 50.0])
+                        .scale_min(0.0)
+                        .build();
+                }
+
+                ui.separator();
+                ui.input_text(im_str!("Csv file"), &mut window.path).build();
+                if !recorder.is_recording() {
+                    if ui.button(im_str!("Start recording"), [0.0, 0.0]) {
+                        window.status = match recorder.start_recording(window.path.to_str()) {
+                            Ok(()) => format!("Recording to '{}'", window.path.to_str()),
+                            Err(e) => format!("Failed to create csv: {}", e),
+                        };
+                    }
+                } else if ui.button(im_str!("Stop recording"), [0.0, 0.0]) {
+                    recorder.stop_recording();
+                    window.status = "Stopped recording".to_owned();
+                }
+                ui.text(&window.status);
+            });
+    }
+}
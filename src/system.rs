@@ -1,15 +1,21 @@
-use crate::simulation::agent_shader::ty::PushConstantData;
-use crate::simulation::blur_fade_shader;
-use crate::simulation::Simulation;
-use imgui::{Context, Ui};
-use imgui_vulkano_renderer::Renderer;
+use crate::device;
+use crate::device::DeviceSelector;
+use crate::error::SimError;
+use crate::model::{Model, Snapshot};
+use crate::post::{PostProcessor, PostSettings};
+use crate::profiler::{GpuTimer, Profiler};
+use crate::screenshot;
+use crate::screenshot::Screenshots;
+use imgui::{Context, Textures, Ui};
+use imgui_vulkano_renderer::{Renderer, Texture};
 use imgui_winit_support::{HiDpiMode, WinitPlatform};
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
 use std::time::{Duration, Instant};
 use vulkano::command_buffer::AutoCommandBufferBuilder;
-use vulkano::device::{Device, DeviceExtensions, Queue};
+use vulkano::device::{Device, DeviceExtensions, Features, Queue};
 use vulkano::image::{ImageUsage, SwapchainImage};
-use vulkano::instance::{Instance, PhysicalDevice};
+use vulkano::instance::{Instance, QueueFamily};
 use vulkano::swapchain;
 use vulkano::swapchain::{
     AcquireError, ColorSpace, FullscreenExclusive, PresentMode, Surface, SurfaceTransform,
@@ -18,78 +24,164 @@ use vulkano::swapchain::{
 use vulkano::sync;
 use vulkano::sync::{FlushError, GpuFuture};
 use vulkano_win::VkSurfaceBuild;
-use winit::event::{Event, WindowEvent};
+use winit::event::{ElementState, Event, KeyboardInput, MouseButton, VirtualKeyCode, WindowEvent};
 use winit::event_loop::{ControlFlow, EventLoop};
-use winit::window::{Window, WindowBuilder};
+use winit::window::{Fullscreen, Window, WindowBuilder};
+
+#[derive(Clone, Copy, Debug)]
+pub struct WindowConfig {
+    pub width: u32,
+    pub height: u32,
+    pub fullscreen: bool,
+    /// Simulation steps per displayed frame, all submitted together.
+    pub steps_per_frame: u32,
+    /// Resize the model along with the window, which starts it over.
+    pub resize_with_window: bool,
+}
+
+impl Default for WindowConfig {
+    fn default() -> Self {
+        WindowConfig {
+            width: 2000,
+            height: 1400,
+            fullscreen: false,
+            steps_per_frame: 1,
+            resize_with_window: false,
+        }
+    }
+}
 
 pub struct System {
     pub event_loop: EventLoop<()>,
     pub device: Arc<Device>,
+    /// Draws to the window.
     pub queue: Arc<Queue>,
+    /// Runs the simulation. The same as `queue` when the device has no separate compute family.
+    pub compute_queue: Arc<Queue>,
     pub surface: Arc<Surface<Window>>,
     pub swapchain: Arc<Swapchain<Window>>,
     pub images: Vec<Arc<SwapchainImage<Window>>>,
     pub imgui: Context,
     pub platform: WinitPlatform,
     pub renderer: Renderer,
+    /// Can be changed from the performance window while running.
+    pub steps_per_frame: u32,
+    pub resize_with_window: bool,
 }
 
+/// Windows and controls that only make sense for one model, added to `System::main_loop`.
+/// The unit type adds nothing.
+pub trait Tools<M: Model> {
+    /// Called at the start of every frame, before the steps are submitted. `idle_gpu` waits
+    /// for the gpu to finish the previous frame, which is needed before changing the model.
+    /// An error stops the main loop.
+    fn before_steps(
+        &mut self,
+        _model: &mut M,
+        _parameters: &mut M::Parameters,
+        _post_settings: &mut PostSettings,
+        _step: &mut u64,
+        _idle_gpu: &mut dyn FnMut() -> Result<(), SimError>,
+    ) -> Result<(), SimError> {
+        Ok(())
+    }
+
+    /// Can add work after the steps of a frame. When `gpu_idle`, the steps have already
+    /// finished, like while profiling. An error stops the main loop.
+    fn after_steps(
+        &mut self,
+        _model: &M,
+        future: Box<dyn GpuFuture>,
+        _step: u64,
+        _gpu_idle: bool,
+    ) -> Result<Box<dyn GpuFuture>, SimError> {
+        Ok(future)
+    }
+
+    fn draw(&mut self, _ui: &Ui, _parameters: &mut M::Parameters) {}
+
+    /// A left click on the model that imgui did not use, in pixels of the model.
+    fn click(&mut self, _position: [f32; 2]) {}
+
+    /// Called after the model was resized, which replaces its buffers and images.
+    fn resized(&mut self, _model: &M) -> Result<(), SimError> {
+        Ok(())
+    }
+}
+
+impl<M: Model> Tools<M> for () {}
+
 impl System {
-    pub fn init(window_title: &str) -> System {
+    /// Uses the device picked by the selector, as long as it can draw to the window.
+    pub fn init(
+        window_title: &str,
+        window_config: &WindowConfig,
+        selector: &DeviceSelector,
+    ) -> Result<System, SimError> {
         // Basic commands taken from the vulkano imgui examples:
         // https://github.com/Tenebryo/imgui-vulkano-renderer/blob/master/examples/support/mod.rs
 
         let instance = {
             let extensions = vulkano_win::required_extensions();
-            Instance::new(None, &extensions, None).expect("Failed to create instance.")
+            Instance::new(None, &extensions, None)?
         };
 
-        let physical = PhysicalDevice::enumerate(&instance)
-            .next()
-            .expect("No device available");
-
         let event_loop = EventLoop::new();
-        let surface = WindowBuilder::new()
+        let mut window_builder = WindowBuilder::new()
             .with_title(window_title.to_owned())
             .with_inner_size(winit::dpi::PhysicalSize {
-                width: 2000,
-                height: 1400,
-            })
+                width: window_config.width,
+                height: window_config.height,
+            });
+        if window_config.fullscreen {
+            window_builder = window_builder.with_fullscreen(Some(Fullscreen::Borderless(None)));
+        }
+        let surface = window_builder
             .build_vk_surface(&event_loop, instance.clone())
-            .unwrap();
+            .map_err(|e| SimError::Window(e.to_string()))?;
+
+        let physical = device::select(&instance, selector, |p| {
+            DeviceExtensions::supported_by_device(p).khr_swapchain
+                && p.queue_families().any(|q| window_queue_family(&surface, q))
+        })?;
 
         let queue_family = physical
             .queue_families()
-            .find(|&q|
-                q.supports_graphics() && q.explicitly_supports_transfers()
-                && surface.is_supported(q).unwrap_or(false)
-            )
-            .expect("Device does not have a queue family that can draw to the window and supports transfers.");
+            .find(|&q| window_queue_family(&surface, q))
+            .ok_or_else(|| {
+                SimError::NoDevice(
+                    "the device has no queue family that can draw to the window, compute and transfer"
+                        .to_owned(),
+                )
+            })?;
+
+        // Prefer a family that can only compute, as that usually runs on its own hardware.
+        let compute_family = physical
+            .queue_families()
+            .filter(|&q| q.supports_compute() && q.id() != queue_family.id())
+            .min_by_key(|&q| q.supports_graphics());
 
         let (device, mut queues) = {
             let device_ext = DeviceExtensions {
                 khr_swapchain: true,
-                // Needed for compute shaders.
-                khr_storage_buffer_storage_class: true,
-                ..DeviceExtensions::none()
+                ..device::required_extensions(physical)?
             };
 
-            Device::new(
-                physical,
-                physical.supported_features(),
-                &device_ext,
-                [(queue_family, 0.5)].iter().cloned(),
-            )
-            .expect("Failed to create device")
+            let mut families = vec![(queue_family, 0.5)];
+            families.extend(compute_family.map(|family| (family, 0.5)));
+
+            Device::new(physical, &Features::none(), &device_ext, families)?
         };
 
         let queue = queues.next().unwrap();
+        // Without a separate compute family, the simulation shares the graphics queue.
+        let compute_queue = queues.next().unwrap_or_else(|| queue.clone());
 
         let format;
         let (swapchain, images) = {
             let caps = surface
                 .capabilities(physical)
-                .expect("Failed to get capabilities.");
+                .map_err(|e| SimError::Window(e.to_string()))?;
             format = caps.supported_formats[0].0;
             let dimensions = caps.current_extent.unwrap_or([1280, 1024]);
             let alpha = caps.supported_composite_alpha.iter().next().unwrap();
@@ -115,7 +207,7 @@ impl System {
                 true,
                 ColorSpace::SrgbNonLinear,
             )
-            .expect("Failed to create swapchain")
+            .map_err(|e| SimError::Window(e.to_string()))?
         };
 
         let mut imgui = Context::create();
@@ -125,33 +217,47 @@ impl System {
         platform.attach_window(imgui.io_mut(), &surface.window(), HiDpiMode::Rounded);
 
         let renderer = Renderer::init(&mut imgui, device.clone(), queue.clone(), format)
-            .expect("Failed to initialize renderer");
+            .map_err(|e| SimError::Window(format!("{:?}", e)))?;
 
-        System {
+        Ok(System {
             event_loop,
             device,
             queue,
+            compute_queue,
             surface,
             swapchain,
             images,
             imgui,
             platform,
             renderer,
-        }
+            steps_per_frame: window_config.steps_per_frame.max(1),
+            resize_with_window: window_config.resize_with_window,
+        })
     }
 
-    pub fn main_loop<
-        F: FnMut(
-                &mut bool,
-                &mut PushConstantData,
-                &mut blur_fade_shader::ty::PushConstantData,
-                &mut Ui,
-            ) + 'static,
-    >(
+    /// Runs any model with the display, post-processing, screenshots and profiler.
+    /// The `tools` add what only makes sense for the model, and `run_ui` draws its parameters.
+    /// `run_ui` is also given the amount of steps submitted in the frame.
+    ///
+    /// Errors while setting up are returned. The event loop itself never returns: once the
+    /// window is closed, or an error stops the loop, `on_exit` is called with the result,
+    /// and the process exits after it.
+    pub fn main_loop<M, T, F, E>(
         self,
-        simulation: Simulation,
+        mut model: M,
+        mut parameters: M::Parameters,
+        mut post_settings: PostSettings,
+        mut tools: T,
         mut run_ui: F,
-    ) {
+        on_exit: E,
+    ) -> Result<(), SimError>
+    where
+        M: Model + 'static,
+        M::Parameters: 'static,
+        T: Tools<M> + 'static,
+        F: FnMut(&mut bool, &mut M::Parameters, &mut Ui, &mut Textures<Texture>, u32) + 'static,
+        E: FnOnce(Result<(), SimError>) + 'static,
+    {
         let System {
             event_loop,
             device,
@@ -162,6 +268,8 @@ impl System {
             mut imgui,
             mut platform,
             mut renderer,
+            mut steps_per_frame,
+            resize_with_window,
             ..
         } = self;
 
@@ -172,118 +280,275 @@ impl System {
         let mut previous_frame_end = Some(sync::now(device.clone()).boxed());
         let mut last_redraw = Instant::now();
 
-        let mut sim_parameters: PushConstantData = PushConstantData {
-            // Pixels per second.
-            agent_speed: 100.0,
-            // Radians per second.
-            agent_turn_speed: 50.0,
-            sensor_radius: 1,
-            // In the range [0 - PI]
-            sensor_angle_spacing: 0.18,
-            // Seconds per frame. (60fps)
-            delta_time: 0.016667,
-        };
+        let mut profiler = Profiler::new();
+        // Times the display pass on the graphics queue while profiling.
+        let display_timer = GpuTimer::new(device.clone(), queue.clone(), 2)?;
 
-        let mut fade_parameters: blur_fade_shader::ty::PushConstantData =
-            blur_fade_shader::ty::PushConstantData {
-                // Seconds per frame. (60fps)
-                delta_time: 0.016667,
-                evaporate_speed: 0.9,
-            };
+        // Runs on the graphics queue, as part of displaying the model.
+        let mut post_processor = PostProcessor::new(&device, &queue, model.result_image())?;
+
+        let mut screenshots = Screenshots::new();
+        // Both are handled at the start of the next frame, once the gpu is idle.
+        let mut take_screenshot = false;
+        let mut dropped_file: Option<PathBuf> = None;
+        // Clicks on the image are passed on to the tools.
+        let mut cursor = [0.0f32; 2];
+        let mut step: u64 = 0;
 
         // target 60 fps
         let target_frame_time = Duration::from_millis(1000 / 60);
 
+        // An error stops the loop, and is handed to `on_exit` once it has stopped.
+        let mut exit_error: Option<SimError> = None;
+        let mut on_exit = Some(on_exit);
+
         event_loop.run(move |event, _, control_flow| {
+            if let Event::LoopDestroyed = event {
+                if let Some(on_exit) = on_exit.take() {
+                    on_exit(exit_error.take().map_or(Ok(()), Err));
+                }
+                return;
+            }
+            // Nothing is drawn anymore once the loop is stopping.
+            if matches!(*control_flow, ControlFlow::Exit) {
+                return;
+            }
+
             *control_flow = ControlFlow::Wait;
             match event {
                 Event::MainEventsCleared => {
-                    platform
-                        .prepare_frame(imgui.io_mut(), &surface.window())
-                        .expect("Failed to prepare frame.");
-                    surface.window().request_redraw();
+                    match platform.prepare_frame(imgui.io_mut(), &surface.window()) {
+                        Ok(()) => surface.window().request_redraw(),
+                        Err(e) => {
+                            exit_error = Some(SimError::Display(e.to_string()));
+                            *control_flow = ControlFlow::Exit;
+                        }
+                    }
                 }
                 Event::RedrawRequested(_) => {
-                    // ---- Stick to the framerate ----
-                    let t = Instant::now();
-                    let since_last = t.duration_since(last_redraw);
-                    last_redraw = t;
+                    let mut redraw = || -> Result<(), SimError> {
+                        // ---- Stick to the framerate ----
+                        let t = Instant::now();
+                        let since_last = t.duration_since(last_redraw);
+                        last_redraw = t;
+
+                        if since_last < target_frame_time {
+                            std::thread::sleep(target_frame_time - since_last);
+                        }
 
-                    if since_last < target_frame_time {
-                        std::thread::sleep(target_frame_time - since_last);
-                    }
+                        // ---- Cleanup ----
 
-                    // ---- Cleanup ----
+                        previous_frame_end.as_mut().unwrap().cleanup_finished();
 
-                    previous_frame_end.as_mut().unwrap().cleanup_finished();
+                        // ---- Let the tools change the model ----
 
-                    // ---- Recreate swapchain if necessary ----
+                        {
+                            let mut idle_gpu = || wait_for_gpu(&mut previous_frame_end, &device);
+                            tools.before_steps(
+                                &mut model,
+                                &mut parameters,
+                                &mut post_settings,
+                                &mut step,
+                                &mut idle_gpu,
+                            )?;
+                        }
 
-                    if recreate_swapchain {
-                        let dimensions: [u32; 2] = surface.window().inner_size().into();
-                        let (new_swapchain, new_images) =
-                            match swapchain.recreate_with_dimensions(dimensions) {
-                                Ok(r) => r,
-                                Err(SwapchainCreationError::UnsupportedDimensions) => return,
-                                Err(e) => panic!("Failed to recreate swapchain: {:?}", e),
+                        // ---- Take a screenshot, or restore one ----
+
+                        if take_screenshot {
+                            take_screenshot = false;
+                            wait_for_gpu(&mut previous_frame_end, &device)?;
+
+                            // At display resolution, the screenshot is what the window shows,
+                            // which is the top left of the model.
+                            let post_applied =
+                                screenshots.display_resolution && post_settings.is_active();
+                            let snapshot = if screenshots.display_resolution {
+                                let window_size: [u32; 2] = surface.window().inner_size().into();
+                                let source = if post_applied {
+                                    post_processor.output()
+                                } else {
+                                    model.result_image()
+                                };
+                                let [width, height] = model.size();
+                                let size = [window_size[0].min(width), window_size[1].min(height)];
+                                screenshot::read_image(&device, &queue, source, size).and_then(
+                                    |image| {
+                                        Ok(Snapshot {
+                                            image,
+                                            texts: model.screenshot_texts(&parameters, step)?,
+                                        })
+                                    },
+                                )
+                            } else {
+                                model.snapshot(&parameters, step)
                             };
 
-                        images = new_images;
-                        swapchain = new_swapchain;
-                        recreate_swapchain = false;
-                    }
+                            let path = screenshots.next_path();
+                            let saved = snapshot
+                                .map_err(|e| format!("Failed to take the screenshot: {}", e))
+                                .and_then(|mut snapshot| {
+                                    if post_applied {
+                                        let text =
+                                            screenshot::post_text(&post_settings).map_err(|e| {
+                                                format!(
+                                                    "Failed to store the post-processing: {}",
+                                                    e
+                                                )
+                                            })?;
+                                        snapshot.texts.push(text);
+                                    }
+                                    snapshot.save(&path).map_err(|e| {
+                                        format!("Failed to save '{}': {}", path.display(), e)
+                                    })
+                                });
+                            let status = match saved {
+                                Ok(()) => format!("Saved '{}'", path.display()),
+                                Err(message) => message,
+                            };
+                            screenshots.set_status(status);
+                        }
 
-                    // ---- Run the user's imgui code ----
+                        if let Some(path) = dropped_file.take() {
+                            wait_for_gpu(&mut previous_frame_end, &device)?;
+
+                            step = 0;
+                            let status = restore_screenshot(
+                                &path,
+                                &mut model,
+                                &mut parameters,
+                                &mut post_settings,
+                            );
+                            screenshots.set_status(status);
+                        }
 
-                    let mut ui = imgui.frame();
-                    let mut run = true;
+                        // ---- Recreate swapchain if necessary ----
 
-                    run_ui(&mut run, &mut sim_parameters, &mut fade_parameters, &mut ui);
+                        if recreate_swapchain {
+                            let dimensions: [u32; 2] = surface.window().inner_size().into();
+                            let (new_swapchain, new_images) = match swapchain
+                                .recreate_with_dimensions(dimensions)
+                            {
+                                Ok(r) => r,
+                                Err(SwapchainCreationError::UnsupportedDimensions) => return Ok(()),
+                                Err(e) => return Err(SimError::Display(e.to_string())),
+                            };
 
-                    if !run {
-                        *control_flow = ControlFlow::Exit;
-                    }
+                            images = new_images;
+                            swapchain = new_swapchain;
+                            recreate_swapchain = false;
+
+                            // A minimised window has no size to resize to.
+                            let resize = resize_with_window
+                                && dimensions != model.size()
+                                && dimensions.iter().all(|&size| size > 0);
+                            if resize {
+                                wait_for_gpu(&mut previous_frame_end, &device)?;
+
+                                step = 0;
+                                model.resize(dimensions[0], dimensions[1])?;
+                                post_processor =
+                                    PostProcessor::new(&device, &queue, model.result_image())?;
+                                tools.resized(&model)?;
+                            }
+                        }
 
-                    // ---- Create draw commands ----
+                        // ---- Acquire the next swapchain image ----
 
-                    let (image_num, suboptimal, acquire_future) =
-                        match swapchain::acquire_next_image(swapchain.clone(), None) {
-                            Ok(r) => r,
-                            Err(AcquireError::OutOfDate) => {
-                                recreate_swapchain = true;
-                                return;
-                            }
-                            Err(e) => panic!("Failed to acquire next image: {:?}", e),
+                        let (image_num, suboptimal, acquire_future) =
+                            match swapchain::acquire_next_image(swapchain.clone(), None) {
+                                Ok(r) => r,
+                                Err(AcquireError::OutOfDate) => {
+                                    recreate_swapchain = true;
+                                    return Ok(());
+                                }
+                                Err(e) => return Err(SimError::Display(e.to_string())),
+                            };
+
+                        if suboptimal {
+                            recreate_swapchain = true;
+                        }
+
+                        // ---- Run the model on the compute queue ----
+
+                        // The step is flushed before the ui is built and recorded, so the gpu
+                        // simulates while the cpu works on the ui. Parameter changes made in the
+                        // ui are therefore used from the next frame on.
+                        let mut timings = None;
+
+                        let simulation_future = if profiler.enabled {
+                            // Wait for the previous frame, so that every pass can be timed on
+                            // its own.
+                            wait_for_gpu(&mut previous_frame_end, &device)?;
+
+                            timings = model.run_steps_profiled(steps_per_frame, &parameters)?;
+
+                            tools.after_steps(
+                                &model,
+                                previous_frame_end.take().unwrap(),
+                                step,
+                                true,
+                            )?
+                        } else {
+                            let future = model.steps_after(
+                                previous_frame_end.take().unwrap(),
+                                steps_per_frame,
+                                &parameters,
+                            )?;
+                            tools.after_steps(&model, future, step, false)?
                         };
 
-                    if suboptimal {
-                        recreate_swapchain = true;
-                    }
+                        // The graphics queue waits on this semaphore before displaying the model.
+                        let simulation_future =
+                            simulation_future.then_signal_semaphore_and_flush()?;
+
+                        // ---- Run the user's imgui code ----
+
+                        let mut ui = imgui.frame();
+                        let mut run = true;
+
+                        run_ui(
+                            &mut run,
+                            &mut parameters,
+                            &mut ui,
+                            renderer.textures(),
+                            steps_per_frame,
+                        );
+                        profiler.draw_overlay(&ui, &mut steps_per_frame);
+                        post_settings.draw_window(&ui);
+                        if screenshots.draw_window(&ui) {
+                            take_screenshot = true;
+                        }
+                        tools.draw(&ui, &mut parameters);
+
+                        if !run {
+                            *control_flow = ControlFlow::Exit;
+                        }
+
+                        // ---- Create draw commands ----
 
-                    platform.prepare_render(&ui, surface.window());
-                    let draw_data = ui.render();
-
-                    let extent_x = simulation
-                        .result_image
-                        .dimensions()
-                        .width()
-                        .min(images[image_num].dimensions()[0]);
-                    let extent_y = simulation
-                        .result_image
-                        .dimensions()
-                        .height()
-                        .min(images[image_num].dimensions()[1]);
-
-                    let mut cmd_buf_builder =
-                        AutoCommandBufferBuilder::new(device.clone(), queue.family())
-                            .expect("Failed to create command buffer");
-                    cmd_buf_builder
-                        .clear_color_image(images[image_num].clone(), [0.0; 4].into())
-                        .unwrap();
-
-                    cmd_buf_builder
-                        .copy_image(
-                            simulation.result_image.clone(),
+                        platform.prepare_render(&ui, surface.window());
+                        let draw_data = ui.render();
+
+                        let [width, height] = model.size();
+                        let extent_x = width.min(images[image_num].dimensions()[0]);
+                        let extent_y = height.min(images[image_num].dimensions()[1]);
+
+                        let mut cmd_buf_builder =
+                            AutoCommandBufferBuilder::new(device.clone(), queue.family())?;
+                        cmd_buf_builder
+                            .clear_color_image(images[image_num].clone(), [0.0; 4].into())?;
+
+                        // The post-processing writes to its own image, the model stays untouched.
+                        let display_image = if post_settings.is_active() {
+                            post_processor.record(&mut cmd_buf_builder, &post_settings)?;
+                            post_processor.output()
+                        } else {
+                            model.result_image()
+                        };
+
+                        cmd_buf_builder.copy_image(
+                            display_image.clone(),
                             [0; 3],
                             0,
                             0,
@@ -293,54 +558,76 @@ impl System {
                             0,
                             [extent_x, extent_y, 1],
                             1,
-                        )
-                        .expect("Failed to create image copy command");
-
-                    renderer
-                        .draw_commands(
-                            &mut cmd_buf_builder,
-                            queue.clone(),
-                            images[image_num].clone(),
-                            draw_data,
-                        )
-                        .expect("Rendering failed");
-
-                    let cmd_buf = cmd_buf_builder
-                        .build()
-                        .expect("Failed to build command buffer");
-
-                    // ---- Execute the draw commands ----
-
-                    let (buffer_1, buffer_2, buffer_3) =
-                        simulation.create_command_buffers(&sim_parameters, &fade_parameters);
-
-                    let future = previous_frame_end
-                        .take()
-                        .unwrap()
-                        .join(acquire_future)
-                        .then_execute(queue.clone(), buffer_1)
-                        .unwrap()
-                        .then_execute(queue.clone(), buffer_2)
-                        .unwrap()
-                        .then_execute(queue.clone(), buffer_3)
-                        .unwrap()
-                        .then_execute(queue.clone(), cmd_buf)
-                        .unwrap()
-                        .then_swapchain_present(queue.clone(), swapchain.clone(), image_num)
-                        .then_signal_fence_and_flush();
-
-                    match future {
-                        Ok(future) => {
-                            previous_frame_end = Some(future.boxed());
-                        }
-                        Err(FlushError::OutOfDate) => {
-                            recreate_swapchain = true;
-                            previous_frame_end = Some(sync::now(device.clone()).boxed());
+                        )?;
+
+                        renderer
+                            .draw_commands(
+                                &mut cmd_buf_builder,
+                                queue.clone(),
+                                images[image_num].clone(),
+                                draw_data,
+                            )
+                            .map_err(|e| SimError::Display(format!("{:?}", e)))?;
+
+                        let cmd_buf = cmd_buf_builder.build()?;
+
+                        // ---- Execute the draw commands ----
+
+                        // Timestamps go around the display pass while the model is timed. The
+                        // first one does not wait for the swapchain image the pass waits for, so
+                        // an image that is acquired late adds to the display time.
+                        let display_timer = display_timer.as_ref().filter(|_| timings.is_some());
+                        if let Some(display_timer) = display_timer {
+                            display_timer.write(0)?;
                         }
-                        Err(e) => {
-                            println!("Failed to flush future: {:?}", e);
-                            previous_frame_end = Some(sync::now(device.clone()).boxed());
+                        let display_future = simulation_future
+                            .join(acquire_future)
+                            .then_execute(queue.clone(), cmd_buf)?;
+
+                        let future = match (&mut timings, display_timer) {
+                            // Wait for the display pass before presenting, so the time spent
+                            // waiting for vsync does not count as display time.
+                            (Some(pass_timings), Some(display_timer)) => {
+                                let displayed = display_future.then_signal_fence_and_flush()?;
+                                display_timer.write(1)?;
+                                displayed.wait(None)?;
+                                // Adds to the time spent converting the model to the
+                                // display format.
+                                pass_timings.display_ms += display_timer.read_intervals()?[0];
+                                displayed
+                                    .then_swapchain_present(
+                                        queue.clone(),
+                                        swapchain.clone(),
+                                        image_num,
+                                    )
+                                    .then_signal_fence_and_flush()
+                                    .map(|future| future.boxed())
+                            }
+                            _ => display_future
+                                .then_swapchain_present(queue.clone(), swapchain.clone(), image_num)
+                                .then_signal_fence_and_flush()
+                                .map(|future| future.boxed()),
+                        };
+
+                        profiler.record_frame(steps_per_frame, timings);
+                        step += steps_per_frame as u64;
+
+                        match future {
+                            Ok(future) => {
+                                previous_frame_end = Some(future);
+                            }
+                            Err(FlushError::OutOfDate) => {
+                                recreate_swapchain = true;
+                                previous_frame_end = Some(sync::now(device.clone()).boxed());
+                            }
+                            Err(e) => return Err(e.into()),
                         }
+                        Ok(())
+                    };
+
+                    if let Err(e) = redraw() {
+                        exit_error = Some(e);
+                        *control_flow = ControlFlow::Exit;
                     }
                 }
                 Event::WindowEvent {
@@ -349,11 +636,103 @@ impl System {
                 } => {
                     *control_flow = ControlFlow::Exit;
                 }
+                Event::WindowEvent {
+                    event: WindowEvent::DroppedFile(path),
+                    ..
+                } => {
+                    dropped_file = Some(path);
+                }
                 event => {
-                    // Pass events on to imgui.
+                    // Pass events on to imgui, and clicks it does not capture to the tools.
                     platform.handle_event(imgui.io_mut(), surface.window(), &event);
+
+                    match &event {
+                        Event::WindowEvent {
+                            event: WindowEvent::CursorMoved { position, .. },
+                            ..
+                        } => {
+                            // The model is shown one pixel per physical pixel.
+                            cursor = [position.x as f32, position.y as f32];
+                        }
+                        Event::WindowEvent {
+                            event:
+                                WindowEvent::MouseInput {
+                                    state: ElementState::Pressed,
+                                    button: MouseButton::Left,
+                                    ..
+                                },
+                            ..
+                        } if !imgui.io().want_capture_mouse => {
+                            tools.click(cursor);
+                        }
+                        _ => {}
+                    }
+
+                    if let Event::WindowEvent {
+                        event:
+                            WindowEvent::KeyboardInput {
+                                input:
+                                    KeyboardInput {
+                                        state: ElementState::Pressed,
+                                        virtual_keycode: Some(VirtualKeyCode::F12),
+                                        ..
+                                    },
+                                ..
+                            },
+                        ..
+                    } = event
+                    {
+                        take_screenshot = true;
+                    }
                 }
             }
         });
     }
 }
+
+/// Whether the queue family can run the simulation and draw it to the window.
+fn window_queue_family(surface: &Arc<Surface<Window>>, family: QueueFamily) -> bool {
+    family.supports_graphics()
+        && family.supports_compute()
+        && family.explicitly_supports_transfers()
+        && surface.is_supported(family).unwrap_or(false)
+}
+
+/// Blocks until the gpu has finished everything that was submitted so far.
+fn wait_for_gpu(
+    previous_frame_end: &mut Option<Box<dyn GpuFuture>>,
+    device: &Arc<Device>,
+) -> Result<(), SimError> {
+    let previous = previous_frame_end.replace(sync::now(device.clone()).boxed());
+    previous
+        .unwrap()
+        .then_signal_fence_and_flush()?
+        .wait(None)?;
+    Ok(())
+}
+
+/// Applies the parameters embedded in a screenshot, and the post-processing when it was
+/// applied to the screenshot. Returns what happened, for the user.
+fn restore_screenshot<M: Model>(
+    path: &Path,
+    model: &mut M,
+    parameters: &mut M::Parameters,
+    post_settings: &mut PostSettings,
+) -> String {
+    let texts = match std::fs::read(path)
+        .map_err(|e| e.into())
+        .and_then(|bytes| screenshot::read_text_chunks(&bytes))
+    {
+        Ok(texts) => texts,
+        Err(e) => return format!("Failed to load '{}': {}", path.display(), e),
+    };
+
+    let status = match model.restore(&texts, parameters) {
+        Ok(status) => status,
+        Err(e) => return format!("Failed to restore '{}': {}", path.display(), e),
+    };
+    if let Ok(Some(post)) = screenshot::post_from_texts(&texts) {
+        *post_settings = post;
+    }
+    status
+}
@@ -0,0 +1,413 @@
+use crate::parameters::{Kind, Parameter, Value, PARAMETERS};
+use crate::simulation::{AgentParameters, FadeParameters};
+use serde::de::Error as _;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::error::Error;
+use std::fmt;
+use std::fs::File;
+use std::io::{BufReader, BufWriter};
+use std::path::Path;
+
+/// How a value moves from one keyframe to the next.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Curve {
+    Linear,
+    /// Starts and ends slowly.
+    Ease,
+    /// Keeps the value until the next keyframe is reached.
+    Step,
+}
+
+impl Curve {
+    pub const ALL: [Curve; 3] = [Curve::Linear, Curve::Ease, Curve::Step];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Curve::Linear => "Linear",
+            Curve::Ease => "Ease",
+            Curve::Step => "Step",
+        }
+    }
+
+    /// Maps `t` in the range [0 - 1] onto the curve.
+    pub fn apply(&self, t: f32) -> f32 {
+        match self {
+            Curve::Linear => t,
+            Curve::Ease => t * t * (3.0 - 2.0 * t),
+            Curve::Step => 0.0,
+        }
+    }
+}
+
+/// Names of the channels of color parameters, which are animated one by one.
+const CHANNELS: [&str; 3] = ["red", "green", "blue"];
+
+/// A number in `parameters::PARAMETERS` that can be animated: a float or int parameter,
+/// or one channel of a color parameter. Timeline files refer to it by its preset key,
+/// like `agent_speed`, followed by the channel for colors, like `trail_color.red`.
+#[derive(Clone, Copy)]
+pub struct AnimatedParameter {
+    pub parameter: &'static Parameter,
+    /// Index into red, green and blue, for color parameters.
+    pub channel: usize,
+}
+
+impl AnimatedParameter {
+    /// Every number of every parameter, in the order of the registry.
+    pub fn all() -> Vec<AnimatedParameter> {
+        PARAMETERS
+            .iter()
+            .flat_map(|parameter| {
+                let channels = match parameter.kind {
+                    Kind::Color => CHANNELS.len(),
+                    _ => 1,
+                };
+                (0..channels).map(move |channel| AnimatedParameter { parameter, channel })
+            })
+            .collect()
+    }
+
+    /// The animated parameter with the given key, as returned by `key`.
+    pub fn find(key: &str) -> Option<AnimatedParameter> {
+        AnimatedParameter::all()
+            .into_iter()
+            .find(|a| a.key() == key)
+    }
+
+    pub fn key(&self) -> String {
+        match self.parameter.kind {
+            Kind::Color => format!("{}.{}", self.parameter.key, CHANNELS[self.channel]),
+            _ => self.parameter.key.to_owned(),
+        }
+    }
+
+    /// Name in the ui, like `Speed (px/s)` or `Trail color (red)`.
+    pub fn name(&self) -> String {
+        match self.parameter.kind {
+            Kind::Color => format!("{} ({})", self.parameter.label, CHANNELS[self.channel]),
+            _ => self.parameter.label_with_units(),
+        }
+    }
+
+    pub fn get(&self, sim_parameters: &AgentParameters, fade_parameters: &FadeParameters) -> f32 {
+        match self.parameter.get(sim_parameters, fade_parameters) {
+            Value::Color(color) => color[self.channel],
+            other => other.as_float(),
+        }
+    }
+
+    /// Sets the value, clamped to the range of the parameter and rounded for ints.
+    pub fn set(
+        &self,
+        sim_parameters: &mut AgentParameters,
+        fade_parameters: &mut FadeParameters,
+        value: f32,
+    ) {
+        let value = match self.parameter.get(sim_parameters, fade_parameters) {
+            Value::Color(mut color) => {
+                color[self.channel] = value;
+                Value::Color(color)
+            }
+            _ => Value::Float(value),
+        };
+        self.parameter.set(sim_parameters, fade_parameters, value);
+    }
+}
+
+impl PartialEq for AnimatedParameter {
+    fn eq(&self, other: &AnimatedParameter) -> bool {
+        self.parameter.key == other.parameter.key && self.channel == other.channel
+    }
+}
+
+impl Eq for AnimatedParameter {}
+
+impl fmt::Debug for AnimatedParameter {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.key())
+    }
+}
+
+impl Serialize for AnimatedParameter {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.key())
+    }
+}
+
+impl<'de> Deserialize<'de> for AnimatedParameter {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let key = String::deserialize(deserializer)?;
+        AnimatedParameter::find(&key).ok_or_else(|| {
+            D::Error::custom(format!("'{}' is not a parameter that can be animated", key))
+        })
+    }
+}
+
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+pub struct Keyframe {
+    /// In simulated seconds.
+    pub time: f32,
+    pub value: f32,
+    /// How to move towards the next keyframe.
+    pub curve: Curve,
+}
+
+/// The keyframes of a single parameter, sorted by time.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Track {
+    pub parameter: AnimatedParameter,
+    pub keyframes: Vec<Keyframe>,
+}
+
+impl Track {
+    pub fn new(parameter: AnimatedParameter) -> Track {
+        Track {
+            parameter,
+            keyframes: Vec::new(),
+        }
+    }
+
+    /// Adds the keyframe, replacing any keyframe at exactly the same time.
+    pub fn insert(&mut self, keyframe: Keyframe) {
+        match self
+            .keyframes
+            .binary_search_by(|k| k.time.total_cmp(&keyframe.time))
+        {
+            Ok(index) => self.keyframes[index] = keyframe,
+            Err(index) => self.keyframes.insert(index, keyframe),
+        }
+    }
+
+    /// Should be called after keyframe times have been edited.
+    pub fn sort(&mut self) {
+        self.keyframes.sort_by(|a, b| a.time.total_cmp(&b.time));
+    }
+
+    /// The value at the given time. Before the first and after the last keyframe,
+    /// the value of that keyframe is held.
+    pub fn value_at(&self, time: f32) -> Option<f32> {
+        let first = self.keyframes.first()?;
+        if time <= first.time {
+            return Some(first.value);
+        }
+
+        for pair in self.keyframes.windows(2) {
+            let (from, to) = (pair[0], pair[1]);
+            if time < to.time {
+                let t = (time - from.time) / (to.time - from.time);
+                return Some(from.value + (to.value - from.value) * from.curve.apply(t));
+            }
+        }
+
+        self.keyframes.last().map(|k| k.value)
+    }
+}
+
+/// Animates parameters over simulated time.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Timeline {
+    /// In simulated seconds.
+    pub length: f32,
+    pub tracks: Vec<Track>,
+}
+
+impl Default for Timeline {
+    fn default() -> Self {
+        Timeline {
+            length: 60.0,
+            tracks: Vec::new(),
+        }
+    }
+}
+
+impl Timeline {
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Timeline, Box<dyn Error>> {
+        let file = File::open(path)?;
+        let mut timeline: Timeline = serde_json::from_reader(BufReader::new(file))?;
+        for track in &mut timeline.tracks {
+            track.sort();
+        }
+        Ok(timeline)
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), Box<dyn Error>> {
+        let file = File::create(path)?;
+        serde_json::to_writer_pretty(BufWriter::new(file), self)?;
+        Ok(())
+    }
+
+    pub fn track(&self, parameter: AnimatedParameter) -> Option<&Track> {
+        self.tracks.iter().find(|t| t.parameter == parameter)
+    }
+
+    /// Returns the track of the parameter, creating an empty one if there is none yet.
+    pub fn track_mut(&mut self, parameter: AnimatedParameter) -> &mut Track {
+        match self.tracks.iter().position(|t| t.parameter == parameter) {
+            Some(index) => &mut self.tracks[index],
+            None => {
+                self.tracks.push(Track::new(parameter));
+                self.tracks.last_mut().unwrap()
+            }
+        }
+    }
+
+    /// Sets every animated parameter to its value at the given time, clamped to its range,
+    /// as keyframes are not limited to it. Parameters without keyframes are left alone.
+    pub fn apply(
+        &self,
+        time: f32,
+        sim_parameters: &mut AgentParameters,
+        fade_parameters: &mut FadeParameters,
+    ) {
+        for track in &self.tracks {
+            if let Some(value) = track.value_at(time) {
+                track.parameter.set(sim_parameters, fade_parameters, value);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parameters::ParameterSet;
+
+    fn keyframe(time: f32, value: f32, curve: Curve) -> Keyframe {
+        Keyframe { time, value, curve }
+    }
+
+    fn track(keyframes: &[Keyframe]) -> Track {
+        let mut track = Track::new(AnimatedParameter::find("agent_speed").unwrap());
+        for &k in keyframes {
+            track.insert(k);
+        }
+        track
+    }
+
+    #[test]
+    fn animated_parameters_are_found_by_key() {
+        let all = AnimatedParameter::all();
+        // Colors are animated channel by channel.
+        assert_eq!(all.len(), PARAMETERS.len() + 2);
+        for animated in &all {
+            assert_eq!(AnimatedParameter::find(&animated.key()), Some(*animated));
+        }
+
+        let green = AnimatedParameter::find("trail_color.green").unwrap();
+        assert_eq!(green.parameter.key, "trail_color");
+        assert_eq!(green.channel, 1);
+        assert!(AnimatedParameter::find("trail_color").is_none());
+        assert!(AnimatedParameter::find("width").is_none());
+    }
+
+    #[test]
+    fn animated_parameters_set_their_channel() {
+        let set = ParameterSet::default();
+        let (mut sim_parameters, mut fade_parameters) = (set.sim, set.fade);
+
+        let green = AnimatedParameter::find("trail_color.green").unwrap();
+        green.set(&mut sim_parameters, &mut fade_parameters, 0.25);
+        assert_eq!(sim_parameters.trail_color[..3], [1.0, 0.25, 0.5]);
+
+        // Clamped and rounded like any other way of setting the parameter.
+        let blur = AnimatedParameter::find("blur_radius").unwrap();
+        blur.set(&mut sim_parameters, &mut fade_parameters, 2.7);
+        assert_eq!(fade_parameters.blur_radius, 3);
+        blur.set(&mut sim_parameters, &mut fade_parameters, 100.0);
+        assert_eq!(blur.get(&sim_parameters, &fade_parameters), 8.0);
+    }
+
+    #[test]
+    fn tracks_are_stored_by_key() {
+        let mut timeline = Timeline::default();
+        timeline
+            .track_mut(AnimatedParameter::find("trail_color.blue").unwrap())
+            .insert(keyframe(1.0, 0.5, Curve::Ease));
+
+        let json = serde_json::to_string(&timeline).unwrap();
+        assert!(json.contains(r#""parameter":"trail_color.blue""#));
+
+        let read: Timeline = serde_json::from_str(&json).unwrap();
+        assert_eq!(read.tracks[0].parameter, timeline.tracks[0].parameter);
+        assert!(serde_json::from_str::<Timeline>(
+            r#"{"length": 1.0, "tracks": [{"parameter": "no_such_parameter", "keyframes": []}]}"#
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn curves_start_at_zero_and_end_at_one() {
+        for curve in &[Curve::Linear, Curve::Ease] {
+            assert_eq!(curve.apply(0.0), 0.0);
+            assert_eq!(curve.apply(1.0), 1.0);
+        }
+        assert_eq!(Curve::Linear.apply(0.25), 0.25);
+        assert_eq!(Curve::Ease.apply(0.5), 0.5);
+        assert!(Curve::Ease.apply(0.25) < 0.25);
+        assert_eq!(Curve::Step.apply(0.99), 0.0);
+    }
+
+    #[test]
+    fn empty_track_has_no_value() {
+        assert_eq!(track(&[]).value_at(1.0), None);
+    }
+
+    #[test]
+    fn value_is_held_outside_the_keyframes() {
+        let track = track(&[
+            keyframe(1.0, 10.0, Curve::Linear),
+            keyframe(3.0, 30.0, Curve::Linear),
+        ]);
+        assert_eq!(track.value_at(0.0), Some(10.0));
+        assert_eq!(track.value_at(1.0), Some(10.0));
+        assert_eq!(track.value_at(3.0), Some(30.0));
+        assert_eq!(track.value_at(5.0), Some(30.0));
+    }
+
+    #[test]
+    fn value_follows_the_curve_of_the_earlier_keyframe() {
+        let track = track(&[
+            keyframe(0.0, 0.0, Curve::Linear),
+            keyframe(2.0, 20.0, Curve::Step),
+            keyframe(4.0, 40.0, Curve::Linear),
+        ]);
+        assert_eq!(track.value_at(1.0), Some(10.0));
+        assert_eq!(track.value_at(3.0), Some(20.0));
+        assert_eq!(track.value_at(4.0), Some(40.0));
+    }
+
+    #[test]
+    fn insert_keeps_keyframes_sorted() {
+        let track = track(&[
+            keyframe(2.0, 2.0, Curve::Linear),
+            keyframe(0.0, 0.0, Curve::Linear),
+            keyframe(1.0, 1.0, Curve::Linear),
+        ]);
+        let times: Vec<f32> = track.keyframes.iter().map(|k| k.time).collect();
+        assert_eq!(times, vec![0.0, 1.0, 2.0]);
+    }
+
+    #[test]
+    fn insert_replaces_a_keyframe_at_the_same_time() {
+        let track = track(&[
+            keyframe(0.0, 0.0, Curve::Linear),
+            keyframe(1.0, 1.0, Curve::Linear),
+            keyframe(1.0, 5.0, Curve::Step),
+        ]);
+        assert_eq!(track.keyframes.len(), 2);
+        assert_eq!(track.keyframes[1].value, 5.0);
+        assert_eq!(track.keyframes[1].curve, Curve::Step);
+    }
+
+    #[test]
+    fn keyframes_at_nan_do_not_panic() {
+        let mut track = track(&[
+            keyframe(1.0, 1.0, Curve::Linear),
+            keyframe(f32::NAN, 2.0, Curve::Linear),
+            keyframe(0.0, 0.0, Curve::Linear),
+        ]);
+        track.sort();
+        assert_eq!(track.keyframes.len(), 3);
+        assert_eq!(track.keyframes[0].time, 0.0);
+    }
+}
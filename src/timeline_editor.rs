@@ -0,0 +1,198 @@
+use crate::simulation::{AgentParameters, FadeParameters};
+use crate::timeline::{AnimatedParameter, Curve, Keyframe, Timeline};
+use imgui::{im_str, ComboBox, Condition, ImString, Slider, Ui, Window};
+
+/// How many points of the selected track are shown in the preview graph.
+const PREVIEW_SAMPLES: usize = 200;
+
+/// Plays back a timeline, and lets the user scrub through it and edit its keyframes.
+pub struct TimelineEditor {
+    pub timeline: Timeline,
+    /// In simulated seconds.
+    pub time: f32,
+    playing: bool,
+    /// Index into `AnimatedParameter::all()`.
+    selected_parameter: usize,
+    path: ImString,
+    status: String,
+}
+
+impl TimelineEditor {
+    pub fn new(timeline: Timeline) -> TimelineEditor {
+        let mut path = ImString::with_capacity(256);
+        path.push_str("timeline.json");
+
+        TimelineEditor {
+            timeline,
+            time: 0.0,
+            playing: false,
+            selected_parameter: 0,
+            path,
+            status: String::new(),
+        }
+    }
+
+    /// Call once per frame, with the amount of steps simulated in it.
+    /// While playing, this advances the time by that many steps and sets the animated parameters.
+    /// While paused the parameters are left alone, so they can be tweaked by hand
+    /// before being stored in a keyframe.
+    pub fn update(
+        &mut self,
+        steps: u32,
+        sim_parameters: &mut AgentParameters,
+        fade_parameters: &mut FadeParameters,
+    ) {
+        if !self.playing {
+            return;
+        }
+
+        self.time += steps as f32 * sim_parameters.delta_time;
+        if self.time >= self.timeline.length {
+            self.time = self.timeline.length;
+            self.playing = false;
+        }
+
+        self.timeline
+            .apply(self.time, sim_parameters, fade_parameters);
+    }
+
+    pub fn draw(
+        &mut self,
+        ui: &Ui,
+        sim_parameters: &mut AgentParameters,
+        fade_parameters: &mut FadeParameters,
+    ) {
+        let editor = self;
+
+        Window::new(im_str!("Timeline"))
+            .position([320.0, 10.0], Condition::FirstUseEver)
+            .size([460.0, 420.0], Condition::FirstUseEver)
+            .build(ui, || {
+                ui.push_item_width(200.0);
+
+                let play_label = if editor.playing {
+                    im_str!("Pause")
+                } else {
+                    im_str!("Play")
+                };
+                if ui.button(play_label, [60.0, 0.0]) {
+                    editor.playing = !editor.playing;
+                }
+                ui.same_line(0.0);
+                let mut scrubbed = false;
+                if ui.button(im_str!("Rewind"), [60.0, 0.0]) {
+                    editor.time = 0.0;
+                    scrubbed = true;
+                }
+
+                scrubbed |= Slider::new(im_str!("Time (s)"))
+                    .range(0.0..=editor.timeline.length)
+                    .build(&ui, &mut editor.time);
+                ui.input_float(im_str!("Length (s)"), &mut editor.timeline.length)
+                    .build();
+                editor.timeline.length = editor.timeline.length.max(0.0);
+
+                if scrubbed {
+                    editor
+                        .timeline
+                        .apply(editor.time, sim_parameters, fade_parameters);
+                }
+
+                ui.separator();
+
+                let animated = AnimatedParameter::all();
+                let names: Vec<ImString> =
+                    animated.iter().map(|p| ImString::new(p.name())).collect();
+                let name_refs: Vec<&ImString> = names.iter().collect();
+                ComboBox::new(im_str!("Parameter")).build_simple_string(
+                    &ui,
+                    &mut editor.selected_parameter,
+                    &name_refs,
+                );
+                let parameter = animated[editor.selected_parameter];
+
+                if ui.button(im_str!("Add keyframe at current time"), [0.0, 0.0]) {
+                    let value = parameter.get(sim_parameters, fade_parameters);
+                    editor.timeline.track_mut(parameter).insert(Keyframe {
+                        time: editor.time,
+                        value,
+                        curve: Curve::Linear,
+                    });
+                }
+
+                if let Some(track) = editor.timeline.track(parameter) {
+                    let length = editor.timeline.length;
+                    let samples: Vec<f32> = (0..PREVIEW_SAMPLES)
+                        .filter_map(|i| {
+                            track.value_at(i as f32 * length / (PREVIEW_SAMPLES - 1) as f32)
+                        })
+                        .collect();
+                    ui.plot_lines(im_str!("Curve"), &samples)
+                        .graph_size([0.0, 60.0])
+                        .build();
+                }
+
+                let mut removed = None;
+                let mut retimed = false;
+                if let Some(track) = editor
+                    .timeline
+                    .tracks
+                    .iter_mut()
+                    .find(|t| t.parameter == parameter)
+                {
+                    ui.push_item_width(80.0);
+                    for (i, keyframe) in track.keyframes.iter_mut().enumerate() {
+                        let id = ui.push_id(i as i32);
+
+                        retimed |= ui.input_float(im_str!("Time"), &mut keyframe.time).build();
+                        ui.same_line(0.0);
+                        ui.input_float(im_str!("Value"), &mut keyframe.value)
+                            .build();
+                        ui.same_line(0.0);
+                        if ui.button(&ImString::new(keyframe.curve.name()), [60.0, 0.0]) {
+                            let index = Curve::ALL
+                                .iter()
+                                .position(|&c| c == keyframe.curve)
+                                .unwrap();
+                            keyframe.curve = Curve::ALL[(index + 1) % Curve::ALL.len()];
+                        }
+                        ui.same_line(0.0);
+                        if ui.button(im_str!("Delete"), [0.0, 0.0]) {
+                            removed = Some(i);
+                        }
+
+                        id.pop(&ui);
+                    }
+
+                    if let Some(index) = removed {
+                        track.keyframes.remove(index);
+                    }
+                    if retimed {
+                        track.sort();
+                    }
+                }
+
+                ui.separator();
+
+                ui.input_text(im_str!("File"), &mut editor.path).build();
+                if ui.button(im_str!("Save"), [60.0, 0.0]) {
+                    editor.status = match editor.timeline.save(editor.path.to_str()) {
+                        Ok(()) => format!("Saved to '{}'", editor.path.to_str()),
+                        Err(e) => format!("Failed to save: {}", e),
+                    };
+                }
+                ui.same_line(0.0);
+                if ui.button(im_str!("Load"), [60.0, 0.0]) {
+                    editor.status = match Timeline::load(editor.path.to_str()) {
+                        Ok(timeline) => {
+                            editor.timeline = timeline;
+                            editor.time = 0.0;
+                            format!("Loaded '{}'", editor.path.to_str())
+                        }
+                        Err(e) => format!("Failed to load: {}", e),
+                    };
+                }
+                ui.text(&editor.status);
+            });
+    }
+}
@@ -0,0 +1,944 @@
+use crate::device;
+use crate::device::TrailFormat;
+use crate::error::SimError;
+use crate::model::Model;
+use crate::preset::Preset;
+use crate::screenshot;
+use crate::screenshot::ScreenshotInfo;
+use crate::simulation::{AgentParameters, FadeParameters, SimulationConfig};
+#[cfg(feature = "ui")]
+use imgui::{im_str, Condition, MouseButton, Slider, Ui, Window};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use std::collections::HashMap;
+use std::error::Error;
+use std::f32::consts::PI;
+use std::sync::Arc;
+use vulkano::buffer::{BufferUsage, CpuAccessibleBuffer, DeviceLocalBuffer};
+use vulkano::command_buffer::{AutoCommandBuffer, AutoCommandBufferBuilder};
+use vulkano::descriptor::descriptor_set::{DescriptorSet, PersistentDescriptorSet};
+use vulkano::descriptor::pipeline_layout::PipelineLayout;
+use vulkano::descriptor::PipelineLayoutAbstract;
+use vulkano::device::{Device, Queue};
+use vulkano::format::Format;
+use vulkano::image::{Dimensions, StorageImage};
+use vulkano::pipeline::ComputePipeline;
+use vulkano::sync;
+use vulkano::sync::GpuFuture;
+
+/// A single trail intensity per voxel. The color is only added when rendering.
+const VOLUME_FORMAT: Format = Format::R32Sfloat;
+
+/// The largest volume size. The shaders index the voxels with 32 bit integers,
+/// which would overflow from about 1290 voxels along each side.
+pub const MAX_SIZE: u32 = 1024;
+
+/// The parts of the volume simulation that are fixed once it has been created.
+#[derive(Clone, Copy, Debug)]
+pub struct VolumeConfig {
+    /// Width, height and depth of the volume, in voxels. At most `MAX_SIZE`.
+    pub size: u32,
+    pub agent_amount: u32,
+    /// Seed for the initial placement of the agents.
+    pub seed: u64,
+    /// Size of the rendered view, in pixels.
+    pub view_width: u32,
+    pub view_height: u32,
+}
+
+impl Default for VolumeConfig {
+    /// Uses a random seed.
+    fn default() -> Self {
+        VolumeConfig {
+            size: 128,
+            agent_amount: 200_000,
+            seed: rand::random(),
+            view_width: 1000,
+            view_height: 1000,
+        }
+    }
+}
+
+/// How the volume is turned into an image.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Projection {
+    /// Shows the brightest voxel along every ray.
+    MaximumIntensity,
+    /// Accumulates the trails front to back as a glowing fog, so nearer trails hide farther ones.
+    Raymarch,
+}
+
+/// Everything that can be changed while the volume runs: the same parameters as in 2D,
+/// and the camera the volume is rendered from.
+#[derive(Clone, Copy)]
+pub struct VolumeParameters {
+    pub sim: AgentParameters,
+    pub fade: FadeParameters,
+    pub camera: OrbitCamera,
+}
+
+/// A camera that circles around the centre of the volume.
+#[derive(Clone, Copy, Debug)]
+pub struct OrbitCamera {
+    /// In radians, around the z axis.
+    pub yaw: f32,
+    /// In radians, above the xy plane.
+    pub pitch: f32,
+    /// From the centre, in volume sizes.
+    pub distance: f32,
+    /// Vertical field of view, in radians.
+    pub fov: f32,
+    pub projection: Projection,
+    /// How quickly the raymarched trails become opaque.
+    pub density: f32,
+}
+
+impl Default for OrbitCamera {
+    fn default() -> Self {
+        OrbitCamera {
+            yaw: 0.6,
+            pitch: 0.4,
+            distance: 1.8,
+            fov: 0.8,
+            projection: Projection::MaximumIntensity,
+            density: 0.2,
+        }
+    }
+}
+
+impl OrbitCamera {
+    /// Rotates the camera around the centre, for example while dragging the mouse.
+    pub fn orbit(&mut self, yaw: f32, pitch: f32) {
+        self.yaw += yaw;
+        // Stop short of the poles, where the camera's up direction flips.
+        self.pitch = (self.pitch + pitch).max(-1.5).min(1.5);
+    }
+
+    /// Moves the camera closer for positive amounts, and away for negative ones.
+    pub fn zoom(&mut self, amount: f32) {
+        self.distance = (self.distance * 0.9f32.powf(amount)).max(0.2).min(10.0);
+    }
+
+    /// Position, forward, right and up, in the space where the volume spans [0 - 1].
+    fn basis(&self) -> [[f32; 4]; 4] {
+        let centre = [0.5, 0.5, 0.5];
+        let offset = direction(self.yaw, self.pitch);
+        let eye = [
+            centre[0] + offset[0] * self.distance,
+            centre[1] + offset[1] * self.distance,
+            centre[2] + offset[2] * self.distance,
+        ];
+        let forward = [-offset[0], -offset[1], -offset[2]];
+        let right = normalize(cross(forward, [0.0, 0.0, 1.0]));
+        let up = cross(right, forward);
+
+        [
+            [eye[0], eye[1], eye[2], 1.0],
+            [forward[0], forward[1], forward[2], 0.0],
+            [right[0], right[1], right[2], 0.0],
+            [up[0], up[1], up[2], 0.0],
+        ]
+    }
+}
+
+fn direction(yaw: f32, pitch: f32) -> [f32; 3] {
+    [
+        pitch.cos() * yaw.cos(),
+        pitch.cos() * yaw.sin(),
+        pitch.sin(),
+    ]
+}
+
+fn cross(a: [f32; 3], b: [f32; 3]) -> [f32; 3] {
+    [
+        a[1] * b[2] - a[2] * b[1],
+        a[2] * b[0] - a[0] * b[2],
+        a[0] * b[1] - a[1] * b[0],
+    ]
+}
+
+fn normalize(v: [f32; 3]) -> [f32; 3] {
+    let length = (v[0] * v[0] + v[1] * v[1] + v[2] * v[2]).sqrt().max(1e-6);
+    [v[0] / length, v[1] / length, v[2] / length]
+}
+
+/// Agents moving through a 3D volume. Works like `Simulation`, and is driven by the same
+/// `AgentParameters` and `FadeParameters`, with distances in voxels instead of pixels.
+pub struct VolumeSimulation {
+    pub config: VolumeConfig,
+    pub device: Arc<Device>,
+    pub queue: Arc<Queue>,
+    /// The queue the view is shared with, kept to recreate it when resizing.
+    display_queue: Arc<Queue>,
+    pub agents_buffer: Arc<CpuAccessibleBuffer<[volume_agent_shader::ty::Agent]>>,
+    /// The two volumes take turns, like the trail images of `Simulation`.
+    volumes: [Arc<StorageImage<Format>>; 2],
+    /// Index of the volume holding the latest trails.
+    current: usize,
+    /// The last rendered view of the volume.
+    view_image: Arc<StorageImage<Format>>,
+    sim_parameters_buffer: Arc<DeviceLocalBuffer<AgentParameters>>,
+    fade_parameters_buffer: Arc<DeviceLocalBuffer<FadeParameters>>,
+    /// Clears the deposits, moves the agents and diffuses the volume with the same index
+    /// into the other one.
+    step_commands: [Arc<AutoCommandBuffer>; 2],
+    render_pipeline: Arc<ComputePipeline<PipelineLayout<volume_render_shader::Layout>>>,
+    /// Renders the volume with the same index.
+    render_sets: [Arc<dyn DescriptorSet + Send + Sync>; 2],
+}
+
+impl VolumeSimulation {
+    pub fn init(
+        device: Arc<Device>,
+        queue: Arc<Queue>,
+        config: &VolumeConfig,
+    ) -> Result<VolumeSimulation, SimError> {
+        VolumeSimulation::with_display_queue(device, queue.clone(), &queue, config)
+    }
+
+    /// Runs the simulation on `queue`, while the `result_image` is displayed from
+    /// `display_queue`, like `Simulation::with_display_queue`.
+    pub fn with_display_queue(
+        device: Arc<Device>,
+        queue: Arc<Queue>,
+        display_queue: &Arc<Queue>,
+        config: &VolumeConfig,
+    ) -> Result<VolumeSimulation, SimError> {
+        let features = VOLUME_FORMAT
+            .properties(device.physical_device())
+            .optimal_tiling_features;
+        if !(features.storage_image && features.transfer_dst) {
+            return Err(SimError::UnsupportedFormat(VOLUME_FORMAT));
+        }
+
+        let size = config.size;
+        let volume_size = Dimensions::Dim3d {
+            width: size,
+            height: size,
+            depth: size,
+        };
+        let volumes = [
+            StorageImage::new(
+                device.clone(),
+                volume_size,
+                VOLUME_FORMAT,
+                Some(queue.family()),
+            )?,
+            StorageImage::new(
+                device.clone(),
+                volume_size,
+                VOLUME_FORMAT,
+                Some(queue.family()),
+            )?,
+        ];
+
+        let mut view_families = vec![queue.family()];
+        if display_queue.family().id() != queue.family().id() {
+            view_families.push(display_queue.family());
+        }
+        let view_image = StorageImage::new(
+            device.clone(),
+            Dimensions::Dim2d {
+                width: config.view_width,
+                height: config.view_height,
+            },
+            device::DISPLAY_FORMAT,
+            view_families,
+        )?;
+
+        // One fixed point intensity per voxel.
+        let deposit_buffer = DeviceLocalBuffer::array(
+            device.clone(),
+            (size as usize).pow(3),
+            BufferUsage {
+                storage_buffer: true,
+                transfer_destination: true,
+                ..BufferUsage::none()
+            },
+            Some(queue.family()),
+        )?;
+
+        let mut rng = StdRng::seed_from_u64(config.seed);
+        let agent_iter = (0..config.agent_amount).map(|_| random_agent(&mut rng, size));
+        let agents_buffer =
+            CpuAccessibleBuffer::from_iter(device.clone(), BufferUsage::all(), false, agent_iter)?;
+
+        let uniform_usage = BufferUsage {
+            uniform_buffer: true,
+            transfer_destination: true,
+            ..BufferUsage::none()
+        };
+        let sim_parameters_buffer =
+            DeviceLocalBuffer::new(device.clone(), uniform_usage, Some(queue.family()))?;
+        let fade_parameters_buffer =
+            DeviceLocalBuffer::new(device.clone(), uniform_usage, Some(queue.family()))?;
+
+        let agent_shader = volume_agent_shader::Shader::load(device.clone())?;
+        let agent_pipeline = Arc::new(ComputePipeline::new(
+            device.clone(),
+            &agent_shader.main_entry_point(),
+            &(),
+            None,
+        )?);
+
+        let diffuse_shader = volume_diffuse_shader::Shader::load(device.clone())?;
+        let diffuse_pipeline = Arc::new(ComputePipeline::new(
+            device.clone(),
+            &diffuse_shader.main_entry_point(),
+            &(),
+            None,
+        )?);
+
+        let render_shader = volume_render_shader::Shader::load(device.clone())?;
+        let render_pipeline = Arc::new(ComputePipeline::new(
+            device.clone(),
+            &render_shader.main_entry_point(),
+            &(),
+            None,
+        )?);
+
+        let agent_groups = [(config.agent_amount + 63) / 64, 1, 1];
+        let diffuse_groups = [(size + 3) / 4, (size + 3) / 4, (size + 3) / 4];
+
+        let record_step = |index: usize| -> Result<Arc<AutoCommandBuffer>, SimError> {
+            let agent_set = Arc::new(
+                PersistentDescriptorSet::start(
+                    agent_pipeline
+                        .layout()
+                        .descriptor_set_layout(0)
+                        .unwrap()
+                        .clone(),
+                )
+                .add_image(volumes[index].clone())?
+                .add_buffer(deposit_buffer.clone())?
+                .add_buffer(agents_buffer.clone())?
+                .add_buffer(sim_parameters_buffer.clone())?
+                .build()?,
+            );
+            let diffuse_set = Arc::new(
+                PersistentDescriptorSet::start(
+                    diffuse_pipeline
+                        .layout()
+                        .descriptor_set_layout(0)
+                        .unwrap()
+                        .clone(),
+                )
+                .add_image(volumes[index].clone())?
+                .add_image(volumes[1 - index].clone())?
+                .add_buffer(deposit_buffer.clone())?
+                .add_buffer(fade_parameters_buffer.clone())?
+                .build()?,
+            );
+
+            let mut builder =
+                AutoCommandBufferBuilder::primary_simultaneous_use(device.clone(), queue.family())?;
+            builder
+                .fill_buffer(deposit_buffer.clone(), 0)?
+                .dispatch(agent_groups, agent_pipeline.clone(), agent_set, ())?
+                .dispatch(diffuse_groups, diffuse_pipeline.clone(), diffuse_set, ())?;
+            Ok(Arc::new(builder.build()?))
+        };
+        let step_commands = [record_step(0)?, record_step(1)?];
+
+        let render_set = |volume: &Arc<StorageImage<Format>>| -> Result<Arc<dyn DescriptorSet + Send + Sync>, SimError> {
+            Ok(Arc::new(
+                PersistentDescriptorSet::start(
+                    render_pipeline
+                        .layout()
+                        .descriptor_set_layout(0)
+                        .unwrap()
+                        .clone(),
+                )
+                .add_image(volume.clone())?
+                .add_image(view_image.clone())?
+                .build()?,
+            ))
+        };
+        let render_sets = [render_set(&volumes[0])?, render_set(&volumes[1])?];
+
+        Ok(VolumeSimulation {
+            config: *config,
+            device,
+            queue,
+            display_queue: display_queue.clone(),
+            agents_buffer,
+            volumes,
+            current: 0,
+            view_image,
+            sim_parameters_buffer,
+            fade_parameters_buffer,
+            step_commands,
+            render_pipeline,
+            render_sets,
+        })
+    }
+
+    /// Places all agents back in the centre, as if the simulation was created with the given
+    /// seed, and clears the trails.
+    /// The gpu should not be using the agents buffer or the volumes when this is called.
+    pub fn reset(&self, seed: u64) -> Result<(), SimError> {
+        let mut rng = StdRng::seed_from_u64(seed);
+        {
+            let mut agents = self.agents_buffer.write()?;
+            for agent in agents.iter_mut() {
+                *agent = random_agent(&mut rng, self.config.size);
+            }
+        }
+
+        let mut builder = AutoCommandBufferBuilder::new(self.device.clone(), self.queue.family())?;
+        builder
+            .clear_color_image(self.volumes[0].clone(), [0.0; 4].into())?
+            .clear_color_image(self.volumes[1].clone(), [0.0; 4].into())?;
+        let command_buffer = builder.build()?;
+
+        sync::now(self.device.clone())
+            .then_execute(self.queue.clone(), command_buffer)?
+            .then_signal_fence_and_flush()?
+            .wait(None)?;
+
+        Ok(())
+    }
+
+    /// Schedules rendering the latest trails to the `result_image`, as seen from the camera.
+    pub fn render_after<F>(
+        &self,
+        future: F,
+        camera: &OrbitCamera,
+        trail_color: [f32; 4],
+    ) -> Result<Box<dyn GpuFuture>, SimError>
+    where
+        F: GpuFuture + 'static,
+    {
+        let [eye, forward, right, up] = camera.basis();
+
+        let mut builder = AutoCommandBufferBuilder::new(self.device.clone(), self.queue.family())?;
+        builder.dispatch(
+            [
+                (self.config.view_width + 7) / 8,
+                (self.config.view_height + 7) / 8,
+                1,
+            ],
+            self.render_pipeline.clone(),
+            self.render_sets[self.current].clone(),
+            volume_render_shader::ty::PushConstantData {
+                eye,
+                forward,
+                right,
+                up,
+                trail_color,
+                view_scale: (camera.fov / 2.0).tan(),
+                density: camera.density,
+                raymarch: (camera.projection == Projection::Raymarch) as i32,
+            },
+        )?;
+
+        Ok(future
+            .then_execute(self.queue.clone(), builder.build()?)?
+            .boxed())
+    }
+}
+
+impl Model for VolumeSimulation {
+    type Config = VolumeConfig;
+    type Parameters = VolumeParameters;
+
+    fn init(
+        device: Arc<Device>,
+        queue: Arc<Queue>,
+        display_queue: &Arc<Queue>,
+        config: &VolumeConfig,
+    ) -> Result<VolumeSimulation, SimError> {
+        VolumeSimulation::with_display_queue(device, queue, display_queue, config)
+    }
+
+    fn device(&self) -> &Arc<Device> {
+        &self.device
+    }
+
+    fn queue(&self) -> &Arc<Queue> {
+        &self.queue
+    }
+
+    /// The size of the rendered view.
+    fn size(&self) -> [u32; 2] {
+        [self.config.view_width, self.config.view_height]
+    }
+
+    /// Renders the view from the camera after the last step.
+    fn steps_after<F>(
+        &mut self,
+        future: F,
+        steps: u32,
+        parameters: &VolumeParameters,
+    ) -> Result<Box<dyn GpuFuture>, SimError>
+    where
+        F: GpuFuture + 'static,
+    {
+        let mut builder = AutoCommandBufferBuilder::new(self.device.clone(), self.queue.family())?;
+        builder
+            .update_buffer(self.sim_parameters_buffer.clone(), parameters.sim)?
+            .update_buffer(self.fade_parameters_buffer.clone(), parameters.fade)?;
+        let mut future = future
+            .then_execute(self.queue.clone(), builder.build()?)?
+            .boxed();
+
+        for _ in 0..steps {
+            future = future
+                .then_execute(self.queue.clone(), self.step_commands[self.current].clone())?
+                .boxed();
+            self.current = 1 - self.current;
+        }
+
+        self.render_after(future, &parameters.camera, parameters.sim.trail_color)
+    }
+
+    /// The last view rendered by `render_after`.
+    fn result_image(&self) -> &Arc<StorageImage<Format>> {
+        &self.view_image
+    }
+
+    fn reset(&mut self, seed: u64) -> Result<(), SimError> {
+        VolumeSimulation::reset(self, seed)
+    }
+
+    /// Changes the size of the view. The agents start over in the centre.
+    fn resize(&mut self, width: u32, height: u32) -> Result<(), SimError> {
+        let config = VolumeConfig {
+            view_width: width,
+            view_height: height,
+            ..self.config
+        };
+        let display_queue = self.display_queue.clone();
+        *self = VolumeSimulation::with_display_queue(
+            self.device.clone(),
+            self.queue.clone(),
+            &display_queue,
+            &config,
+        )?;
+        Ok(())
+    }
+
+    /// The same texts as a screenshot of the 2D simulation, with the view as the resolution,
+    /// so the image also works as a preset. The camera is not stored.
+    fn screenshot_texts(
+        &self,
+        parameters: &VolumeParameters,
+        step: u64,
+    ) -> Result<Vec<(&'static str, String)>, SimError> {
+        let config = SimulationConfig {
+            width: self.config.view_width,
+            height: self.config.view_height,
+            agent_amount: self.config.agent_amount,
+            seed: self.config.seed,
+            trail_format: TrailFormat::default(),
+        };
+        let texts = screenshot::info_texts(&ScreenshotInfo {
+            preset: Preset::from_parameters(&config, &parameters.sim, &parameters.fade),
+            step,
+            post: None,
+        })?;
+        Ok(texts)
+    }
+
+    /// The agents can only be restored by starting with the screenshot as preset.
+    fn restore(
+        &mut self,
+        texts: &HashMap<String, String>,
+        parameters: &mut VolumeParameters,
+    ) -> Result<String, Box<dyn Error>> {
+        let info = screenshot::info_from_texts(texts)?;
+
+        // Keep the time step of the frame rate, like the 2D simulation does.
+        let delta_time = parameters.sim.delta_time;
+        parameters.sim = info.preset.sim_parameters();
+        parameters.fade = info.preset.fade_parameters();
+        parameters.sim.delta_time = delta_time;
+        parameters.fade.delta_time = delta_time;
+
+        let config = info.preset.config();
+        self.config.seed = config.seed;
+        VolumeSimulation::reset(self, config.seed)?;
+
+        if config.agent_amount != self.config.agent_amount {
+            Ok(format!(
+                "Restored the parameters of step {}. Start with the screenshot as --preset \
+                 to also restore the agents.",
+                info.step
+            ))
+        } else {
+            Ok(format!("Restored the parameters of step {}", info.step))
+        }
+    }
+}
+
+/// Switches between the projections of the volume. Also orbits the camera while the left
+/// mouse button is dragged outside of the windows, and zooms when scrolling.
+#[cfg(feature = "ui")]
+pub fn draw_camera_window(ui: &Ui, camera: &mut OrbitCamera) {
+    Window::new(im_str!("Camera"))
+        .position([320.0, 10.0], Condition::FirstUseEver)
+        .size([300.0, 140.0], Condition::FirstUseEver)
+        .build(ui, || {
+            ui.text("Drag to orbit, scroll to zoom.");
+            ui.radio_button(
+                im_str!("Maximum intensity"),
+                &mut camera.projection,
+                Projection::MaximumIntensity,
+            );
+            ui.radio_button(
+                im_str!("Raymarch"),
+                &mut camera.projection,
+                Projection::Raymarch,
+            );
+            if camera.projection == Projection::Raymarch {
+                Slider::new(im_str!("Density"))
+                    .range(0.01..=2.0)
+                    .build(ui, &mut camera.density);
+            }
+        });
+
+    // Orbits by the same amount per physical pixel, imgui uses logical ones.
+    let io = ui.io();
+    if !io.want_capture_mouse {
+        if ui.is_mouse_down(MouseButton::Left) {
+            let scale = io.display_framebuffer_scale;
+            camera.orbit(
+                -io.mouse_delta[0] * scale[0] * 0.01,
+                io.mouse_delta[1] * scale[1] * 0.01,
+            );
+        }
+        camera.zoom(io.mouse_wheel);
+    }
+}
+
+/// An agent somewhere in a ball in the centre of the volume, heading in a random direction.
+fn random_agent<R: Rng>(rng: &mut R, size: u32) -> volume_agent_shader::ty::Agent {
+    let centre = size as f32 / 2.0;
+    let distance = rng.gen::<f32>().cbrt() * size as f32 / 4.0;
+    let [x, y, z] = direction(
+        rng.gen::<f32>() * 2.0 * PI,
+        (rng.gen::<f32>() * 2.0 - 1.0).asin(),
+    );
+
+    volume_agent_shader::ty::Agent {
+        pos: [
+            centre + x * distance,
+            centre + y * distance,
+            centre + z * distance,
+        ],
+        yaw: rng.gen::<f32>() * 2.0 * PI,
+        pitch: (rng.gen::<f32>() * 2.0 - 1.0).asin(),
+        // Padding up to the 16 byte alignment of the vec3.
+        _dummy0: [0u8; 12],
+    }
+}
+
+pub mod volume_agent_shader {
+    vulkano_shaders::shader! {
+        ty: "compute",
+        src:
+"
+#version 450
+
+const float PI = 3.1415926535897932384626433832795;
+const float HALF_PI = PI / 2.0;
+
+struct Agent {
+    vec3 pos;
+    // In radians, around the z axis.
+    float yaw;
+    // In radians, above the xy plane.
+    float pitch;
+};
+
+layout(local_size_x = 64, local_size_y = 1, local_size_z = 1) in;
+
+// Has to match DEPOSIT_SCALE in the volume_diffuse_shader.
+const float DEPOSIT_SCALE = 4096.0;
+
+layout(set = 0, binding = 0, r32f) uniform readonly image3D trail_img;
+// Fixed point intensity per voxel, added to by all agents.
+layout(set = 0, binding = 1) buffer Deposits {
+    uint data[];
+} deposits;
+layout(set = 0, binding = 2) buffer Agents {
+    Agent data[];
+} buf;
+
+// The same block as in the agent_shader, so the same parameters drive both modes.
+layout(set = 0, binding = 3) uniform Parameters {
+    // Only used when rendering.
+    vec4 trail_color;
+    // In voxels / second.
+    float agent_speed;
+    // In radians / second.
+    float agent_turn_speed;
+    int sensor_radius;
+    // In radians from straight ahead, for the sensors left, right, above and below.
+    float sensor_angle_spacing;
+    float delta_time;
+    float deposit_amount;
+    // There is no attractant in 3D.
+    float attractant_weight;
+    // There is no flocking in 3D, the agents always follow the trails.
+    float trail_weight;
+    float separation_weight;
+    float alignment_weight;
+    float cohesion_weight;
+    float flock_radius;
+} params;
+
+uint hash(uint state) {
+    state ^= 2747636419u;
+    state *= 2654435769u;
+    state ^= state >> 16;
+    state *= 2654435769u;
+    state ^= state >> 16;
+    state *= 2654435769u;
+    return state;
+}
+
+float normalize_from_hash(uint hash_val) {
+    return float(hash_val) / 4294967295.0;
+}
+
+vec3 direction(float yaw, float pitch) {
+    return vec3(cos(pitch) * cos(yaw), cos(pitch) * sin(yaw), sin(pitch));
+}
+
+float sense(Agent agent, float yaw_offset, float pitch_offset) {
+    float sensor_centre_distance = 9.0;
+    ivec3 size = imageSize(trail_img);
+
+    vec3 sensor_dir = direction(agent.yaw + yaw_offset, agent.pitch + pitch_offset);
+    ivec3 sensor_centre = ivec3(agent.pos + sensor_dir * sensor_centre_distance);
+
+    float sum = 0;
+    for (int x = -params.sensor_radius; x <= params.sensor_radius; x++) {
+        for (int y = -params.sensor_radius; y <= params.sensor_radius; y++) {
+            for (int z = -params.sensor_radius; z <= params.sensor_radius; z++) {
+                ivec3 sample_pos = sensor_centre + ivec3(x, y, z);
+                if (all(greaterThanEqual(sample_pos, ivec3(0))) && all(lessThan(sample_pos, size))) {
+                    sum += imageLoad(trail_img, sample_pos).x;
+                }
+            }
+        }
+    }
+
+    return sum;
+}
+
+// Turns towards the stronger of two opposite sensors, or randomly when both beat the one ahead.
+float steer(float forward, float positive, float negative, float strength) {
+    if (forward > positive && forward > negative) {
+        return 0.0;
+    } else if (forward < positive && forward < negative) {
+        return (strength - 0.5) * 2;
+    } else if (positive > negative) {
+        return strength;
+    } else if (positive < negative) {
+        return -strength;
+    }
+    return 0.0;
+}
+
+void main() {
+    uint id = gl_GlobalInvocationID.x;
+    if (id >= buf.data.length()) {
+        return;
+    }
+
+    ivec3 size = imageSize(trail_img);
+
+    Agent agent = buf.data[id];
+    uint random = hash(uint((agent.pos.z * size.y + agent.pos.y) * size.x + agent.pos.x + hash(id)));
+
+    // The sensor cone: straight ahead, and spaced out to the left, right, top and bottom.
+    float spacing = params.sensor_angle_spacing;
+    float sense_forward = sense(agent, 0, 0);
+    float sense_left = sense(agent, spacing, 0);
+    float sense_right = sense(agent, -spacing, 0);
+    float sense_up = sense(agent, 0, spacing);
+    float sense_down = sense(agent, 0, -spacing);
+
+    float turn = params.agent_turn_speed * params.delta_time;
+    float yaw = agent.yaw + steer(sense_forward, sense_left, sense_right, normalize_from_hash(random)) * turn;
+    float pitch = agent.pitch + steer(sense_forward, sense_up, sense_down, normalize_from_hash(hash(random))) * turn;
+    // Near the poles, turning left or right hardly changes the direction.
+    pitch = clamp(pitch, -HALF_PI * 0.9, HALF_PI * 0.9);
+
+    vec3 new_pos = agent.pos + direction(yaw, pitch) * params.agent_speed * params.delta_time;
+
+    // Bounce off the sides by mirroring the heading.
+    float edge_holdout = 0.01;
+    if (new_pos.x < 0 || new_pos.x >= size.x) {
+        yaw = PI - yaw;
+        new_pos.x = clamp(new_pos.x, edge_holdout, size.x - edge_holdout);
+    }
+    if (new_pos.y < 0 || new_pos.y >= size.y) {
+        yaw = -yaw;
+        new_pos.y = clamp(new_pos.y, edge_holdout, size.y - edge_holdout);
+    }
+    if (new_pos.z < 0 || new_pos.z >= size.z) {
+        pitch = -pitch;
+        new_pos.z = clamp(new_pos.z, edge_holdout, size.z - edge_holdout);
+    }
+
+    buf.data[id].pos = new_pos;
+    buf.data[id].yaw = yaw;
+    buf.data[id].pitch = pitch;
+
+    // Draw trail.
+    ivec3 trail_pos = ivec3(agent.pos);
+    uint index = uint((trail_pos.z * size.y + trail_pos.y) * size.x + trail_pos.x);
+    atomicAdd(deposits.data[index], uint(params.deposit_amount * DEPOSIT_SCALE + 0.5));
+}
+"
+    }
+}
+
+pub mod volume_diffuse_shader {
+    vulkano_shaders::shader! {
+        ty: "compute",
+        src:
+"
+#version 450
+
+layout(local_size_x = 4, local_size_y = 4, local_size_z = 4) in;
+
+// Has to match DEPOSIT_SCALE in the volume_agent_shader.
+const float DEPOSIT_SCALE = 4096.0;
+
+layout(set = 0, binding = 0, r32f) uniform readonly image3D in_img;
+layout(set = 0, binding = 1, r32f) uniform writeonly image3D out_img;
+layout(set = 0, binding = 2) readonly buffer Deposits {
+    uint data[];
+} deposits;
+
+// The same block as in the blur_fade_shader.
+layout(set = 0, binding = 3) uniform Parameters {
+    float delta_time;
+    // How much intensity is 'evaporated' per second.
+    float evaporate_speed;
+    // In voxels.
+    int blur_radius;
+} params;
+
+void main() {
+    ivec3 size = imageSize(in_img);
+    ivec3 pos = ivec3(gl_GlobalInvocationID);
+    if (any(greaterThanEqual(pos, size))) {
+        return;
+    }
+
+    int radius = params.blur_radius;
+
+    // ---- Diffuse ----
+    float sum = 0.0;
+    for (int x = -radius; x <= radius; x++) {
+        for (int y = -radius; y <= radius; y++) {
+            for (int z = -radius; z <= radius; z++) {
+                ivec3 sample_pos = pos + ivec3(x, y, z);
+                if (all(greaterThanEqual(sample_pos, ivec3(0))) && all(lessThan(sample_pos, size))) {
+                    uint index = uint((sample_pos.z * size.y + sample_pos.y) * size.x + sample_pos.x);
+                    sum += imageLoad(in_img, sample_pos).x + deposits.data[index] / DEPOSIT_SCALE;
+                }
+            }
+        }
+    }
+
+    int width = radius * 2 + 1;
+    float diffused = sum / (width * width * width);
+
+    // ---- Evaporate ----
+    float result = clamp(diffused - params.evaporate_speed * params.delta_time, 0.0, 1.0);
+
+    imageStore(out_img, pos, vec4(result));
+}
+"
+    }
+}
+
+pub mod volume_render_shader {
+    vulkano_shaders::shader! {
+        ty: "compute",
+        src:
+"
+#version 450
+
+layout(local_size_x = 8, local_size_y = 8, local_size_z = 1) in;
+
+layout(set = 0, binding = 0, r32f) uniform readonly image3D volume_img;
+layout(set = 0, binding = 1, rgba8) uniform writeonly image2D out_img;
+
+// The camera, in the space where the volume spans [0 - 1].
+layout(push_constant) uniform PushConstantData {
+    vec4 eye;
+    vec4 forward;
+    vec4 right;
+    vec4 up;
+    vec4 trail_color;
+    // Tangent of half the vertical field of view.
+    float view_scale;
+    // How quickly the raymarched trails become opaque.
+    float density;
+    // Raymarches when not 0, and shows the maximum intensity along the ray otherwise.
+    int raymarch;
+} pc;
+
+void main() {
+    ivec2 pos = ivec2(gl_GlobalInvocationID.xy);
+    ivec2 out_size = imageSize(out_img);
+    if (pos.x >= out_size.x || pos.y >= out_size.y) {
+        return;
+    }
+
+    vec2 uv = (vec2(pos) + 0.5) / vec2(out_size) * 2.0 - 1.0;
+    float aspect = float(out_size.x) / float(out_size.y);
+    vec3 dir = normalize(pc.forward.xyz + (uv.x * aspect * pc.right.xyz - uv.y * pc.up.xyz) * pc.view_scale);
+    vec3 origin = pc.eye.xyz;
+
+    // Where the ray enters and leaves the volume.
+    vec3 t0 = (vec3(0.0) - origin) / dir;
+    vec3 t1 = (vec3(1.0) - origin) / dir;
+    vec3 t_min = min(t0, t1);
+    vec3 t_max = max(t0, t1);
+    float near = max(max(t_min.x, t_min.y), max(t_min.z, 0.0));
+    float far = min(min(t_max.x, t_max.y), t_max.z);
+
+    vec3 color = vec3(0.0);
+    if (near < far) {
+        ivec3 size = imageSize(volume_img);
+        // About one sample per voxel.
+        float step_length = 1.0 / float(max(size.x, max(size.y, size.z)));
+        int steps = int((far - near) / step_length) + 1;
+
+        float maximum = 0.0;
+        float alpha = 0.0;
+        for (int i = 0; i < steps; i++) {
+            vec3 p = origin + dir * (near + (float(i) + 0.5) * step_length);
+            ivec3 voxel = clamp(ivec3(p * vec3(size)), ivec3(0), size - 1);
+            float value = imageLoad(volume_img, voxel).x;
+
+            if (pc.raymarch != 0) {
+                float sample_alpha = 1.0 - exp(-value * pc.density);
+                color += (1.0 - alpha) * sample_alpha * pc.trail_color.rgb;
+                alpha += (1.0 - alpha) * sample_alpha;
+                if (alpha > 0.99) {
+                    break;
+                }
+            } else {
+                maximum = max(maximum, value);
+            }
+        }
+
+        if (pc.raymarch == 0) {
+            color = pc.trail_color.rgb * maximum;
+        }
+    }
+
+    imageStore(out_img, pos, vec4(color, 1.0));
+}
+"
+    }
+}